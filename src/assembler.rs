@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use crate::hack::{self, Instruction};
+
+#[derive(Debug)]
+pub struct Program {
+  pub instructions: Vec<u16>,
+  pub labels: BTreeMap<String, u16>,
+  pub variables: BTreeMap<String, u16>,
+}
+
+enum Line<'a> {
+  Label(&'a str),
+  Address(&'a str),
+  Compute(&'a str),
+}
+
+fn parse_line(line: &str) -> Option<Line<'_>> {
+  let line = line.split("//").next().unwrap().trim();
+  if line.is_empty() {
+    None
+  } else if line.starts_with('(') && line.ends_with(')') {
+    Some(Line::Label(&line[1..line.len() - 1]))
+  } else if let Some(symbol) = line.strip_prefix('@') {
+    Some(Line::Address(symbol))
+  } else {
+    Some(Line::Compute(line))
+  }
+}
+
+pub fn is_instruction(line: &str) -> bool {
+  matches!(
+    parse_line(line),
+    Some(Line::Address(_)) | Some(Line::Compute(_))
+  )
+}
+
+pub fn is_label(line: &str) -> bool {
+  matches!(parse_line(line), Some(Line::Label(_)))
+}
+
+pub fn instruction_count(asm: &str) -> usize {
+  asm.lines().filter(|line| is_instruction(line)).count()
+}
+
+pub fn assemble(asm: &str) -> Result<Program, String> {
+  let mut labels = BTreeMap::new();
+  let mut address = 0;
+  for (i, line) in asm.lines().enumerate() {
+    match parse_line(line) {
+      Some(Line::Label(label)) if labels.insert(label.to_string(), address).is_some() => {
+        return Err(format!("line {}: duplicate label {}", i + 1, label));
+      }
+      Some(Line::Label(_)) => (),
+      Some(_) => address += 1,
+      None => (),
+    }
+  }
+  if address as usize > hack::ROM_SIZE {
+    return Err(format!(
+      "program has {} instructions, Hack ROM holds {}",
+      address,
+      hack::ROM_SIZE
+    ));
+  }
+
+  let mut variables = BTreeMap::new();
+  let mut instructions = Vec::new();
+  for (i, line) in asm.lines().enumerate() {
+    let instruction = match parse_line(line) {
+      Some(Line::Address(symbol)) => {
+        let value = if let Ok(value) = symbol.parse::<u16>() {
+          if value > 0x7fff {
+            return Err(format!("line {}: constant {} out of range", i + 1, value));
+          }
+          value
+        } else if let Some(value) = hack::predefined_symbol(symbol) {
+          value
+        } else if let Some(value) = labels.get(symbol) {
+          *value
+        } else {
+          let next = 16 + variables.len() as u16;
+          *variables.entry(symbol.to_string()).or_insert(next)
+        };
+        Instruction::A(value)
+      }
+      Some(Line::Compute(command)) => {
+        Instruction::parse_c(command).map_err(|err| format!("line {}: {}", i + 1, err))?
+      }
+      _ => continue,
+    };
+    instructions.push(instruction.encode());
+  }
+
+  Ok(Program {
+    instructions,
+    labels,
+    variables,
+  })
+}
+
+impl Program {
+  // Symbol map read back by the disassembler, one `kind address name` per line.
+  pub fn symbol_map(&self) -> String {
+    let mut symbols: Vec<(&str, u16, &String)> = self
+      .labels
+      .iter()
+      .map(|(name, address)| ("label", *address, name))
+      .chain(
+        self
+          .variables
+          .iter()
+          .map(|(name, address)| ("var", *address, name)),
+      )
+      .collect();
+    symbols.sort_by_key(|(kind, address, _)| (*kind, *address));
+    symbols
+      .iter()
+      .map(|(kind, address, name)| format!("{} {} {}\n", kind, address, name))
+      .collect()
+  }
+}
@@ -0,0 +1,248 @@
+use std::fmt::Write;
+
+use crate::emulator::{Emulator, KBD};
+use crate::function::Function;
+use crate::memory_access::{AccessCommand, MemoryAccess, Segment};
+use crate::Command;
+
+// Error codes a failed check leaves in R15, with the ROM address of the check
+// in R14.
+pub const STACK_OVERFLOW: u16 = 1;
+pub const STACK_UNDERFLOW: u16 = 2;
+pub const SEGMENT_BOUNDS: u16 = 3;
+pub const BAD_FRAME: u16 = 4;
+pub const CODE: usize = 15;
+pub const ADDRESS: usize = 14;
+
+pub const STACK: u16 = 256;
+pub const HEAP: u16 = 2048;
+
+pub fn describe(code: u16) -> &'static str {
+  match code {
+    STACK_OVERFLOW => "stack overflow",
+    STACK_UNDERFLOW => "stack underflow",
+    SEGMENT_BOUNDS => "this/that access outside the heap and screen",
+    BAD_FRAME => "return with an invalid frame",
+    _ => "unknown trap",
+  }
+}
+
+// Generates the runtime checks of `--checked` builds: assembly run before a
+// command that jumps to a trap routine when the command would corrupt memory.
+// The failure paths and trap routines go after the program, so a passing check
+// costs a handful of instructions.
+#[derive(Debug, Default)]
+pub struct Checks {
+  sites: usize,
+  failures: String,
+}
+
+impl Checks {
+  // The checks to run before `command`.
+  pub fn guard(&mut self, command: &Command) -> String {
+    match command {
+      Command::MemoryAccess(MemoryAccess {
+        command,
+        segment,
+        index,
+        ..
+      }) => {
+        let mut asm = String::new();
+        if let Segment::This | Segment::That = segment {
+          asm += &self.segment_bounds(*segment, *index);
+        }
+        asm += &match command {
+          AccessCommand::Push => self.stack_space(1),
+          AccessCommand::Pop => self.stack_values(),
+        };
+        asm
+      }
+      Command::Function(Function::Call { .. }) => self.stack_space(5),
+      Command::Function(Function::Decl { nlocals, .. }) if *nlocals > 0 => {
+        self.stack_space(*nlocals)
+      }
+      Command::Function(Function::Return) => self.frame(),
+      _ => String::new(),
+    }
+  }
+
+  // A new check site: its label, and the label its failure path starts at.
+  fn site(&mut self, code: u16) -> (String, String) {
+    let label = format!("VM.check.{}", self.sites);
+    let fail = format!("{}.fail", label);
+    self.sites += 1;
+    write!(
+      self.failures,
+      "({fail})\n\
+       @{label} // D = address of the failed check\n\
+       D=A\n\
+       @VM.trap.{code}\n\
+       0;JMP\n",
+      fail = fail,
+      label = label,
+      code = code
+    )
+    .unwrap();
+    (label, fail)
+  }
+
+  // Room on the stack for `count` more values.
+  fn stack_space(&mut self, count: usize) -> String {
+    let (label, fail) = self.site(STACK_OVERFLOW);
+    format!(
+      "({label})\n\
+       @SP // check SP + {count} <= {heap}\n\
+       D=M\n\
+       @{limit}\n\
+       D=D-A\n\
+       @{fail}\n\
+       D;JGT\n",
+      label = label,
+      count = count,
+      heap = HEAP,
+      limit = (HEAP as usize).saturating_sub(count),
+      fail = fail
+    )
+  }
+
+  // A value on the stack to pop.
+  fn stack_values(&mut self) -> String {
+    let (label, fail) = self.site(STACK_UNDERFLOW);
+    format!(
+      "({label})\n\
+       @SP // check SP > {stack}\n\
+       D=M\n\
+       @{stack}\n\
+       D=D-A\n\
+       @{fail}\n\
+       D;JLE\n",
+      label = label,
+      stack = STACK,
+      fail = fail
+    )
+  }
+
+  // The address `segment index` is in the heap or screen.
+  fn segment_bounds(&mut self, segment: Segment, index: i32) -> String {
+    let (label, fail) = self.site(SEGMENT_BOUNDS);
+    let pointer = if segment == Segment::This {
+      "THIS"
+    } else {
+      "THAT"
+    };
+    format!(
+      "({label})\n\
+       @{pointer} // check {heap} <= {pointer} + {index} < {kbd}\n\
+       D=M\n\
+       @{index}\n\
+       D=D+A\n\
+       @{heap}\n\
+       D=D-A\n\
+       @{fail}\n\
+       D;JLT\n\
+       @{size}\n\
+       D=D-A\n\
+       @{fail}\n\
+       D;JGE\n",
+      label = label,
+      pointer = pointer,
+      index = index,
+      heap = HEAP,
+      kbd = KBD,
+      size = KBD - HEAP as usize,
+      fail = fail
+    )
+  }
+
+  // The frame `return` unwinds: LCL is above the saved frame, the return
+  // value is on the stack, and the arguments are below the saved frame.
+  fn frame(&mut self) -> String {
+    let (label, fail) = self.site(BAD_FRAME);
+    format!(
+      "({label})\n\
+       @LCL // check LCL >= {frame_start}\n\
+       D=M\n\
+       @{frame_start}\n\
+       D=D-A\n\
+       @{fail}\n\
+       D;JLT\n\
+       @SP // check SP > LCL\n\
+       D=M\n\
+       @LCL\n\
+       D=D-M\n\
+       @{fail}\n\
+       D;JLE\n\
+       @ARG // check ARG >= {stack}\n\
+       D=M\n\
+       @{stack}\n\
+       D=D-A\n\
+       @{fail}\n\
+       D;JLT\n\
+       @LCL // check LCL - ARG >= 5\n\
+       D=M\n\
+       @ARG\n\
+       D=D-M\n\
+       @5\n\
+       D=D-A\n\
+       @{fail}\n\
+       D;JLT\n",
+      label = label,
+      frame_start = STACK + 5,
+      stack = STACK,
+      fail = fail
+    )
+  }
+
+  // A halt loop for programs that run off their last command, the failure
+  // paths, then one routine per error code that records it and halts.
+  pub fn finish(self) -> String {
+    let mut asm = format!(
+      "// runtime checks\n\
+       (VM.checks.end)\n\
+       @VM.checks.end\n\
+       0;JMP\n\
+       {}",
+      self.failures
+    );
+    for code in STACK_OVERFLOW..=BAD_FRAME {
+      write!(
+        asm,
+        "(VM.trap.{code}) // {description}\n\
+         @R{address}\n\
+         M=D\n\
+         @{code}\n\
+         D=A\n\
+         @R{register}\n\
+         M=D\n\
+         (VM.trap.{code}.halt)\n\
+         @VM.trap.{code}.halt\n\
+         0;JMP\n",
+        code = code,
+        description = describe(code),
+        address = ADDRESS,
+        register = CODE
+      )
+      .unwrap();
+    }
+    asm
+  }
+}
+
+// The error code and check address when the emulator halted in a trap
+// routine. Trap routines end with `@code D=A @R15 M=D` right before their
+// halt loop, which tells them apart from programs that halt with a value in
+// R15.
+pub fn trap(emulator: &Emulator) -> Option<(u16, u16)> {
+  let pc = emulator.pc as usize;
+  let code = emulator.ram[CODE];
+  let halted = emulator.is_halted() && pc >= 4;
+  if halted
+    && (STACK_OVERFLOW..=BAD_FRAME).contains(&code)
+    && emulator.rom[pc - 4] == code
+    && emulator.rom[pc - 2] == CODE as u16
+  {
+    Some((code, emulator.ram[ADDRESS]))
+  } else {
+    None
+  }
+}
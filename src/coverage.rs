@@ -0,0 +1,168 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::source_map::{Mapping, SourceMap};
+use crate::{Build, VmParser};
+
+#[derive(Debug)]
+pub struct Coverage {
+  pub cycles: u64,
+  pub halted: bool,
+  pub source_map: SourceMap,
+  // Times each ROM address executed.
+  pub hits: Vec<u64>,
+  // Name and path of each VM file in translation order.
+  files: Vec<(String, String)>,
+}
+
+// Runs the translated program in the emulator, counting how often each ROM
+// address executes. A VM command counts as executed when its first
+// instruction did; commands that generate no code, like labels, aren't counted.
+pub fn coverage(parsers: Vec<VmParser>, max_cycles: u64) -> Result<Coverage, String> {
+  let files = parsers
+    .iter()
+    .map(|parser| {
+      let name = Path::new(&parser.filename).file_name().unwrap();
+      (name.to_string_lossy().into_owned(), parser.filename.clone())
+    })
+    .collect();
+  let Build {
+    source_map,
+    mut emulator,
+    ..
+  } = crate::build(parsers, false)?;
+  let mut hits = vec![0; source_map.instruction_count()];
+  let mut halted = false;
+  while emulator.cycles < max_cycles {
+    // The command a program halts in was reached, even if never left.
+    if let Some(hit) = hits.get_mut(emulator.pc as usize) {
+      *hit += 1;
+    }
+    if emulator.pc as usize >= hits.len() || emulator.is_halted() {
+      halted = true;
+      break;
+    }
+    emulator.step()?;
+  }
+  Ok(Coverage {
+    cycles: emulator.cycles,
+    halted,
+    source_map,
+    hits,
+    files,
+  })
+}
+
+fn percent(hit: usize, total: usize) -> f64 {
+  if total == 0 {
+    100.0
+  } else {
+    hit as f64 * 100.0 / total as f64
+  }
+}
+
+impl Coverage {
+  // The commands of `file` that generated code.
+  fn commands<'a>(&'a self, file: &'a str) -> impl Iterator<Item = &'a Mapping> {
+    self
+      .source_map
+      .mappings
+      .iter()
+      .filter(move |mapping| mapping.file.as_deref() == Some(file) && mapping.count > 0)
+  }
+
+  fn command_hits(&self, mapping: &Mapping) -> u64 {
+    self.hits[mapping.address]
+  }
+
+  // Line and function coverage per file, followed by the commands that never
+  // ran.
+  pub fn summary(&self) -> String {
+    let mut report = format!(
+      "{:<40}{:>14}{:>8}{:>14}{:>8}\n",
+      "File", "lines", "%", "functions", "%"
+    );
+    let mut totals = (0, 0, 0, 0);
+    let mut missed = Vec::new();
+    for (file, _) in &self.files {
+      let (mut lines, mut lines_hit, mut functions, mut functions_hit) = (0, 0, 0, 0);
+      for mapping in self.commands(file) {
+        let hit = self.command_hits(mapping) > 0;
+        lines += 1;
+        lines_hit += hit as usize;
+        if mapping.kind == "function" {
+          functions += 1;
+          functions_hit += hit as usize;
+        }
+        if !hit {
+          missed.push(format!("{}:{}: {}", file, mapping.line, mapping.command));
+        }
+      }
+      writeln!(
+        report,
+        "{:<40}{:>14}{:>7.1}%{:>14}{:>7.1}%",
+        file,
+        format!("{}/{}", lines_hit, lines),
+        percent(lines_hit, lines),
+        format!("{}/{}", functions_hit, functions),
+        percent(functions_hit, functions)
+      )
+      .unwrap();
+      totals.0 += lines_hit;
+      totals.1 += lines;
+      totals.2 += functions_hit;
+      totals.3 += functions;
+    }
+    writeln!(
+      report,
+      "{:<40}{:>14}{:>7.1}%{:>14}{:>7.1}%",
+      "Total",
+      format!("{}/{}", totals.0, totals.1),
+      percent(totals.0, totals.1),
+      format!("{}/{}", totals.2, totals.3),
+      percent(totals.2, totals.3)
+    )
+    .unwrap();
+    if !missed.is_empty() {
+      writeln!(report, "\nNot executed:").unwrap();
+      for line in missed {
+        writeln!(report, "  {}", line).unwrap();
+      }
+    }
+    report
+  }
+
+  // The coverage in lcov's tracefile format, one record per VM file.
+  pub fn lcov(&self) -> String {
+    let mut lcov = String::new();
+    for (file, path) in &self.files {
+      writeln!(lcov, "TN:\nSF:{}", path).unwrap();
+      let functions: Vec<&Mapping> = self
+        .commands(file)
+        .filter(|mapping| mapping.kind == "function")
+        .collect();
+      for mapping in &functions {
+        let name = mapping.function.as_deref().unwrap_or_default();
+        writeln!(lcov, "FN:{},{}", mapping.line, name).unwrap();
+      }
+      for mapping in &functions {
+        let name = mapping.function.as_deref().unwrap_or_default();
+        writeln!(lcov, "FNDA:{},{}", self.command_hits(mapping), name).unwrap();
+      }
+      let functions_hit = functions
+        .iter()
+        .filter(|mapping| self.command_hits(mapping) > 0)
+        .count();
+      writeln!(lcov, "FNF:{}\nFNH:{}", functions.len(), functions_hit).unwrap();
+      let (mut lines, mut lines_hit) = (0, 0);
+      for mapping in self.commands(file) {
+        let hits = self.command_hits(mapping);
+        writeln!(lcov, "DA:{},{}", mapping.line, hits).unwrap();
+        lines += 1;
+        lines_hit += (hits > 0) as usize;
+      }
+      writeln!(lcov, "LF:{}\nLH:{}\nend_of_record", lines, lines_hit).unwrap();
+    }
+    lcov
+  }
+}
@@ -0,0 +1,553 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::debugger::{Breakpoint, Debugger, Stop};
+use crate::history;
+use crate::json::Json;
+use crate::VmParser;
+
+const THREAD: i64 = 1;
+// Cycles to run between checks for a pause request while continuing.
+const CONTINUE_CHUNK: u64 = 100_000;
+// Longest message body accepted; larger ones are skipped and answered with
+// an error.
+const MAX_MESSAGE: usize = 1 << 20;
+// Longest header line read at once.
+const MAX_HEADER: u64 = 1024;
+const SEGMENTS: [&str; 7] = [
+  "local", "argument", "this", "that", "static", "pointer", "temp",
+];
+
+// A Debug Adapter Protocol server driving the VM debugger over a pair of
+// streams, normally stdin and stdout. Scopes are the VM segments of a frame,
+// with variable references numbered frame * SEGMENTS.len() + segment + 1.
+// Requests are read on their own thread so that a running program can be
+// paused. Messages that can't be read are answered with an error response.
+pub struct DapServer<W> {
+  messages: Receiver<Result<Json, String>>,
+  // Requests that came in while the program was running.
+  pending: VecDeque<Result<Json, String>>,
+  output: W,
+  seq: i64,
+  debugger: Option<Debugger>,
+  // Full path of each VM file by file name, as stack frames need it.
+  paths: HashMap<String, String>,
+  stop_on_entry: bool,
+}
+
+impl<W: Write> DapServer<W> {
+  pub fn new<R: BufRead + Send + 'static>(mut input: R, output: W) -> Self {
+    let (sender, messages) = mpsc::channel();
+    // The channel closes at the end of the input.
+    thread::spawn(move || loop {
+      match receive(&mut input) {
+        Ok(Some(message)) => {
+          if sender.send(message).is_err() {
+            break;
+          }
+        }
+        Ok(None) => break,
+        Err(err) => {
+          let _ = sender.send(Err(err.to_string()));
+          break;
+        }
+      }
+    });
+    DapServer {
+      messages,
+      pending: VecDeque::new(),
+      output,
+      seq: 0,
+      debugger: None,
+      paths: HashMap::new(),
+      stop_on_entry: false,
+    }
+  }
+
+  // Serves requests until the client disconnects or closes the stream.
+  pub fn run(&mut self) -> Result<(), String> {
+    while let Some(message) = self.next_message() {
+      let request = match message {
+        Ok(request) => request,
+        Err(err) => {
+          self.respond(0, "", &Err(err))?;
+          continue;
+        }
+      };
+      let command = request
+        .get("command")
+        .and_then(Json::as_str)
+        .unwrap_or_default()
+        .to_string();
+      let arguments = request.get("arguments").cloned().unwrap_or(Json::Null);
+      let request_seq = request.get("seq").and_then(Json::as_i64).unwrap_or(0);
+      let result = self.handle(&command, &arguments);
+      self.respond(request_seq, &command, &result)?;
+      if result.is_ok() {
+        if let Err(err) = self.after(&command) {
+          self.event(
+            "output",
+            Json::object(vec![
+              ("category", "stderr".into()),
+              ("output", format!("{}\n", err).into()),
+            ]),
+          )?;
+          self.stopped("exception")?;
+        }
+      }
+      if command == "disconnect" {
+        break;
+      }
+    }
+    Ok(())
+  }
+
+  fn respond(
+    &mut self,
+    request_seq: i64,
+    command: &str,
+    result: &Result<Json, String>,
+  ) -> Result<(), String> {
+    let mut response = vec![
+      ("type", "response".into()),
+      ("request_seq", request_seq.into()),
+      ("success", result.is_ok().into()),
+      ("command", command.into()),
+    ];
+    match result {
+      Ok(Json::Null) => (),
+      Ok(body) => response.push(("body", body.clone())),
+      Err(err) => response.push(("message", err.as_str().into())),
+    }
+    self.send(Json::object(response))
+  }
+
+  fn handle(&mut self, command: &str, arguments: &Json) -> Result<Json, String> {
+    match command {
+      "initialize" => Ok(Json::object(vec![
+        ("supportsConfigurationDoneRequest", true.into()),
+        ("supportsFunctionBreakpoints", true.into()),
+        ("supportsConditionalBreakpoints", true.into()),
+        ("supportsStepBack", true.into()),
+        ("supportsEvaluateForHovers", false.into()),
+      ])),
+      "launch" => {
+        let program = arguments
+          .get("program")
+          .and_then(Json::as_str)
+          .ok_or("launch needs a program")?;
+        let parsers = VmParser::open(Path::new(program))
+          .map_err(|err| format!("Cannot open {}: {}", program, err))?;
+        for parser in &parsers {
+          let path = Path::new(&parser.filename);
+          let name = path.file_name().unwrap().to_string_lossy().into_owned();
+          self.paths.insert(name, parser.filename.clone());
+        }
+        self.stop_on_entry = arguments
+          .get("stopOnEntry")
+          .and_then(Json::as_bool)
+          .unwrap_or(false);
+        self.debugger = Some(Debugger::new(parsers, history::DEFAULT_DELTAS)?);
+        Ok(Json::Null)
+      }
+      "setBreakpoints" => self.set_breakpoints(arguments),
+      "setFunctionBreakpoints" => self.set_function_breakpoints(arguments),
+      "setExceptionBreakpoints" | "configurationDone" | "pause" | "disconnect" => Ok(Json::Null),
+      "threads" => Ok(Json::object(vec![(
+        "threads",
+        Json::Array(vec![Json::object(vec![
+          ("id", THREAD.into()),
+          ("name", "main".into()),
+        ])]),
+      )])),
+      "stackTrace" => self.stack_trace(),
+      "scopes" => {
+        let frame = arguments.get("frameId").and_then(Json::as_i64).unwrap_or(0);
+        Ok(Json::object(vec![(
+          "scopes",
+          Json::Array(
+            SEGMENTS
+              .iter()
+              .enumerate()
+              .map(|(i, segment)| {
+                Json::object(vec![
+                  ("name", (*segment).into()),
+                  (
+                    "variablesReference",
+                    (frame * SEGMENTS.len() as i64 + i as i64 + 1).into(),
+                  ),
+                  ("expensive", false.into()),
+                ])
+              })
+              .collect(),
+          ),
+        )]))
+      }
+      "variables" => {
+        let reference = arguments
+          .get("variablesReference")
+          .and_then(Json::as_i64)
+          .ok_or("variables needs a variablesReference")?;
+        let debugger = self.debugger()?;
+        // Frame 0 has scopes even in the bootstrap, outside any function.
+        let scopes = debugger.backtrace().len().max(1) * SEGMENTS.len();
+        if reference < 1 || reference as usize > scopes {
+          return Err(format!("invalid variablesReference {}", reference));
+        }
+        let reference = reference as usize - 1;
+        let frame = reference / SEGMENTS.len();
+        let segment = SEGMENTS[reference % SEGMENTS.len()];
+        let values = debugger.segment(frame, segment, None).unwrap_or_default();
+        Ok(Json::object(vec![(
+          "variables",
+          Json::Array(
+            values
+              .into_iter()
+              .map(|(name, value)| {
+                Json::object(vec![
+                  ("name", name.into()),
+                  ("value", value.to_string().into()),
+                  ("variablesReference", Json::Number(0)),
+                ])
+              })
+              .collect(),
+          ),
+        )]))
+      }
+      "evaluate" => {
+        let expression = arguments
+          .get("expression")
+          .and_then(Json::as_str)
+          .unwrap_or_default();
+        let frame = arguments.get("frameId").and_then(Json::as_i64).unwrap_or(0) as usize;
+        let value = self.evaluate(frame, expression)?;
+        Ok(Json::object(vec![
+          ("result", value.to_string().into()),
+          ("variablesReference", Json::Number(0)),
+        ]))
+      }
+      "continue" => Ok(Json::object(vec![("allThreadsContinued", true.into())])),
+      "next" | "stepIn" | "stepOut" | "stepBack" | "reverseContinue" => Ok(Json::Null),
+      _ => Err(format!("unsupported request {}", command)),
+    }
+  }
+
+  // Runs the program for requests that resume it, once they've been answered.
+  fn after(&mut self, command: &str) -> Result<(), String> {
+    let debugger = match self.debugger.as_mut() {
+      Some(debugger) => debugger,
+      None => return Ok(()),
+    };
+    let stop = match command {
+      "launch" => {
+        return self.event("initialized", Json::Null);
+      }
+      "configurationDone" if self.stop_on_entry => {
+        return self.stopped("entry");
+      }
+      "configurationDone" | "continue" => match self.cont()? {
+        Some(stop) => stop,
+        None => return Ok(()),
+      },
+      "next" => debugger.step_over()?,
+      "stepIn" => debugger.step()?,
+      "stepOut" => debugger.finish()?,
+      "stepBack" => debugger.reverse_step()?,
+      "reverseContinue" => debugger.reverse_continue()?,
+      "pause" => return self.stopped("pause"),
+      _ => return Ok(()),
+    };
+    match stop {
+      Stop::Step => self.stopped("step"),
+      Stop::Breakpoint(_) => self.stopped("breakpoint"),
+      Stop::Write(_) | Stop::Watch { .. } => self.stopped("data breakpoint"),
+      Stop::HistoryStart | Stop::CycleLimit => self.stopped("pause"),
+      Stop::Halted => {
+        let cycles = self.debugger()?.emulator.cycles;
+        self.event(
+          "output",
+          Json::object(vec![
+            ("category", "console".into()),
+            ("output", format!("Halted after {} cycles\n", cycles).into()),
+          ]),
+        )?;
+        self.event("exited", Json::object(vec![("exitCode", Json::Number(0))]))?;
+        self.event("terminated", Json::Null)
+      }
+    }
+  }
+
+  // Continues in chunks until the program stops, or returns `None` when a
+  // pause request or the end of the input comes in first. The pending
+  // requests are then answered as usual, the pause with a stopped event.
+  fn cont(&mut self) -> Result<Option<Stop>, String> {
+    loop {
+      let debugger = self.debugger.as_mut().ok_or("no program launched")?;
+      let stop = debugger.cont_for(CONTINUE_CHUNK)?;
+      if stop != Stop::CycleLimit {
+        return Ok(Some(stop));
+      }
+      let ended = loop {
+        match self.messages.try_recv() {
+          Ok(message) => self.pending.push_back(message),
+          Err(TryRecvError::Empty) => break false,
+          Err(TryRecvError::Disconnected) => break true,
+        }
+      };
+      if ended
+        || self.pending.iter().any(|message| {
+          message
+            .as_ref()
+            .is_ok_and(|request| request.get("command").and_then(Json::as_str) == Some("pause"))
+        })
+      {
+        return Ok(None);
+      }
+    }
+  }
+
+  // The next request, or `None` at the end of the input.
+  fn next_message(&mut self) -> Option<Result<Json, String>> {
+    self
+      .pending
+      .pop_front()
+      .or_else(|| self.messages.recv().ok())
+  }
+
+  fn debugger(&self) -> Result<&Debugger, String> {
+    self
+      .debugger
+      .as_ref()
+      .ok_or_else(|| "no program launched".to_string())
+  }
+
+  fn source(&self, file: &str) -> Json {
+    Json::object(vec![
+      ("name", file.into()),
+      ("path", self.paths.get(file).cloned().into()),
+    ])
+  }
+
+  // Replaces the line breakpoints of one file.
+  fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+    let path = arguments
+      .get("source")
+      .and_then(|source| source.get("path"))
+      .and_then(Json::as_str)
+      .ok_or("setBreakpoints needs a source path")?;
+    let file = Path::new(path)
+      .file_name()
+      .map(|name| name.to_string_lossy().into_owned())
+      .unwrap_or_default();
+    let lines: Vec<(usize, Option<&str>)> = arguments
+      .get("breakpoints")
+      .and_then(Json::as_array)
+      .unwrap_or_default()
+      .iter()
+      .filter_map(|breakpoint| {
+        let line = breakpoint.get("line").and_then(Json::as_i64)?;
+        let condition = breakpoint.get("condition").and_then(Json::as_str);
+        Some((line as usize, condition))
+      })
+      .collect();
+    let debugger = self.debugger.as_mut().ok_or("no program launched")?;
+    debugger
+      .breakpoints
+      .retain(|(breakpoint, ..)| !matches!(breakpoint, Breakpoint::Line(name, _) if *name == file));
+    let breakpoints = lines
+      .into_iter()
+      .map(|(line, condition)| {
+        let added = match condition.filter(|condition| !condition.trim().is_empty()) {
+          Some(condition) => condition.parse().map(Some),
+          None => Ok(None),
+        }
+        .and_then(|condition| {
+          debugger.add_breakpoint(Breakpoint::Line(file.clone(), line), condition)
+        });
+        match added {
+          Ok(address) => {
+            let line = debugger
+              .source_map
+              .lookup(address)
+              .map_or(line, |mapping| mapping.line);
+            Json::object(vec![("verified", true.into()), ("line", line.into())])
+          }
+          Err(err) => Json::object(vec![
+            ("verified", false.into()),
+            ("line", line.into()),
+            ("message", err.into()),
+          ]),
+        }
+      })
+      .collect();
+    Ok(Json::object(vec![(
+      "breakpoints",
+      Json::Array(breakpoints),
+    )]))
+  }
+
+  fn set_function_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+    let names: Vec<String> = arguments
+      .get("breakpoints")
+      .and_then(Json::as_array)
+      .unwrap_or_default()
+      .iter()
+      .filter_map(|breakpoint| breakpoint.get("name").and_then(Json::as_str))
+      .map(String::from)
+      .collect();
+    let debugger = self.debugger.as_mut().ok_or("no program launched")?;
+    debugger
+      .breakpoints
+      .retain(|(breakpoint, ..)| !matches!(breakpoint, Breakpoint::Function(_)));
+    let breakpoints = names
+      .into_iter()
+      .map(
+        |name| match debugger.add_breakpoint(Breakpoint::Function(name), None) {
+          Ok(_) => Json::object(vec![("verified", true.into())]),
+          Err(err) => Json::object(vec![("verified", false.into()), ("message", err.into())]),
+        },
+      )
+      .collect();
+    Ok(Json::object(vec![(
+      "breakpoints",
+      Json::Array(breakpoints),
+    )]))
+  }
+
+  fn stack_trace(&self) -> Result<Json, String> {
+    let frames: Vec<Json> = self
+      .debugger()?
+      .backtrace()
+      .iter()
+      .enumerate()
+      .map(|(i, frame)| {
+        Json::object(vec![
+          ("id", i.into()),
+          (
+            "name",
+            frame.function.as_deref().unwrap_or(&frame.file).into(),
+          ),
+          ("source", self.source(&frame.file)),
+          ("line", frame.line.into()),
+          ("column", Json::Number(1)),
+        ])
+      })
+      .collect();
+    Ok(Json::object(vec![
+      ("totalFrames", frames.len().into()),
+      ("stackFrames", Json::Array(frames)),
+    ]))
+  }
+
+  // `<segment> <index>` or `RAM[<address>]`.
+  fn evaluate(&self, frame: usize, expression: &str) -> Result<i16, String> {
+    let debugger = self.debugger()?;
+    let expression = expression.trim();
+    if let Some(address) = expression
+      .strip_prefix("RAM[")
+      .and_then(|rest| rest.strip_suffix(']'))
+    {
+      let address: usize = address
+        .parse()
+        .map_err(|_| format!("invalid address {}", address))?;
+      return debugger
+        .emulator
+        .ram
+        .get(address)
+        .map(|word| *word as i16)
+        .ok_or_else(|| format!("RAM[{}] is out of range", address));
+    }
+    match expression
+      .split_whitespace()
+      .collect::<Vec<&str>>()
+      .as_slice()
+    {
+      [segment, index] => {
+        let index = index
+          .parse()
+          .map_err(|_| format!("invalid index {}", index))?;
+        Ok(debugger.segment(frame, segment, Some(index))?[0].1)
+      }
+      _ => Err(format!(
+        "cannot evaluate {}, try `local 0` or `RAM[256]`",
+        expression
+      )),
+    }
+  }
+
+  fn stopped(&mut self, reason: &str) -> Result<(), String> {
+    self.event(
+      "stopped",
+      Json::object(vec![
+        ("reason", reason.into()),
+        ("threadId", THREAD.into()),
+        ("allThreadsStopped", true.into()),
+      ]),
+    )
+  }
+
+  fn event(&mut self, event: &str, body: Json) -> Result<(), String> {
+    let mut message = vec![("type", "event".into()), ("event", event.into())];
+    if body != Json::Null {
+      message.push(("body", body));
+    }
+    self.send(Json::object(message))
+  }
+
+  fn send(&mut self, mut message: Json) -> Result<(), String> {
+    self.seq += 1;
+    if let Json::Object(fields) = &mut message {
+      fields.insert(0, ("seq".to_string(), self.seq.into()));
+    }
+    let message = message.to_string();
+    write!(
+      self.output,
+      "Content-Length: {}\r\n\r\n{}",
+      message.len(),
+      message
+    )
+    .and_then(|_| self.output.flush())
+    .map_err(|err| err.to_string())
+  }
+}
+
+// The next message, or `None` at the end of the input. A message without
+// a length, over MAX_MESSAGE or with a body that doesn't parse is an error
+// for that message alone.
+fn receive(input: &mut impl BufRead) -> io::Result<Option<Result<Json, String>>> {
+  let mut length = None;
+  loop {
+    let mut header = String::new();
+    if input.by_ref().take(MAX_HEADER).read_line(&mut header)? == 0 {
+      return Ok(None);
+    }
+    let header = header.trim();
+    if header.is_empty() {
+      break;
+    }
+    if let Some(value) = header.strip_prefix("Content-Length:") {
+      length = value.trim().parse().ok();
+    }
+  }
+  let length: usize = match length {
+    Some(length) => length,
+    None => {
+      return Ok(Some(Err(
+        "message without a Content-Length header".to_string(),
+      )))
+    }
+  };
+  if length > MAX_MESSAGE {
+    io::copy(&mut input.by_ref().take(length as u64), &mut io::sink())?;
+    return Ok(Some(Err(format!(
+      "message of {} bytes is over the {} byte limit",
+      length, MAX_MESSAGE
+    ))));
+  }
+  let mut body = vec![0; length];
+  input.read_exact(&mut body)?;
+  Ok(Some(String::from_utf8_lossy(&body).parse()))
+}
@@ -0,0 +1,536 @@
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+
+use crate::emulator::Emulator;
+use crate::history::History;
+use crate::interpreter::{ARG, LCL, THAT, THIS};
+use crate::snapshot::Snapshot;
+use crate::source_map::{Mapping, SourceMap};
+use crate::watch::{Condition, Watch};
+use crate::{Build, VmParser};
+
+// Cycles `continue` runs before giving control back.
+const MAX_CYCLES: u64 = 100_000_000;
+const SEGMENT_PREVIEW: u16 = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+  Function(String),
+  Line(String, usize),
+}
+
+impl FromStr for Breakpoint {
+  type Err = String;
+  fn from_str(str: &str) -> Result<Self, Self::Err> {
+    match str.rsplit_once(':') {
+      Some((file, line)) => {
+        let line = line
+          .parse()
+          .map_err(|_| format!("invalid line number {}", line))?;
+        let file = if file.ends_with(".vm") {
+          file.to_string()
+        } else {
+          format!("{}.vm", file)
+        };
+        Ok(Breakpoint::Line(file, line))
+      }
+      None if str.is_empty() => Err("expected a function name or file:line".to_string()),
+      None => Ok(Breakpoint::Function(str.to_string())),
+    }
+  }
+}
+
+impl std::fmt::Display for Breakpoint {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      Breakpoint::Function(name) => write!(f, "{}", name),
+      Breakpoint::Line(file, line) => write!(f, "{}:{}", file, line),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+  Step,
+  Breakpoint(usize),
+  Halted,
+  CycleLimit,
+  Write(u16),
+  // Watch `watch` saw RAM[address] change from `old` to `new` in the
+  // instruction at ROM address `pc`.
+  Watch {
+    watch: usize,
+    address: u16,
+    old: u16,
+    new: u16,
+    pc: u16,
+  },
+  HistoryStart,
+}
+
+// A function activation, located at the current command for the innermost
+// frame and at the pending `call` for its callers.
+#[derive(Debug)]
+pub struct Frame {
+  pub function: Option<String>,
+  pub file: String,
+  pub line: usize,
+  pub command: String,
+  pub lcl: u16,
+  pub arg: u16,
+  pub this: u16,
+  pub that: u16,
+}
+
+// Runs translated code in the emulator and stops between VM commands, using
+// the source map to relate ROM addresses back to the VM files.
+pub struct Debugger {
+  pub emulator: Emulator,
+  pub source_map: SourceMap,
+  // Breakpoints with their ROM address and the condition for stopping there.
+  pub breakpoints: Vec<(Breakpoint, usize, Option<Condition>)>,
+  // Watches with the address of those that don't move with the frames.
+  pub watches: Vec<(Watch, Option<u16>)>,
+  pub history: History,
+  variables: BTreeMap<String, u16>,
+  starts: HashSet<usize>,
+}
+
+impl Debugger {
+  // Keeps the last `max_deltas` cycles for stepping backwards.
+  pub fn new(parsers: Vec<VmParser>, max_deltas: usize) -> Result<Self, String> {
+    let Build {
+      program,
+      source_map,
+      emulator,
+    } = crate::build(parsers, false)?;
+    let starts = source_map
+      .mappings
+      .iter()
+      .filter(|mapping| mapping.file.is_some() && mapping.count > 0)
+      .map(|mapping| mapping.address)
+      .collect();
+    let mut debugger = Debugger {
+      history: History::new(&emulator, max_deltas),
+      emulator,
+      source_map,
+      breakpoints: Vec::new(),
+      watches: Vec::new(),
+      variables: program.variables,
+      starts,
+    };
+    if debugger
+      .current()
+      .is_some_and(|mapping| mapping.file.is_none())
+    {
+      // Run the bootstrap code so we start at the first command of Sys.init.
+      debugger.run_until(MAX_CYCLES, |_| true)?;
+    }
+    Ok(debugger)
+  }
+
+  // Continues from a snapshot of the same program, without the history that
+  // led to it.
+  pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), String> {
+    if snapshot.rom[..] != self.emulator.rom[..self.emulator.size] {
+      return Err("the snapshot is of a different program".to_string());
+    }
+    self.emulator = snapshot.emulator()?;
+    self.history.restart(&self.emulator);
+    Ok(())
+  }
+
+  pub fn current(&self) -> Option<&Mapping> {
+    self.source_map.lookup(self.emulator.pc as usize)
+  }
+
+  // Stuck in an `(END) @END 0;JMP` loop or run past the last command.
+  pub fn is_halted(&self) -> bool {
+    self.emulator.is_halted() || self.emulator.pc as usize >= self.source_map.instruction_count()
+  }
+
+  pub fn add_breakpoint(
+    &mut self,
+    breakpoint: Breakpoint,
+    condition: Option<Condition>,
+  ) -> Result<usize, String> {
+    let address = self
+      .source_map
+      .mappings
+      .iter()
+      .find(|mapping| match &breakpoint {
+        Breakpoint::Function(name) => {
+          mapping.kind == "function" && mapping.function.as_ref() == Some(name)
+        }
+        Breakpoint::Line(file, line) => {
+          mapping.file.as_ref() == Some(file) && mapping.line >= *line && mapping.count > 0
+        }
+      })
+      .map(|mapping| mapping.address)
+      .ok_or_else(|| format!("no code for {}", breakpoint))?;
+    self.breakpoints.push((breakpoint, address, condition));
+    Ok(address)
+  }
+
+  // Adds a watch, filling in the current file or function where it has none.
+  pub fn add_watch(&mut self, watch: Watch) -> Result<(Watch, Option<u16>), String> {
+    let current = self.current();
+    let file = current
+      .and_then(|mapping| mapping.file.as_deref())
+      .map(|file| file.trim_end_matches(".vm").to_string());
+    let function = current.and_then(|mapping| mapping.function.clone());
+    let watch = match watch {
+      Watch::Static(None, index) => Watch::Static(Some(file.ok_or("not in a VM file")?), index),
+      Watch::Local(None, index) => Watch::Local(Some(function.ok_or("not in a function")?), index),
+      Watch::Argument(None, index) => {
+        Watch::Argument(Some(function.ok_or("not in a function")?), index)
+      }
+      watch => watch,
+    };
+    let address =
+      match &watch {
+        Watch::Static(Some(file), index) => Some(
+          *self
+            .variables
+            .get(&format!("{}.{}", file, index))
+            .ok_or_else(|| format!("{} has no static {}", file, index))?,
+        ),
+        Watch::Local(Some(function), _) | Watch::Argument(Some(function), _) => {
+          if !self.source_map.mappings.iter().any(|mapping| {
+            mapping.kind == "function" && mapping.function.as_ref() == Some(function)
+          }) {
+            return Err(format!("no function {}", function));
+          }
+          None
+        }
+        watch => watch.address(),
+      };
+    self.watches.push((watch.clone(), address));
+    Ok((watch, address))
+  }
+
+  // Runs to the next VM command.
+  pub fn step(&mut self) -> Result<Stop, String> {
+    self.run_until(MAX_CYCLES, |_| true)
+  }
+
+  // Like `step`, but runs a `call` through to the command after it.
+  pub fn step_over(&mut self) -> Result<Stop, String> {
+    match self.current() {
+      Some(mapping) if mapping.kind == "call" => {
+        let address = mapping.address + mapping.count;
+        let lcl = self.emulator.ram[LCL];
+        self.run_until(MAX_CYCLES, |debugger| {
+          debugger.emulator.pc as usize == address && debugger.emulator.ram[LCL] == lcl
+        })
+      }
+      _ => self.step(),
+    }
+  }
+
+  // Runs until the current function returns to its caller.
+  pub fn finish(&mut self) -> Result<Stop, String> {
+    let backtrace = self.backtrace();
+    if backtrace.len() < 2 {
+      return Err("the outermost frame has no caller".to_string());
+    }
+    let frame = backtrace[0].lcl as usize;
+    let address = self.emulator.ram[frame - 5] as usize;
+    let lcl = self.emulator.ram[frame - 4];
+    self.run_until(MAX_CYCLES, |debugger| {
+      debugger.emulator.pc as usize == address && debugger.emulator.ram[LCL] == lcl
+    })
+  }
+
+  pub fn cont(&mut self) -> Result<Stop, String> {
+    self.cont_for(MAX_CYCLES)
+  }
+
+  // Like `cont`, but gives control back after `max_cycles`.
+  pub fn cont_for(&mut self, max_cycles: u64) -> Result<Stop, String> {
+    self.run_until(max_cycles, |_| false)
+  }
+
+  // Steps the emulator until it reaches a command for which `stop` holds or
+  // that has a breakpoint on it.
+  fn run_until(&mut self, max_cycles: u64, stop: impl Fn(&Self) -> bool) -> Result<Stop, String> {
+    for _ in 0..max_cycles {
+      if self.is_halted() {
+        return Ok(Stop::Halted);
+      }
+      let write = self.watched_write();
+      let pc = self.emulator.pc;
+      self.history.step(&mut self.emulator)?;
+      if let Some((address, old)) = write {
+        let new = self.emulator.ram[address as usize];
+        if new != old {
+          if let Some(watch) = self.watch_at(address, pc as usize) {
+            return Ok(Stop::Watch {
+              watch,
+              address,
+              old,
+              new,
+              pc,
+            });
+          }
+        }
+      }
+      if let Some(stop) = self.stop_at_command(&stop) {
+        return Ok(stop);
+      }
+    }
+    Ok(Stop::CycleLimit)
+  }
+
+  fn stop_at_command(&self, stop: impl Fn(&Self) -> bool) -> Option<Stop> {
+    let pc = self.emulator.pc as usize;
+    if !self.starts.contains(&pc) {
+      return None;
+    }
+    // A condition that can't be evaluated stops, to be looked at.
+    if let Some(i) = self.breakpoints.iter().position(|(_, address, condition)| {
+      *address == pc
+        && condition
+          .as_ref()
+          .is_none_or(|condition| condition.holds(&self.emulator).unwrap_or(true))
+    }) {
+      return Some(Stop::Breakpoint(i));
+    }
+    Some(Stop::Step).filter(|_| stop(self))
+  }
+
+  // The address and old value of the RAM word the next instruction writes,
+  // when anything is watched.
+  fn watched_write(&self) -> Option<(u16, u16)> {
+    if self.watches.is_empty() {
+      return None;
+    }
+    let word = *self.emulator.rom.get(self.emulator.pc as usize)?;
+    if word & 0x8008 != 0x8008 {
+      return None;
+    }
+    let address = self.emulator.a;
+    Some((address, *self.emulator.ram.get(address as usize)?))
+  }
+
+  // The watch on RAM[address] as written by the instruction at `pc`. Locals
+  // and arguments are looked up in the frames of the call stack there.
+  fn watch_at(&self, address: u16, pc: usize) -> Option<usize> {
+    let mut frames = None;
+    self.watches.iter().position(|(watch, fixed)| {
+      let (function, index, local) = match (watch, fixed) {
+        (_, Some(fixed)) => return *fixed == address,
+        (Watch::Local(Some(function), index), _) => (function, *index, true),
+        (Watch::Argument(Some(function), index), _) => (function, *index, false),
+        _ => return false,
+      };
+      frames
+        .get_or_insert_with(|| backtrace(&self.emulator.ram, &self.source_map, pc))
+        .iter()
+        .any(|frame| {
+          let base = if local { frame.lcl } else { frame.arg };
+          frame.function.as_ref() == Some(function) && base.wrapping_add(index) == address
+        })
+    })
+  }
+
+  // Runs backwards to the previous VM command.
+  pub fn reverse_step(&mut self) -> Result<Stop, String> {
+    Ok(self.reverse_until(|_| true))
+  }
+
+  // Runs backwards to the previous VM command in this function or its
+  // callers, skipping over the commands of any function it called.
+  pub fn reverse_step_over(&mut self) -> Result<Stop, String> {
+    let lcl = self.emulator.ram[LCL];
+    Ok(self.reverse_until(|debugger| debugger.emulator.ram[LCL] <= lcl))
+  }
+
+  // Runs backwards to the previous breakpoint.
+  pub fn reverse_continue(&mut self) -> Result<Stop, String> {
+    Ok(self.reverse_until(|_| false))
+  }
+
+  // Runs backwards to just before the last instruction that wrote RAM[address].
+  pub fn reverse_to_write(&mut self, address: u16) -> Result<Stop, String> {
+    while let Some(write) = self.history.undo(&mut self.emulator) {
+      if write == Some(address) {
+        return Ok(Stop::Write(address));
+      }
+    }
+    Ok(Stop::HistoryStart)
+  }
+
+  pub fn seek(&mut self, cycle: u64) -> Result<(), String> {
+    self.history.seek(&mut self.emulator, cycle)
+  }
+
+  fn reverse_until(&mut self, stop: impl Fn(&Self) -> bool) -> Stop {
+    while self.history.undo(&mut self.emulator).is_some() {
+      if let Some(stop) = self.stop_at_command(&stop) {
+        return stop;
+      }
+    }
+    Stop::HistoryStart
+  }
+
+  // The call stack at the current command.
+  pub fn backtrace(&self) -> Vec<Frame> {
+    backtrace(
+      &self.emulator.ram,
+      &self.source_map,
+      self.emulator.pc as usize,
+    )
+  }
+
+  // Values of a segment in frame `frame` of the backtrace by name, all of it
+  // or just the entry at `index`. `this` and `that` show their first few
+  // entries.
+  pub fn segment(
+    &self,
+    frame: usize,
+    name: &str,
+    index: Option<u16>,
+  ) -> Result<Vec<(String, i16)>, String> {
+    let ram = &self.emulator.ram;
+    let frames = self.backtrace();
+    let (lcl, arg, this, that, function, file) = match frames.get(frame) {
+      Some(frame) => (
+        frame.lcl,
+        frame.arg,
+        frame.this,
+        frame.that,
+        frame.function.as_deref(),
+        Some(frame.file.as_str()),
+      ),
+      // Outside any VM file, in the bootstrap code.
+      None if frame == 0 => (ram[LCL], ram[ARG], ram[THIS], ram[THAT], None, None),
+      None => return Err(format!("no frame {}", frame)),
+    };
+    let (base, len) = match name {
+      "local" => (
+        lcl,
+        function.and_then(|function| self.local_count(function)),
+      ),
+      "argument" => (arg, lcl.checked_sub(arg.wrapping_add(5))),
+      "this" => (this, Some(SEGMENT_PREVIEW)),
+      "that" => (that, Some(SEGMENT_PREVIEW)),
+      "pointer" => {
+        let pointers = [this, that];
+        let indexes = match index {
+          Some(index) if index < 2 => index..index + 1,
+          Some(index) => return Err(format!("pointer {} out of range", index)),
+          None => 0..2,
+        };
+        return Ok(
+          indexes
+            .map(|index| {
+              (
+                format!("pointer {}", index),
+                pointers[index as usize] as i16,
+              )
+            })
+            .collect(),
+        );
+      }
+      "temp" => (5, Some(8)),
+      "static" => return self.statics(file.ok_or("not in a VM file")?, index),
+      _ => return Err(format!("unknown segment {}", name)),
+    };
+    let indexes = match (index, len) {
+      (Some(index), _) => index..index + 1,
+      (None, Some(len)) => 0..len,
+      (None, None) => return Err(format!("{} needs an index here", name)),
+    };
+    indexes
+      .map(|index| {
+        let address = base.wrapping_add(index) as usize;
+        ram
+          .get(address)
+          .map(|word| (format!("{} {}", name, index), *word as i16))
+          .ok_or_else(|| format!("{} {} is outside RAM at {}", name, index, address))
+      })
+      .collect()
+  }
+
+  fn local_count(&self, function: &str) -> Option<u16> {
+    let declaration = self.source_map.mappings.iter().find(|mapping| {
+      mapping.kind == "function" && mapping.function.as_deref() == Some(function)
+    })?;
+    declaration.command.rsplit(' ').next()?.parse().ok()
+  }
+
+  fn statics(&self, file: &str, index: Option<u16>) -> Result<Vec<(String, i16)>, String> {
+    let prefix = format!("{}.", file.trim_end_matches(".vm"));
+    let mut statics: Vec<(u16, u16)> = self
+      .variables
+      .iter()
+      .filter_map(|(name, address)| {
+        let index = name.strip_prefix(&prefix)?.parse().ok()?;
+        Some((index, *address))
+      })
+      .filter(|(i, _)| index.is_none_or(|index| index == *i))
+      .collect();
+    statics.sort();
+    if statics.is_empty() && index.is_some() {
+      return Err(format!("{} has no such static", file));
+    }
+    Ok(
+      statics
+        .into_iter()
+        .map(|(index, address)| {
+          (
+            format!("static {}", index),
+            self.emulator.ram[address as usize] as i16,
+          )
+        })
+        .collect(),
+    )
+  }
+}
+
+// The call stack at ROM address `pc`. Walks the saved frames from LCL: each
+// holds the return address at LCL-5 and the caller's LCL and ARG at LCL-4 and
+// LCL-3.
+pub fn backtrace(ram: &[u16], source_map: &SourceMap, pc: usize) -> Vec<Frame> {
+  let mut frames = Vec::new();
+  let mut mapping = match source_map.lookup(pc) {
+    Some(mapping) => mapping,
+    None => return frames,
+  };
+  let mut lcl = ram[LCL];
+  let mut arg = ram[ARG];
+  let mut this = ram[THIS];
+  let mut that = ram[THAT];
+  while let Some(file) = &mapping.file {
+    frames.push(Frame {
+      function: mapping.function.clone(),
+      file: file.clone(),
+      line: mapping.line,
+      command: mapping.command.clone(),
+      lcl,
+      arg,
+      this,
+      that,
+    });
+    if mapping.function.is_none() || lcl < 5 || lcl as usize > ram.len() {
+      break;
+    }
+    let frame = lcl as usize;
+    let caller = (ram[frame - 5] as usize)
+      .checked_sub(1)
+      .and_then(|address| source_map.lookup(address));
+    match caller {
+      Some(caller) if caller.kind == "call" || caller.file.is_none() => mapping = caller,
+      _ => break,
+    }
+    if ram[frame - 4] >= lcl {
+      break;
+    }
+    lcl = ram[frame - 4];
+    arg = ram[frame - 3];
+    this = ram[frame - 2];
+    that = ram[frame - 1];
+  }
+  frames
+}
@@ -0,0 +1,124 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::hack::{self, Instruction};
+
+#[derive(Debug, Default)]
+pub struct SymbolMap {
+  pub labels: BTreeMap<u16, Vec<String>>,
+  pub variables: BTreeMap<u16, String>,
+}
+
+impl SymbolMap {
+  fn label(&self, address: u16) -> Option<&String> {
+    self.labels.get(&address).and_then(|names| names.first())
+  }
+}
+
+impl std::str::FromStr for SymbolMap {
+  type Err = String;
+  fn from_str(str: &str) -> Result<Self, Self::Err> {
+    let mut map = SymbolMap::default();
+    for (i, line) in str.lines().enumerate() {
+      match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        [] => (),
+        [kind, address, name] => {
+          let address = address
+            .parse()
+            .map_err(|_| format!("line {}: invalid address {}", i + 1, address))?;
+          match *kind {
+            "label" => map
+              .labels
+              .entry(address)
+              .or_insert_with(Vec::new)
+              .push(name.to_string()),
+            "var" => {
+              map.variables.insert(address, name.to_string());
+            }
+            _ => return Err(format!("line {}: unknown symbol kind {}", i + 1, kind)),
+          }
+        }
+        _ => return Err(format!("line {}: couldn't parse symbol {}", i + 1, line)),
+      }
+    }
+    Ok(map)
+  }
+}
+
+pub fn disassemble(rom: &[u16], symbols: &SymbolMap) -> String {
+  let instructions: Vec<Option<Instruction>> = rom
+    .iter()
+    .map(|word| Instruction::decode(*word).ok())
+    .collect();
+
+  // The jump bits count even in C instructions with an unknown comp.
+  let jumps = |address: usize| {
+    rom
+      .get(address)
+      .is_some_and(|word| word & 0x8000 != 0 && word & 0b111 != 0)
+  };
+  let load_return = Instruction::parse_c("D=A").unwrap();
+  // An A instruction loads a ROM address when a jump follows it, or when `D=A`
+  // follows it and the address comes right after an unconditional jump, like
+  // the return address a VM `call` pushes.
+  let loads_address = |address: usize| match instructions[address] {
+    Some(Instruction::A(value)) if (value as usize) < rom.len() => {
+      jumps(address + 1)
+        || instructions.get(address + 1) == Some(&Some(load_return))
+          && value > 0
+          && rom[value as usize - 1] & 0x8007 == 0x8007
+    }
+    _ => false,
+  };
+  let targets: BTreeSet<u16> = (0..rom.len())
+    .filter(|address| loads_address(*address))
+    .map(|address| rom[address])
+    .collect();
+  let label = |address: u16| {
+    symbols
+      .label(address)
+      .cloned()
+      .unwrap_or_else(|| format!("L{}", address))
+  };
+
+  let mut asm = String::new();
+  for (address, instruction) in instructions.iter().enumerate() {
+    let address = address as u16;
+    match symbols.labels.get(&address) {
+      Some(names) => names
+        .iter()
+        .for_each(|name| asm.push_str(&format!("({})\n", name))),
+      None if targets.contains(&address) => asm.push_str(&format!("({})\n", label(address))),
+      None => (),
+    }
+    let next = instructions.get(address as usize + 1).cloned().flatten();
+    let text = match instruction {
+      Some(Instruction::A(value)) if loads_address(address as usize) => {
+        format!("@{}", label(*value))
+      }
+      Some(Instruction::A(value)) if next.is_some_and(|n| n.uses_memory()) => {
+        match symbols
+          .variables
+          .get(value)
+          .cloned()
+          .or_else(|| hack::register_name(*value))
+        {
+          Some(name) => format!("@{}", name),
+          None => format!("@{}", value),
+        }
+      }
+      Some(instruction) => instruction.to_asm(),
+      // The assembler has no spelling for it, so the raw word stays as a
+      // comment.
+      None => {
+        let word = rom[address as usize];
+        format!(
+          "// {:016b} unknown comp {:07b}",
+          word,
+          word >> 6 & 0b1111111
+        )
+      }
+    };
+    asm.push_str(&format!("{:<32}// {}\n", text, address));
+  }
+  asm
+}
@@ -0,0 +1,222 @@
+use std::fs;
+use std::path::Path;
+
+use crate::assembler;
+use crate::hack;
+use crate::loops::LoopDetector;
+use crate::traps::{Trap, Traps};
+
+pub const RAM_SIZE: usize = 32768;
+pub const SCREEN: usize = 16384;
+pub const KBD: usize = 24576;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+  Running,
+  Halted,
+  CycleLimit,
+}
+
+#[derive(Debug, Clone)]
+pub struct Emulator {
+  pub rom: Vec<u16>,
+  pub ram: Vec<u16>,
+  pub a: u16,
+  pub d: u16,
+  pub pc: u16,
+  pub cycles: u64,
+  // Number of instructions loaded.
+  pub size: usize,
+  pub traps: Option<Traps>,
+  pub loops: Option<LoopDetector>,
+}
+
+impl Emulator {
+  pub fn new(program: &[u16]) -> Result<Self, String> {
+    if program.len() > hack::ROM_SIZE {
+      return Err(format!(
+        "program has {} instructions, Hack ROM holds {}",
+        program.len(),
+        hack::ROM_SIZE
+      ));
+    }
+    let size = program.len();
+    let mut rom = program.to_vec();
+    rom.resize(hack::ROM_SIZE, 0);
+    Ok(Emulator {
+      rom,
+      ram: vec![0; RAM_SIZE],
+      a: 0,
+      d: 0,
+      pc: 0,
+      cycles: 0,
+      size,
+      traps: None,
+      loops: None,
+    })
+  }
+
+  pub fn from_asm(asm: &str) -> Result<Self, String> {
+    Emulator::new(&assembler::assemble(asm)?.instructions)
+  }
+
+  // Loads a `.asm` file, assembling it, or a `.hack` file.
+  pub fn load(path: &Path) -> Result<Self, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    match path.extension().and_then(|str| str.to_str()) {
+      Some("asm") => Emulator::from_asm(&text),
+      Some("hack") => Emulator::new(&hack::parse_hack(&text)?),
+      _ => Err(format!("{}: expected a .asm or .hack file", path.display())),
+    }
+  }
+
+  // Stops the program at the first trap from here on; `stack` keeps RAM[0]
+  // within the VM stack.
+  pub fn enable_traps(&mut self, stack: bool) {
+    self.traps = Some(Traps::new(self, self.size, stack));
+  }
+
+  // Stops the program once it goes back to an earlier state, other than the
+  // halt loop.
+  pub fn enable_loop_detection(&mut self) {
+    self.loops = Some(LoopDetector::new(self));
+  }
+
+  // A program is halted when it sits in the `(END) @END 0;JMP` loop: an
+  // unconditional jump to itself that doesn't store anything.
+  pub fn is_halted(&self) -> bool {
+    let pc = self.pc as usize;
+    let jumps = |word: &u16| word & 0x803f == 0x8007;
+    match self.rom.get(pc..pc + 2) {
+      Some([at, next]) if *at == self.pc && jumps(next) => true,
+      _ => self.a == self.pc && self.rom.get(pc).is_some_and(jumps),
+    }
+  }
+
+  pub fn run(&mut self, max_cycles: u64) -> Result<Status, String> {
+    for _ in 0..max_cycles {
+      if self.is_halted() {
+        return Ok(Status::Halted);
+      }
+      self.step()?;
+    }
+    Ok(if self.is_halted() {
+      Status::Halted
+    } else {
+      Status::CycleLimit
+    })
+  }
+
+  pub fn step(&mut self) -> Result<(), String> {
+    if let Some(traps) = &self.traps {
+      if let Err(trap) = traps.check(self) {
+        return Err(self.trapped(trap));
+      }
+    }
+    let pc = self.pc;
+    let write = match self.execute() {
+      Ok(write) => write,
+      Err(err) if self.traps.is_some() => return Err(self.trapped(Trap::Fault(err))),
+      Err(err) => return Err(err),
+    };
+    if let Some(traps) = &mut self.traps {
+      if let Err(trap) = traps.ran(&self.ram, pc, write) {
+        return Err(self.trapped(trap));
+      }
+    }
+    if let Some(mut loops) = self.loops.take() {
+      let result = loops.ran(self, write);
+      self.loops = Some(loops);
+      result.map_err(|stuck| stuck.to_string())?;
+    }
+    Ok(())
+  }
+
+  fn trapped(&mut self, trap: Trap) -> String {
+    let message = trap.to_string();
+    if let Some(traps) = &mut self.traps {
+      traps.trap = Some(trap);
+    }
+    message
+  }
+
+  // Runs one instruction, returning the address it wrote to.
+  fn execute(&mut self) -> Result<Option<u16>, String> {
+    let word = *self
+      .rom
+      .get(self.pc as usize)
+      .ok_or_else(|| format!("PC {} outside ROM", self.pc))?;
+    self.cycles += 1;
+    if word & 0x8000 == 0 {
+      self.a = word;
+      self.pc += 1;
+      return Ok(None);
+    }
+
+    let uses_memory = word & 0x1000 != 0;
+    let y = if uses_memory {
+      self.read(self.a)?
+    } else {
+      self.a
+    };
+    let out = alu(self.d, y, word >> 6);
+    let address = self.a;
+    let write = word & 0b001000 != 0;
+    if write {
+      self.write(address, out)?;
+    }
+    if word & 0b100000 != 0 {
+      self.a = out;
+    }
+    if word & 0b010000 != 0 {
+      self.d = out;
+    }
+    let negative = out & 0x8000 != 0;
+    let jump = match word & 0b111 {
+      0 => false,
+      1 => !negative && out != 0,
+      2 => out == 0,
+      3 => !negative,
+      4 => negative,
+      5 => out != 0,
+      6 => negative || out == 0,
+      _ => true,
+    };
+    self.pc = if jump { address } else { self.pc + 1 };
+    Ok(if write { Some(address) } else { None })
+  }
+
+  fn read(&self, address: u16) -> Result<u16, String> {
+    self
+      .ram
+      .get(address as usize)
+      .cloned()
+      .ok_or_else(|| format!("read outside RAM at {} (PC {})", address, self.pc))
+  }
+
+  fn write(&mut self, address: u16, value: u16) -> Result<(), String> {
+    let pc = self.pc;
+    match self.ram.get_mut(address as usize) {
+      Some(word) => {
+        *word = value;
+        Ok(())
+      }
+      None => Err(format!("write outside RAM at {} (PC {})", address, pc)),
+    }
+  }
+}
+
+// The Hack ALU, driven by the six control bits zx nx zy ny f no.
+fn alu(x: u16, y: u16, control: u16) -> u16 {
+  let bit = |n: u16| control & (1 << n) != 0;
+  let x = if bit(5) { 0 } else { x };
+  let x = if bit(4) { !x } else { x };
+  let y = if bit(3) { 0 } else { y };
+  let y = if bit(2) { !y } else { y };
+  let out = if bit(1) { x.wrapping_add(y) } else { x & y };
+  if bit(0) {
+    !out
+  } else {
+    out
+  }
+}
@@ -0,0 +1,402 @@
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::emulator::Emulator;
+use crate::hack::ROM_SIZE;
+
+pub const DEFAULT_PORT: u16 = 1234;
+// Cycles to run between checks for an interrupt from gdb while continuing.
+const CONTINUE_CHUNK: u64 = 100_000;
+// ROM is mapped into gdb's byte address space above RAM.
+const ROM_BASE: usize = 0x20000;
+// Bytes of A, D and PC.
+const REGISTER_BYTES: [usize; 3] = [2, 2, 4];
+
+const TARGET_XML: &str = "<?xml version=\"1.0\"?>\
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+<target version=\"1.0\">\
+<architecture>hack</architecture>\
+<feature name=\"org.nand2tetris.hack.cpu\">\
+<reg name=\"a\" bitsize=\"16\" type=\"uint16\" regnum=\"0\"/>\
+<reg name=\"d\" bitsize=\"16\" type=\"int16\" regnum=\"1\"/>\
+<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"2\"/>\
+</feature>\
+</target>";
+
+// A gdb remote serial protocol target for the emulator. Memory is byte
+// addressed with two bytes per word: RAM word n is at 2n and ROM word n at
+// 0x20000 + 2n. Registers are A and D, 16 bits little endian, and PC, 32 bits
+// holding the byte address of its ROM word, so that code addresses in
+// registers, breakpoints and memory reads all agree.
+pub struct GdbStub {
+  pub emulator: Emulator,
+  breakpoints: HashSet<u16>,
+}
+
+impl GdbStub {
+  pub fn new(emulator: Emulator) -> Self {
+    GdbStub {
+      emulator,
+      breakpoints: HashSet::new(),
+    }
+  }
+
+  // Serves a single gdb connection until it detaches or kills the target.
+  pub fn serve(&mut self, listener: &TcpListener) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    // Acks and replies are tiny writes that shouldn't wait on each other.
+    stream.set_nodelay(true)?;
+    let mut connection = Connection { stream };
+    while let Some(packet) = connection.receive()? {
+      match self.handle(&packet, &mut connection)? {
+        Some(reply) => connection.send(&reply)?,
+        None if packet.starts_with('D') => {
+          connection.send("OK")?;
+          break;
+        }
+        None => break,
+      }
+    }
+    Ok(())
+  }
+
+  // The reply to a packet, or `None` when the session ends.
+  fn handle(&mut self, packet: &str, connection: &mut Connection) -> io::Result<Option<String>> {
+    // The packet came from arbitrary bytes, so the command may not be ASCII.
+    let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+    let reply = match command {
+      "?" => "S05".to_string(),
+      "g" => self
+        .registers()
+        .iter()
+        .zip(REGISTER_BYTES)
+        .map(|(value, bytes)| le_hex(*value, bytes))
+        .collect(),
+      "G" => match parse_registers(args) {
+        Some(registers) if self.set_registers(registers) => "OK".to_string(),
+        _ => "E01".to_string(),
+      },
+      "p" => match usize::from_str_radix(args, 16) {
+        Ok(n) if n < 3 => le_hex(self.registers()[n], REGISTER_BYTES[n]),
+        _ => "E01".to_string(),
+      },
+      "P" => match args.split_once('=').and_then(|(n, value)| {
+        let n = usize::from_str_radix(n, 16).ok().filter(|n| *n < 3)?;
+        Some((n, parse_le(value, REGISTER_BYTES[n])?))
+      }) {
+        Some((n, value)) => {
+          let mut registers = self.registers();
+          registers[n] = value;
+          if self.set_registers(registers) {
+            "OK".to_string()
+          } else {
+            "E01".to_string()
+          }
+        }
+        None => "E01".to_string(),
+      },
+      "m" => match parse_range(args).and_then(|(address, len)| self.read(address, len)) {
+        Some(bytes) => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        None => "E01".to_string(),
+      },
+      "M" => match args.split_once(':').and_then(|(range, data)| {
+        let (address, len) = parse_range(range)?;
+        let bytes = parse_bytes(data)?;
+        Some((address, bytes)).filter(|(_, bytes)| bytes.len() == len)
+      }) {
+        Some((address, bytes)) if self.write(address, &bytes) => "OK".to_string(),
+        _ => "E01".to_string(),
+      },
+      "Z" | "z" => match args.split(',').collect::<Vec<&str>>().as_slice() {
+        [kind, address, _] if *kind == "0" || *kind == "1" => {
+          match usize::from_str_radix(address, 16).ok().and_then(code_word) {
+            Some(address) if command == "Z" => {
+              self.breakpoints.insert(address);
+              "OK".to_string()
+            }
+            Some(address) => {
+              self.breakpoints.remove(&address);
+              "OK".to_string()
+            }
+            None => "E01".to_string(),
+          }
+        }
+        _ => String::new(),
+      },
+      "s" | "c" => {
+        if !self.resume_at(args) {
+          "E01".to_string()
+        } else if command == "s" {
+          self.step()
+        } else {
+          self.cont(connection)?
+        }
+      }
+      "H" => "OK".to_string(),
+      "D" | "k" => return Ok(None),
+      "q" => self.query(args),
+      _ => String::new(),
+    };
+    Ok(Some(reply))
+  }
+
+  fn query(&self, query: &str) -> String {
+    if query.starts_with("Supported") {
+      return "PacketSize=4000;qXfer:features:read+;swbreak+".to_string();
+    }
+    if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+      return match parse_range(range) {
+        Some((offset, _)) if offset >= TARGET_XML.len() => "l".to_string(),
+        Some((offset, len)) => {
+          let end = (offset + len).min(TARGET_XML.len());
+          let more = if end < TARGET_XML.len() { "m" } else { "l" };
+          format!("{}{}", more, &TARGET_XML[offset..end])
+        }
+        None => "E01".to_string(),
+      };
+    }
+    match query {
+      "Attached" => "1".to_string(),
+      "C" => "QC1".to_string(),
+      "fThreadInfo" => "m1".to_string(),
+      "sThreadInfo" => "l".to_string(),
+      _ => String::new(),
+    }
+  }
+
+  fn registers(&self) -> [u32; 3] {
+    [
+      self.emulator.a as u32,
+      self.emulator.d as u32,
+      (ROM_BASE + 2 * self.emulator.pc as usize) as u32,
+    ]
+  }
+
+  // Fails, changing nothing, when PC isn't the address of a ROM word.
+  fn set_registers(&mut self, registers: [u32; 3]) -> bool {
+    match code_word(registers[2] as usize) {
+      Some(pc) => {
+        self.emulator.a = registers[0] as u16;
+        self.emulator.d = registers[1] as u16;
+        self.emulator.pc = pc;
+        true
+      }
+      None => false,
+    }
+  }
+
+  // `s` and `c` may carry the address to resume at.
+  fn resume_at(&mut self, address: &str) -> bool {
+    if address.is_empty() {
+      return true;
+    }
+    match usize::from_str_radix(address, 16).ok().and_then(code_word) {
+      Some(pc) => {
+        self.emulator.pc = pc;
+        true
+      }
+      None => false,
+    }
+  }
+
+  fn step(&mut self) -> String {
+    match self.emulator.step() {
+      Ok(()) => "S05".to_string(),
+      Err(_) => "S0b".to_string(),
+    }
+  }
+
+  // Runs until a breakpoint, the halt loop, an error or an interrupt from gdb.
+  fn cont(&mut self, connection: &mut Connection) -> io::Result<String> {
+    loop {
+      for _ in 0..CONTINUE_CHUNK {
+        if self.emulator.is_halted() {
+          return Ok("S05".to_string());
+        }
+        if self.emulator.step().is_err() {
+          return Ok("S0b".to_string());
+        }
+        if self.breakpoints.contains(&self.emulator.pc) {
+          return Ok("T05swbreak:;".to_string());
+        }
+      }
+      if connection.interrupted()? {
+        return Ok("S02".to_string());
+      }
+    }
+  }
+
+  fn read(&self, address: usize, len: usize) -> Option<Vec<u8>> {
+    (address..address.checked_add(len)?)
+      .map(|byte| {
+        let word = self.word(byte)?;
+        Some(if byte.is_multiple_of(2) {
+          word as u8
+        } else {
+          (word >> 8) as u8
+        })
+      })
+      .collect()
+  }
+
+  fn word(&self, byte: usize) -> Option<u16> {
+    match byte.checked_sub(ROM_BASE) {
+      Some(rom) => self.emulator.rom.get(rom / 2).cloned(),
+      None => self.emulator.ram.get(byte / 2).cloned(),
+    }
+  }
+
+  fn write(&mut self, address: usize, bytes: &[u8]) -> bool {
+    for (i, byte) in bytes.iter().enumerate() {
+      let byte_address = match address.checked_add(i) {
+        Some(byte_address) => byte_address,
+        None => return false,
+      };
+      let word = match byte_address.checked_sub(ROM_BASE) {
+        Some(rom) => self.emulator.rom.get_mut(rom / 2),
+        None => self.emulator.ram.get_mut(byte_address / 2),
+      };
+      match word {
+        Some(word) if byte_address.is_multiple_of(2) => *word = (*word & 0xff00) | *byte as u16,
+        Some(word) => *word = (*word & 0x00ff) | (*byte as u16) << 8,
+        None => return false,
+      }
+    }
+    true
+  }
+}
+
+struct Connection {
+  stream: TcpStream,
+}
+
+impl Connection {
+  fn read_byte(&mut self) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match self.stream.read(&mut byte)? {
+      0 => Ok(None),
+      _ => Ok(Some(byte[0])),
+    }
+  }
+
+  // The next `$data#checksum` packet, acknowledging it, or `None` when gdb
+  // disconnects.
+  fn receive(&mut self) -> io::Result<Option<String>> {
+    loop {
+      match self.read_byte()? {
+        None => return Ok(None),
+        Some(b'$') => (),
+        // Acks, nacks and interrupts outside of `c`.
+        Some(_) => continue,
+      }
+      let mut data = Vec::new();
+      loop {
+        match self.read_byte()? {
+          None => return Ok(None),
+          Some(b'#') => break,
+          Some(byte) => data.push(byte),
+        }
+      }
+      let mut checksum = [0; 2];
+      self.stream.read_exact(&mut checksum)?;
+      let expected = std::str::from_utf8(&checksum)
+        .ok()
+        .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+      if expected != Some(sum(&data)) {
+        self.stream.write_all(b"-")?;
+        continue;
+      }
+      self.stream.write_all(b"+")?;
+      return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+    }
+  }
+
+  fn send(&mut self, data: &str) -> io::Result<()> {
+    let packet = format!("${}#{:02x}", data, sum(data.as_bytes()));
+    loop {
+      self.stream.write_all(packet.as_bytes())?;
+      match self.read_byte()? {
+        Some(b'-') => continue,
+        _ => return Ok(()),
+      }
+    }
+  }
+
+  // Whether gdb sent an interrupt (Ctrl-C) while the target was running.
+  fn interrupted(&mut self) -> io::Result<bool> {
+    self.stream.set_nonblocking(true)?;
+    let mut byte = [0];
+    let interrupted = match self.stream.read(&mut byte) {
+      Ok(1) => byte[0] == 0x03,
+      Ok(_) => false,
+      Err(err) if err.kind() == io::ErrorKind::WouldBlock => false,
+      Err(err) => return Err(err),
+    };
+    self.stream.set_nonblocking(false)?;
+    Ok(interrupted)
+  }
+}
+
+fn sum(data: &[u8]) -> u8 {
+  data.iter().fold(0, |sum: u8, byte| sum.wrapping_add(*byte))
+}
+
+// The ROM word at a code byte address.
+fn code_word(address: usize) -> Option<u16> {
+  let offset = address.checked_sub(ROM_BASE)?;
+  if offset.is_multiple_of(2) && offset / 2 < ROM_SIZE {
+    Some((offset / 2) as u16)
+  } else {
+    None
+  }
+}
+
+fn le_hex(value: u32, bytes: usize) -> String {
+  value.to_le_bytes()[..bytes]
+    .iter()
+    .map(|byte| format!("{:02x}", byte))
+    .collect()
+}
+
+fn parse_bytes(hex: &str) -> Option<Vec<u8>> {
+  if !hex.len().is_multiple_of(2) {
+    return None;
+  }
+  (0..hex.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+    .collect()
+}
+
+// A little endian value of `bytes` bytes.
+fn parse_le(hex: &str, bytes: usize) -> Option<u32> {
+  let bytes_read = parse_bytes(hex).filter(|read| read.len() == bytes)?;
+  Some(
+    bytes_read
+      .iter()
+      .rev()
+      .fold(0, |value, byte| value << 8 | *byte as u32),
+  )
+}
+
+// All registers, as `g` sends them.
+fn parse_registers(hex: &str) -> Option<[u32; 3]> {
+  let mut registers = [0; 3];
+  let mut rest = hex;
+  for (register, bytes) in registers.iter_mut().zip(REGISTER_BYTES) {
+    let (value, after) = rest.split_at_checked(bytes * 2)?;
+    *register = parse_le(value, bytes)?;
+    rest = after;
+  }
+  Some(registers).filter(|_| rest.is_empty())
+}
+
+// `address,length` in hex.
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+  let (address, len) = range.split_once(',')?;
+  Some((
+    usize::from_str_radix(address, 16).ok()?,
+    usize::from_str_radix(len, 16).ok()?,
+  ))
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use crate::emulator::Emulator;
+use crate::screen::{Screen, HEIGHT, WIDTH};
+
+// Palette index 0 is white and 1 black, like the pixels of the Hack screen.
+const PALETTE: [u8; 6] = [0xff, 0xff, 0xff, 0x00, 0x00, 0x00];
+// The smallest LZW code size GIF allows, enough for two colors.
+const MIN_CODE_SIZE: u8 = 2;
+const MAX_CODES: u16 = 4096;
+const MAX_DELAY: u16 = u16::MAX;
+
+// An animated, endlessly looping GIF of black and white frames. A frame that
+// repeats the previous one only extends how long that one is shown, and each
+// new frame only stores the rectangle that changed.
+#[derive(Debug)]
+pub struct GifEncoder {
+  width: usize,
+  height: usize,
+  bytes: Vec<u8>,
+  // The last frame added, not yet written while it may still be extended.
+  pending: Option<(Vec<bool>, u16)>,
+  // The image shown once everything written so far has played.
+  shown: Vec<bool>,
+  frames: usize,
+}
+
+impl GifEncoder {
+  pub fn new(width: usize, height: usize) -> Self {
+    let mut bytes = b"GIF89a".to_vec();
+    bytes.extend_from_slice(&(width as u16).to_le_bytes());
+    bytes.extend_from_slice(&(height as u16).to_le_bytes());
+    // Global color table of 2 entries, background color 0, square pixels.
+    bytes.extend_from_slice(&[0x80, 0, 0]);
+    bytes.extend_from_slice(&PALETTE);
+    // Netscape application extension: loop forever.
+    bytes.extend_from_slice(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+    GifEncoder {
+      width,
+      height,
+      bytes,
+      pending: None,
+      shown: vec![false; width * height],
+      frames: 0,
+    }
+  }
+
+  // Adds a frame shown for `delay` hundredths of a second; `pixels` holds
+  // `width` entries per row, true for black.
+  pub fn add_frame(&mut self, pixels: &[bool], delay: u16) {
+    match &mut self.pending {
+      Some((pending, pending_delay)) if pending == pixels && *pending_delay < MAX_DELAY => {
+        *pending_delay = pending_delay.saturating_add(delay);
+      }
+      _ => {
+        self.write_pending();
+        self.pending = Some((pixels.to_vec(), delay));
+      }
+    }
+  }
+
+  pub fn finish(mut self) -> Vec<u8> {
+    self.write_pending();
+    self.bytes.push(0x3b);
+    self.bytes
+  }
+
+  fn write_pending(&mut self) {
+    let (pixels, delay) = match self.pending.take() {
+      Some(pending) => pending,
+      None => return,
+    };
+    let (left, top, right, bottom) = self.changed(&pixels);
+    // Graphic control extension: keep the previous frame under this one.
+    self.bytes.extend_from_slice(&[0x21, 0xf9, 0x04, 0x04]);
+    self.bytes.extend_from_slice(&delay.to_le_bytes());
+    self.bytes.extend_from_slice(&[0, 0]);
+    // Image descriptor without a local color table.
+    self.bytes.push(0x2c);
+    for value in [left, top, right - left, bottom - top] {
+      self.bytes.extend_from_slice(&(value as u16).to_le_bytes());
+    }
+    self.bytes.push(0);
+    let indices: Vec<u8> = (top..bottom)
+      .flat_map(|y| pixels[y * self.width + left..y * self.width + right].iter())
+      .map(|black| *black as u8)
+      .collect();
+    self.bytes.push(MIN_CODE_SIZE);
+    for block in lzw(&indices).chunks(255) {
+      self.bytes.push(block.len() as u8);
+      self.bytes.extend_from_slice(block);
+    }
+    self.bytes.push(0);
+    self.shown = pixels;
+    self.frames += 1;
+  }
+
+  // Bounds of the pixels that differ from what's shown, as left, top, right
+  // and bottom, the last two exclusive. The first frame is always written
+  // whole, and a frame with no changes still needs a pixel to carry its delay.
+  fn changed(&self, pixels: &[bool]) -> (usize, usize, usize, usize) {
+    if self.frames == 0 {
+      return (0, 0, self.width, self.height);
+    }
+    let (mut left, mut top, mut right, mut bottom) = (self.width, self.height, 0, 0);
+    for (i, (new, old)) in pixels.iter().zip(&self.shown).enumerate() {
+      if new != old {
+        let (x, y) = (i % self.width, i / self.width);
+        left = left.min(x);
+        top = top.min(y);
+        right = right.max(x + 1);
+        bottom = bottom.max(y + 1);
+      }
+    }
+    if right == 0 {
+      (0, 0, 1, 1)
+    } else {
+      (left, top, right, bottom)
+    }
+  }
+}
+
+// Packs variable length codes least significant bit first.
+struct CodeWriter {
+  bytes: Vec<u8>,
+  bits: u32,
+  count: u32,
+}
+
+impl CodeWriter {
+  fn write(&mut self, code: u16, size: u8) {
+    self.bits |= (code as u32) << self.count;
+    self.count += size as u32;
+    while self.count >= 8 {
+      self.bytes.push(self.bits as u8);
+      self.bits >>= 8;
+      self.count -= 8;
+    }
+  }
+}
+
+// GIF flavored LZW: codes start one bit wider than the color indices, grow
+// as the table fills, and a clear code resets the table once it has 4096
+// entries.
+fn lzw(indices: &[u8]) -> Vec<u8> {
+  let clear = 1u16 << MIN_CODE_SIZE;
+  let end = clear + 1;
+  let mut writer = CodeWriter {
+    bytes: Vec::new(),
+    bits: 0,
+    count: 0,
+  };
+  let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+  let mut next = end + 1;
+  let mut size = MIN_CODE_SIZE + 1;
+  writer.write(clear, size);
+  let mut prefix = match indices.first() {
+    Some(index) => *index as u16,
+    None => {
+      writer.write(end, size);
+      return writer.bytes;
+    }
+  };
+  for index in &indices[1..] {
+    if let Some(code) = table.get(&(prefix, *index)) {
+      prefix = *code;
+      continue;
+    }
+    writer.write(prefix, size);
+    if next == MAX_CODES {
+      writer.write(clear, size);
+      table.clear();
+      next = end + 1;
+      size = MIN_CODE_SIZE + 1;
+    } else {
+      // The decoder learns each code one step later, so it widens its codes
+      // only once the code just assigned no longer fits.
+      if next == 1 << size {
+        size += 1;
+      }
+      table.insert((prefix, *index), next);
+      next += 1;
+    }
+    prefix = *index as u16;
+  }
+  writer.write(prefix, size);
+  // Reading that code makes the decoder add its last entry, which may widen
+  // the end code.
+  if next == 1 << size && next < MAX_CODES {
+    size += 1;
+  }
+  writer.write(end, size);
+  if writer.count > 0 {
+    writer.bytes.push(writer.bits as u8);
+  }
+  writer.bytes
+}
+
+// Records the Hack screen into a GIF every `every` cycles of an emulator run.
+#[derive(Debug)]
+pub struct ScreenRecorder {
+  encoder: GifEncoder,
+  every: u64,
+  delay: u16,
+  // The cycle of the next frame.
+  pub next: u64,
+  last: Option<u64>,
+}
+
+impl ScreenRecorder {
+  // Each frame is shown for `delay` hundredths of a second.
+  pub fn new(every: u64, delay: u16) -> Self {
+    ScreenRecorder {
+      encoder: GifEncoder::new(WIDTH, HEIGHT),
+      every: every.max(1),
+      delay,
+      next: 0,
+      last: None,
+    }
+  }
+
+  // Captures the screen if the emulator reached the next frame's cycle.
+  pub fn capture(&mut self, emulator: &Emulator) {
+    if emulator.cycles >= self.next {
+      let screen = Screen::new(&emulator.ram);
+      self.encoder.add_frame(screen.pixels(), self.delay);
+      self.next = (emulator.cycles / self.every + 1) * self.every;
+      self.last = Some(emulator.cycles);
+    }
+  }
+
+  // Adds the final screen, unless it was just captured, and returns the GIF
+  // with the number of frames written.
+  pub fn finish(mut self, emulator: &Emulator) -> (Vec<u8>, usize) {
+    if self.last != Some(emulator.cycles) {
+      let screen = Screen::new(&emulator.ram);
+      self.encoder.add_frame(screen.pixels(), self.delay);
+    }
+    self.encoder.write_pending();
+    let frames = self.encoder.frames;
+    (self.encoder.finish(), frames)
+  }
+}
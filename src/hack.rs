@@ -0,0 +1,197 @@
+// Binary encoding of Hack machine instructions.
+
+pub const ROM_SIZE: usize = 32768;
+
+// comp mnemonics with their 7 bit `a c1..c6` encoding. The first entry for a
+// given encoding is the canonical spelling used when decoding, the others are
+// commutative aliases accepted by the assembler (e.g. `A+D` emitted by
+// `MemoryAccess::to_asm`).
+const COMP: &[(&str, u16)] = &[
+  ("0", 0b0101010),
+  ("1", 0b0111111),
+  ("-1", 0b0111010),
+  ("D", 0b0001100),
+  ("A", 0b0110000),
+  ("!D", 0b0001101),
+  ("!A", 0b0110001),
+  ("-D", 0b0001111),
+  ("-A", 0b0110011),
+  ("D+1", 0b0011111),
+  ("A+1", 0b0110111),
+  ("D-1", 0b0001110),
+  ("A-1", 0b0110010),
+  ("D+A", 0b0000010),
+  ("A+D", 0b0000010),
+  ("D-A", 0b0010011),
+  ("A-D", 0b0000111),
+  ("D&A", 0b0000000),
+  ("A&D", 0b0000000),
+  ("D|A", 0b0010101),
+  ("A|D", 0b0010101),
+  ("M", 0b1110000),
+  ("!M", 0b1110001),
+  ("-M", 0b1110011),
+  ("M+1", 0b1110111),
+  ("M-1", 0b1110010),
+  ("D+M", 0b1000010),
+  ("M+D", 0b1000010),
+  ("D-M", 0b1010011),
+  ("M-D", 0b1000111),
+  ("D&M", 0b1000000),
+  ("M&D", 0b1000000),
+  ("D|M", 0b1010101),
+  ("M|D", 0b1010101),
+];
+
+// dest mnemonics as the course's assembler spells them, indexed by the `d1 d2
+// d3` (A D M) bits. Any other order of the same letters is accepted too.
+const DEST: &[&str] = &["", "M", "D", "MD", "A", "AM", "AD", "AMD"];
+const JUMP: &[&str] = &["", "JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+  A(u16),
+  C { dest: u16, comp: u16, jump: u16 },
+}
+
+impl Instruction {
+  pub fn encode(&self) -> u16 {
+    match self {
+      Instruction::A(value) => value & 0x7fff,
+      Instruction::C { dest, comp, jump } => 0b111 << 13 | comp << 6 | dest << 3 | jump,
+    }
+  }
+
+  // Like the CPU, ignores the two unused bits of C instructions, which other
+  // toolchains don't always set. Fails only for comps without a mnemonic,
+  // which the ALU still computes something for.
+  pub fn decode(word: u16) -> Result<Self, String> {
+    if word & 0x8000 == 0 {
+      return Ok(Instruction::A(word));
+    }
+    let comp = (word >> 6) & 0b1111111;
+    if comp_mnemonic(comp).is_none() {
+      return Err(format!("unknown comp {:07b}", comp));
+    }
+    Ok(Instruction::C {
+      dest: (word >> 3) & 0b111,
+      comp,
+      jump: word & 0b111,
+    })
+  }
+
+  pub fn parse_c(str: &str) -> Result<Self, String> {
+    let (dest, rest) = match str.find('=') {
+      Some(i) => (&str[..i], &str[i + 1..]),
+      None => ("", str),
+    };
+    let (comp, jump) = match rest.find(';') {
+      Some(i) => (&rest[..i], &rest[i + 1..]),
+      None => (rest, ""),
+    };
+    let dest = dest.chars().try_fold(0, |bits, c| match c {
+      'A' if bits & 0b100 == 0 => Ok(bits | 0b100),
+      'D' if bits & 0b010 == 0 => Ok(bits | 0b010),
+      'M' if bits & 0b001 == 0 => Ok(bits | 0b001),
+      _ => Err(format!("invalid dest {}", dest)),
+    })?;
+    let comp = COMP
+      .iter()
+      .find(|(mnemonic, _)| *mnemonic == comp)
+      .map(|(_, bits)| *bits)
+      .ok_or_else(|| format!("invalid comp {}", comp))?;
+    let jump = JUMP
+      .iter()
+      .position(|mnemonic| *mnemonic == jump)
+      .ok_or_else(|| format!("invalid jump {}", jump))? as u16;
+    Ok(Instruction::C { dest, comp, jump })
+  }
+
+  pub fn is_jump(&self) -> bool {
+    match self {
+      Instruction::C { jump, .. } => *jump != 0,
+      _ => false,
+    }
+  }
+
+  // True when the instruction reads or writes RAM[A].
+  pub fn uses_memory(&self) -> bool {
+    match self {
+      Instruction::C { dest, comp, .. } => comp & 0b1000000 != 0 || dest & 0b001 != 0,
+      _ => false,
+    }
+  }
+
+  pub fn to_asm(self) -> String {
+    match self {
+      Instruction::A(value) => format!("@{}", value),
+      Instruction::C { dest, comp, jump } => {
+        let mut asm = String::new();
+        if dest != 0 {
+          asm.push_str(DEST[dest as usize]);
+          asm.push('=');
+        }
+        asm.push_str(comp_mnemonic(comp).unwrap_or("?"));
+        if jump != 0 {
+          asm.push(';');
+          asm.push_str(JUMP[jump as usize]);
+        }
+        asm
+      }
+    }
+  }
+}
+
+fn comp_mnemonic(comp: u16) -> Option<&'static str> {
+  COMP
+    .iter()
+    .find(|(_, bits)| *bits == comp)
+    .map(|(mnemonic, _)| *mnemonic)
+}
+
+pub fn predefined_symbol(name: &str) -> Option<u16> {
+  match name {
+    "SP" => Some(0),
+    "LCL" => Some(1),
+    "ARG" => Some(2),
+    "THIS" => Some(3),
+    "THAT" => Some(4),
+    "SCREEN" => Some(16384),
+    "KBD" => Some(24576),
+    _ if name.starts_with('R') => match name[1..].parse::<u16>() {
+      Ok(n) if n < 16 && name[1..] == n.to_string() => Some(n),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+// Name used for a RAM address when disassembling memory references.
+pub fn register_name(address: u16) -> Option<String> {
+  match address {
+    0 => Some("SP".to_string()),
+    1 => Some("LCL".to_string()),
+    2 => Some("ARG".to_string()),
+    3 => Some("THIS".to_string()),
+    4 => Some("THAT".to_string()),
+    5..=15 => Some(format!("R{}", address)),
+    16384 => Some("SCREEN".to_string()),
+    24576 => Some("KBD".to_string()),
+    _ => None,
+  }
+}
+
+pub fn parse_hack(text: &str) -> Result<Vec<u16>, String> {
+  text
+    .lines()
+    .enumerate()
+    .filter(|(_, line)| !line.trim().is_empty())
+    .map(|(i, line)| {
+      let line = line.trim();
+      if line.len() != 16 {
+        return Err(format!("line {}: expected 16 bits, got {}", i + 1, line));
+      }
+      u16::from_str_radix(line, 2).map_err(|_| format!("line {}: invalid binary {}", i + 1, line))
+    })
+    .collect()
+}
@@ -0,0 +1,193 @@
+use std::collections::VecDeque;
+
+use crate::emulator::Emulator;
+
+pub const DEFAULT_DELTAS: usize = 1 << 20;
+const SNAPSHOT_INTERVAL: u64 = 50_000;
+const MAX_SNAPSHOTS: usize = 256;
+// The furthest `seek` runs forward past the present.
+pub const MAX_SEEK_CYCLES: u64 = 100_000_000;
+
+// What a single instruction overwrote, enough to undo it.
+#[derive(Debug, Clone, Copy)]
+struct Delta {
+  pc: u16,
+  a: u16,
+  d: u16,
+  write: Option<(u16, u16)>,
+}
+
+#[derive(Debug)]
+struct Snapshot {
+  cycle: u64,
+  pc: u16,
+  a: u16,
+  d: u16,
+  ram: Vec<u16>,
+}
+
+// Execution history of an emulator. The last `max_deltas` cycles can be
+// undone one at a time; older cycles are reached by restoring a RAM snapshot
+// and running forward again, which also records the deltas to undo further.
+// Snapshots are thinned out as they accumulate so memory stays bounded
+// however long the program runs.
+#[derive(Debug)]
+pub struct History {
+  deltas: VecDeque<Delta>,
+  max_deltas: usize,
+  snapshots: Vec<Snapshot>,
+  interval: u64,
+}
+
+impl History {
+  pub fn new(emulator: &Emulator, max_deltas: usize) -> Self {
+    History {
+      deltas: VecDeque::new(),
+      max_deltas,
+      snapshots: vec![snapshot(emulator)],
+      interval: SNAPSHOT_INTERVAL,
+    }
+  }
+
+  // Forgets the recorded history, starting over from the emulator's state.
+  pub fn restart(&mut self, emulator: &Emulator) {
+    *self = History::new(emulator, self.max_deltas);
+  }
+
+  // The earliest cycle that can be undone to without restoring a snapshot.
+  pub fn first_undoable(&self, emulator: &Emulator) -> u64 {
+    emulator.cycles - self.deltas.len() as u64
+  }
+
+  // The earliest cycle `undo` and `seek` can reach.
+  pub fn first_cycle(&self) -> u64 {
+    self.snapshots[0].cycle
+  }
+
+  pub fn step(&mut self, emulator: &mut Emulator) -> Result<(), String> {
+    let word = emulator.rom.get(emulator.pc as usize).cloned().unwrap_or(0);
+    let write = if word & 0x8008 == 0x8008 {
+      emulator
+        .ram
+        .get(emulator.a as usize)
+        .map(|old| (emulator.a, *old))
+    } else {
+      None
+    };
+    let delta = Delta {
+      pc: emulator.pc,
+      a: emulator.a,
+      d: emulator.d,
+      write,
+    };
+    emulator.step()?;
+    self.deltas.push_back(delta);
+    if self.deltas.len() > self.max_deltas {
+      self.deltas.pop_front();
+    }
+    if emulator.cycles - self.snapshots.last().unwrap().cycle >= self.interval {
+      self.snapshots.push(snapshot(emulator));
+      if self.snapshots.len() > MAX_SNAPSHOTS {
+        let mut i = 0;
+        self.snapshots.retain(|_| {
+          i += 1;
+          i % 2 == 1
+        });
+        self.interval *= 2;
+      }
+    }
+    Ok(())
+  }
+
+  // Undoes the last cycle, returning the RAM address it wrote, or `None` when
+  // there's nothing left to undo.
+  pub fn undo(&mut self, emulator: &mut Emulator) -> Option<Option<u16>> {
+    if self.deltas.is_empty() && emulator.cycles > self.first_cycle() {
+      self.replay(emulator).ok()?;
+    }
+    let delta = self.deltas.pop_back()?;
+    emulator.pc = delta.pc;
+    emulator.a = delta.a;
+    emulator.d = delta.d;
+    emulator.cycles -= 1;
+    if let Some((address, old)) = delta.write {
+      emulator.ram[address as usize] = old;
+    }
+    while self.snapshots.len() > 1 && self.snapshots.last().unwrap().cycle > emulator.cycles {
+      self.snapshots.pop();
+    }
+    Some(delta.write.map(|(address, _)| address))
+  }
+
+  // Moves the emulator to `cycle`, undoing, restoring a snapshot and replaying
+  // or simply running forward. Running forward past the present stops early
+  // when the program halts.
+  pub fn seek(&mut self, emulator: &mut Emulator, cycle: u64) -> Result<(), String> {
+    if cycle < self.first_cycle() {
+      return Err(format!(
+        "cycle {} is before the recorded history, which starts at {}",
+        cycle,
+        self.first_cycle()
+      ));
+    }
+    if cycle > emulator.cycles + MAX_SEEK_CYCLES {
+      return Err(format!(
+        "cycle {} is more than {} cycles ahead",
+        cycle, MAX_SEEK_CYCLES
+      ));
+    }
+    let present = emulator.cycles;
+    if cycle < self.first_undoable(emulator) {
+      self.restore(emulator, cycle);
+    }
+    while emulator.cycles > cycle {
+      self.undo(emulator);
+    }
+    while emulator.cycles < cycle {
+      if emulator.cycles >= present && emulator.is_halted() {
+        break;
+      }
+      self.step(emulator)?;
+    }
+    Ok(())
+  }
+
+  // Goes back to the last snapshot at or before `cycle`, forgetting what came
+  // after it.
+  fn restore(&mut self, emulator: &mut Emulator, cycle: u64) {
+    let i = self
+      .snapshots
+      .iter()
+      .rposition(|snapshot| snapshot.cycle <= cycle)
+      .unwrap();
+    self.snapshots.truncate(i + 1);
+    let snapshot = &self.snapshots[i];
+    emulator.pc = snapshot.pc;
+    emulator.a = snapshot.a;
+    emulator.d = snapshot.d;
+    emulator.ram.copy_from_slice(&snapshot.ram);
+    emulator.cycles = snapshot.cycle;
+    self.deltas.clear();
+  }
+
+  // Runs again from the snapshot before the current cycle, recording the
+  // deltas of the cycles since.
+  fn replay(&mut self, emulator: &mut Emulator) -> Result<(), String> {
+    let cycle = emulator.cycles;
+    self.restore(emulator, cycle - 1);
+    while emulator.cycles < cycle {
+      self.step(emulator)?;
+    }
+    Ok(())
+  }
+}
+
+fn snapshot(emulator: &Emulator) -> Snapshot {
+  Snapshot {
+    cycle: emulator.cycles,
+    pc: emulator.pc,
+    a: emulator.a,
+    d: emulator.d,
+    ram: emulator.ram.clone(),
+  }
+}
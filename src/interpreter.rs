@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+
+use crate::arithmetic::Arithmetic;
+use crate::branching::Branching;
+use crate::function::Function;
+use crate::memory_access::{AccessCommand, MemoryAccess, Segment};
+use crate::{Command, SourceCommand, VmParser};
+
+pub const RAM_SIZE: usize = 32768;
+pub const SP: usize = 0;
+pub const LCL: usize = 1;
+pub const ARG: usize = 2;
+pub const THIS: usize = 3;
+pub const THAT: usize = 4;
+
+// Return address saved by the bootstrap call to `Sys.init`.
+const HALT: u16 = 0xffff;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+  Running,
+  Halted,
+  StepLimit,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+  Arithmetic(Arithmetic),
+  Push(Segment, u16),
+  Pop(Segment, u16),
+  Label,
+  Goto(usize),
+  IfGoto(usize),
+  Function(usize),
+  Call(usize, usize),
+  Return,
+}
+
+// Executes VM commands directly on a Hack-shaped RAM: the pointers live in
+// RAM[0..5], temp in RAM[5..13], statics from RAM[16] in the order the
+// assembler would allocate them and the stack from RAM[256]. Return addresses
+// pushed by `call` are command indexes rather than ROM addresses.
+#[derive(Debug)]
+pub struct Interpreter {
+  pub ram: Vec<u16>,
+  pub commands: Vec<SourceCommand>,
+  pub pc: usize,
+  pub steps: usize,
+  ops: Vec<Op>,
+  labels: HashMap<String, usize>,
+  statics: HashMap<(String, i32), u16>,
+}
+
+impl Interpreter {
+  pub fn new(parsers: Vec<VmParser>) -> Result<Self, String> {
+    let commands: Vec<SourceCommand> = parsers
+      .into_iter()
+      .flatten()
+      .filter(|source| !matches!(source.command, Command::Noop))
+      .collect();
+    let mut labels = HashMap::new();
+    let mut statics = HashMap::new();
+    for (i, source) in commands.iter().enumerate() {
+      let label = match &source.command {
+        Command::Function(Function::Decl { name, .. }) => name,
+        Command::Branching(Branching::Label(label)) => label,
+        Command::MemoryAccess(MemoryAccess {
+          segment: Segment::Static,
+          index,
+          filename,
+          ..
+        }) => {
+          let next = 16 + statics.len() as u16;
+          statics.entry((filename.clone(), *index)).or_insert(next);
+          continue;
+        }
+        _ => continue,
+      };
+      if labels.insert(label.clone(), i).is_some() {
+        return Err(format!(
+          "{}:{}: duplicate label {}",
+          source.file, source.line, label
+        ));
+      }
+    }
+    let ops = commands
+      .iter()
+      .map(|source| {
+        compile(&source.command, &labels, &statics)
+          .map_err(|err| format!("{}:{}: {}: {}", source.file, source.line, source.text, err))
+      })
+      .collect::<Result<Vec<Op>, String>>()?;
+    Ok(Interpreter {
+      ram: vec![0; RAM_SIZE],
+      commands,
+      pc: 0,
+      steps: 0,
+      ops,
+      labels,
+      statics,
+    })
+  }
+
+  // Mirrors the code emitted by `AsmWriter`: SP = 256, then call Sys.init.
+  pub fn bootstrap(&mut self) -> Result<(), String> {
+    self.ram[SP] = 256;
+    let sys_init = *self
+      .labels
+      .get("Sys.init")
+      .ok_or("Sys.init is not defined")?;
+    self.call(sys_init, 0, HALT)
+  }
+
+  pub fn static_address(&self, filename: &str, index: i32) -> Option<u16> {
+    self.statics.get(&(filename.to_string(), index)).cloned()
+  }
+
+  pub fn statics(&self) -> impl Iterator<Item = (&(String, i32), &u16)> {
+    self.statics.iter()
+  }
+
+  pub fn current(&self) -> Option<&SourceCommand> {
+    self.commands.get(self.pc)
+  }
+
+  pub fn run(&mut self, max_steps: usize) -> Result<Status, String> {
+    for _ in 0..max_steps {
+      if let Status::Halted = self.step()? {
+        return Ok(Status::Halted);
+      }
+    }
+    Ok(Status::StepLimit)
+  }
+
+  pub fn step(&mut self) -> Result<Status, String> {
+    let op = match self.ops.get(self.pc) {
+      Some(op) => *op,
+      None => return Ok(Status::Halted),
+    };
+    let next = self.execute(op).map_err(|err| {
+      let source = &self.commands[self.pc];
+      format!("{}:{}: {}: {}", source.file, source.line, source.text, err)
+    })?;
+    self.steps += 1;
+    match next {
+      Some(next) if next < self.ops.len() => {
+        self.pc = next;
+        Ok(Status::Running)
+      }
+      Some(next) => {
+        self.pc = next;
+        Ok(Status::Halted)
+      }
+      None => Ok(Status::Halted),
+    }
+  }
+
+  // Runs one command, returning the next command index or `None` when the
+  // program halts.
+  fn execute(&mut self, op: Op) -> Result<Option<usize>, String> {
+    let next = self.pc + 1;
+    match op {
+      Op::Arithmetic(arithmetic) => self.arithmetic(arithmetic)?,
+      Op::Push(segment, index) => {
+        let value = match self.address(segment, index) {
+          Some(address) => self.read(address)?,
+          None => index,
+        };
+        self.push(value)?;
+      }
+      Op::Pop(segment, index) => {
+        let address = self.address(segment, index).ok_or("cannot pop constant")?;
+        let value = self.pop()?;
+        self.write(address, value)?;
+      }
+      Op::Label => (),
+      // `label L; goto L` is the conventional end of a program.
+      Op::Goto(target) if target + 1 == self.pc => return Ok(None),
+      Op::Goto(target) => return Ok(Some(target)),
+      Op::IfGoto(target) => {
+        if self.pop()? != 0 {
+          return Ok(Some(target));
+        }
+      }
+      Op::Function(nlocals) => {
+        for _ in 0..nlocals {
+          self.push(0)?;
+        }
+      }
+      Op::Call(target, nargs) => {
+        self.call(target, nargs, next as u16)?;
+        return Ok(Some(target));
+      }
+      Op::Return => return self.ret(),
+    }
+    Ok(Some(next))
+  }
+
+  fn call(&mut self, target: usize, nargs: usize, return_address: u16) -> Result<(), String> {
+    self.push(return_address)?;
+    for pointer in &[LCL, ARG, THIS, THAT] {
+      self.push(self.ram[*pointer])?;
+    }
+    let sp = self.ram[SP];
+    self.ram[ARG] = sp.wrapping_sub(5 + nargs as u16);
+    self.ram[LCL] = sp;
+    self.pc = target;
+    Ok(())
+  }
+
+  fn ret(&mut self) -> Result<Option<usize>, String> {
+    let frame = self.ram[LCL];
+    let return_address = self.read(frame.wrapping_sub(5))?;
+    let value = self.pop()?;
+    self.write(self.ram[ARG], value)?;
+    self.ram[SP] = self.ram[ARG].wrapping_add(1);
+    for (i, pointer) in [THAT, THIS, ARG, LCL].iter().enumerate() {
+      self.ram[*pointer] = self.read(frame.wrapping_sub(i as u16 + 1))?;
+    }
+    if return_address == HALT {
+      return Ok(None);
+    }
+    Ok(Some(return_address as usize))
+  }
+
+  fn arithmetic(&mut self, arithmetic: Arithmetic) -> Result<(), String> {
+    let result = match arithmetic {
+      Arithmetic::Neg => self.pop()?.wrapping_neg(),
+      Arithmetic::Not => !self.pop()?,
+      _ => {
+        let y = self.pop()?;
+        let x = self.pop()?;
+        match arithmetic {
+          Arithmetic::Add => x.wrapping_add(y),
+          Arithmetic::Sub => x.wrapping_sub(y),
+          Arithmetic::And => x & y,
+          Arithmetic::Or => x | y,
+          Arithmetic::Eq(_) => truth(x == y),
+          Arithmetic::Lt(_) => truth((x as i16) < (y as i16)),
+          Arithmetic::Gt(_) => truth((x as i16) > (y as i16)),
+          Arithmetic::Neg | Arithmetic::Not => unreachable!(),
+        }
+      }
+    };
+    self.push(result)
+  }
+
+  // RAM address of a segment entry, `None` for the constant segment. Static
+  // indexes are resolved to addresses when compiling.
+  fn address(&self, segment: Segment, index: u16) -> Option<u16> {
+    let base = match segment {
+      Segment::Constant => return None,
+      Segment::Static => return Some(index),
+      Segment::Temp => 5,
+      Segment::Pointer => 3,
+      Segment::Local => self.ram[LCL],
+      Segment::Argument => self.ram[ARG],
+      Segment::This => self.ram[THIS],
+      Segment::That => self.ram[THAT],
+    };
+    Some(base.wrapping_add(index))
+  }
+
+  fn push(&mut self, value: u16) -> Result<(), String> {
+    let sp = self.ram[SP];
+    self.write(sp, value)?;
+    self.ram[SP] = sp.wrapping_add(1);
+    Ok(())
+  }
+
+  fn pop(&mut self) -> Result<u16, String> {
+    let sp = self.ram[SP].wrapping_sub(1);
+    self.ram[SP] = sp;
+    self.read(sp)
+  }
+
+  fn read(&self, address: u16) -> Result<u16, String> {
+    self
+      .ram
+      .get(address as usize)
+      .cloned()
+      .ok_or_else(|| format!("read outside RAM at {}", address))
+  }
+
+  fn write(&mut self, address: u16, value: u16) -> Result<(), String> {
+    match self.ram.get_mut(address as usize) {
+      Some(word) => {
+        *word = value;
+        Ok(())
+      }
+      None => Err(format!("write outside RAM at {}", address)),
+    }
+  }
+}
+
+fn compile(
+  command: &Command,
+  labels: &HashMap<String, usize>,
+  statics: &HashMap<(String, i32), u16>,
+) -> Result<Op, String> {
+  let label = |label: &str| {
+    labels
+      .get(label)
+      .cloned()
+      .ok_or_else(|| format!("undefined label {}", label))
+  };
+  Ok(match command {
+    Command::Arithmetic(arithmetic) => Op::Arithmetic(*arithmetic),
+    Command::MemoryAccess(MemoryAccess {
+      command,
+      segment,
+      index,
+      filename,
+      ..
+    }) => {
+      let index = match segment {
+        _ if *index < 0 => return Err(format!("negative index {}", index)),
+        Segment::Constant if *index > 0x7fff => {
+          return Err(format!("constant {} out of range", index))
+        }
+        Segment::Constant if *command == AccessCommand::Pop => {
+          return Err("cannot pop constant".to_string())
+        }
+        Segment::Temp if *index > 7 => return Err(format!("temp {} out of range", index)),
+        Segment::Pointer if *index > 1 => return Err(format!("pointer {} out of range", index)),
+        Segment::Static => statics[&(filename.clone(), *index)],
+        _ => *index as u16,
+      };
+      match command {
+        AccessCommand::Push => Op::Push(*segment, index),
+        AccessCommand::Pop => Op::Pop(*segment, index),
+      }
+    }
+    Command::Branching(Branching::Label(_)) | Command::Noop => Op::Label,
+    Command::Branching(Branching::Goto(target)) => Op::Goto(label(target)?),
+    Command::Branching(Branching::IfGoto(target)) => Op::IfGoto(label(target)?),
+    Command::Function(Function::Decl { nlocals, .. }) => Op::Function(*nlocals),
+    Command::Function(Function::Call { name, nargs, .. }) => Op::Call(label(name)?, *nargs),
+    Command::Function(Function::Return) => Op::Return,
+  })
+}
+
+fn truth(bool: bool) -> u16 {
+  if bool {
+    0xffff
+  } else {
+    0
+  }
+}
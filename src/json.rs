@@ -0,0 +1,350 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(i64),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>),
+}
+
+impl Json {
+  pub fn object(fields: Vec<(&str, Json)>) -> Self {
+    Json::Object(
+      fields
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect(),
+    )
+  }
+
+  // The value of `key` when this is an object.
+  pub fn get(&self, key: &str) -> Option<&Json> {
+    match self {
+      Json::Object(fields) => fields
+        .iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Json::String(str) => Some(str),
+      _ => None,
+    }
+  }
+
+  pub fn as_i64(&self) -> Option<i64> {
+    match self {
+      Json::Number(number) => Some(*number),
+      _ => None,
+    }
+  }
+
+  pub fn as_bool(&self) -> Option<bool> {
+    match self {
+      Json::Bool(bool) => Some(*bool),
+      _ => None,
+    }
+  }
+
+  pub fn as_array(&self) -> Option<&[Json]> {
+    match self {
+      Json::Array(values) => Some(values),
+      _ => None,
+    }
+  }
+}
+
+impl From<bool> for Json {
+  fn from(bool: bool) -> Self {
+    Json::Bool(bool)
+  }
+}
+
+impl From<i64> for Json {
+  fn from(number: i64) -> Self {
+    Json::Number(number)
+  }
+}
+
+impl From<&str> for Json {
+  fn from(str: &str) -> Self {
+    Json::String(str.to_string())
+  }
+}
+
+impl From<String> for Json {
+  fn from(str: String) -> Self {
+    Json::String(str)
+  }
+}
+
+impl From<usize> for Json {
+  fn from(number: usize) -> Self {
+    Json::Number(number as i64)
+  }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+  fn from(option: Option<T>) -> Self {
+    option.map_or(Json::Null, Into::into)
+  }
+}
+
+fn write_string(f: &mut fmt::Formatter, str: &str) -> fmt::Result {
+  write!(f, "\"")?;
+  for c in str.chars() {
+    match c {
+      '"' => write!(f, "\\\"")?,
+      '\\' => write!(f, "\\\\")?,
+      '\n' => write!(f, "\\n")?,
+      '\r' => write!(f, "\\r")?,
+      '\t' => write!(f, "\\t")?,
+      c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+      c => write!(f, "{}", c)?,
+    }
+  }
+  write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Json::Null => write!(f, "null"),
+      Json::Bool(bool) => write!(f, "{}", bool),
+      Json::Number(number) => write!(f, "{}", number),
+      Json::String(str) => write_string(f, str),
+      Json::Array(values) => {
+        write!(f, "[")?;
+        for (i, value) in values.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write!(f, "{}", value)?;
+        }
+        write!(f, "]")
+      }
+      Json::Object(fields) => {
+        write!(f, "{{")?;
+        for (i, (key, value)) in fields.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write_string(f, key)?;
+          write!(f, ":{}", value)?;
+        }
+        write!(f, "}}")
+      }
+    }
+  }
+}
+
+impl FromStr for Json {
+  type Err = String;
+  fn from_str(str: &str) -> Result<Self, Self::Err> {
+    let mut parser = Parser {
+      chars: str.chars().collect(),
+      pos: 0,
+      depth: 0,
+    };
+    let value = parser.value()?;
+    parser.whitespace();
+    match parser.peek() {
+      None => Ok(value),
+      Some(c) => Err(parser.error(&format!("unexpected {:?} after value", c))),
+    }
+  }
+}
+
+// Arrays and objects nested deeper than this are rejected rather than
+// recursed into.
+pub const MAX_DEPTH: usize = 64;
+
+// Numbers are integers: a fraction or exponent is only accepted when the
+// value is a whole number, like 1.0 or 1e5.
+struct Parser {
+  chars: Vec<char>,
+  pos: usize,
+  // Arrays and objects the parser is inside of.
+  depth: usize,
+}
+
+impl Parser {
+  fn error(&self, message: &str) -> String {
+    format!("{} at offset {}", message, self.pos)
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.chars.get(self.pos).cloned()
+  }
+
+  fn next(&mut self) -> Result<char, String> {
+    let c = self
+      .peek()
+      .ok_or_else(|| self.error("unexpected end of input"))?;
+    self.pos += 1;
+    Ok(c)
+  }
+
+  fn whitespace(&mut self) {
+    while self.peek().is_some_and(char::is_whitespace) {
+      self.pos += 1;
+    }
+  }
+
+  fn expect(&mut self, expected: char) -> Result<(), String> {
+    match self.next()? {
+      c if c == expected => Ok(()),
+      c => Err(self.error(&format!("expected {:?}, found {:?}", expected, c))),
+    }
+  }
+
+  fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+    for expected in keyword.chars() {
+      self.expect(expected)?;
+    }
+    Ok(value)
+  }
+
+  fn value(&mut self) -> Result<Json, String> {
+    self.whitespace();
+    match self.peek() {
+      Some('n') => self.keyword("null", Json::Null),
+      Some('t') => self.keyword("true", Json::Bool(true)),
+      Some('f') => self.keyword("false", Json::Bool(false)),
+      Some('"') => Ok(Json::String(self.string()?)),
+      Some('[' | '{') if self.depth == MAX_DEPTH => {
+        Err(self.error(&format!("nesting deeper than {} levels", MAX_DEPTH)))
+      }
+      Some('[') => {
+        self.depth += 1;
+        let array = self.array();
+        self.depth -= 1;
+        array
+      }
+      Some('{') => {
+        self.depth += 1;
+        let object = self.object();
+        self.depth -= 1;
+        object
+      }
+      Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+      Some(c) => Err(self.error(&format!("unexpected {:?}", c))),
+      None => Err(self.error("unexpected end of input")),
+    }
+  }
+
+  fn array(&mut self) -> Result<Json, String> {
+    self.pos += 1;
+    let mut values = Vec::new();
+    self.whitespace();
+    if self.peek() == Some(']') {
+      self.pos += 1;
+      return Ok(Json::Array(values));
+    }
+    loop {
+      values.push(self.value()?);
+      self.whitespace();
+      match self.next()? {
+        ',' => continue,
+        ']' => return Ok(Json::Array(values)),
+        c => return Err(self.error(&format!("expected ',' or ']', found {:?}", c))),
+      }
+    }
+  }
+
+  fn object(&mut self) -> Result<Json, String> {
+    self.pos += 1;
+    let mut fields = Vec::new();
+    self.whitespace();
+    if self.peek() == Some('}') {
+      self.pos += 1;
+      return Ok(Json::Object(fields));
+    }
+    loop {
+      self.whitespace();
+      let key = self.string()?;
+      self.whitespace();
+      self.expect(':')?;
+      fields.push((key, self.value()?));
+      self.whitespace();
+      match self.next()? {
+        ',' => continue,
+        '}' => return Ok(Json::Object(fields)),
+        c => return Err(self.error(&format!("expected ',' or '}}', found {:?}", c))),
+      }
+    }
+  }
+
+  fn number(&mut self) -> Result<Json, String> {
+    let start = self.pos;
+    if self.peek() == Some('-') {
+      self.pos += 1;
+    }
+    while self
+      .peek()
+      .is_some_and(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'))
+    {
+      self.pos += 1;
+    }
+    let number: String = self.chars[start..self.pos].iter().collect();
+    if let Ok(integer) = number.parse() {
+      return Ok(Json::Number(integer));
+    }
+    match number.parse::<f64>() {
+      Ok(value) if value.fract() == 0.0 && value.abs() < i64::MAX as f64 => {
+        Ok(Json::Number(value as i64))
+      }
+      Ok(_) => Err(self.error(&format!("{} is not an integer", number))),
+      Err(_) => Err(self.error(&format!("invalid number {}", number))),
+    }
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    self.expect('"')?;
+    let mut string = String::new();
+    loop {
+      match self.next()? {
+        '"' => return Ok(string),
+        '\\' => match self.next()? {
+          'n' => string.push('\n'),
+          'r' => string.push('\r'),
+          't' => string.push('\t'),
+          'b' => string.push('\u{8}'),
+          'f' => string.push('\u{c}'),
+          'u' => {
+            let mut code = self.hex4()?;
+            // A surrogate pair encodes a character outside the BMP.
+            if (0xd800..0xdc00).contains(&code) {
+              self.expect('\\')?;
+              self.expect('u')?;
+              let low = self.hex4()?;
+              code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+            }
+            string.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+          }
+          c => string.push(c),
+        },
+        c => string.push(c),
+      }
+    }
+  }
+
+  fn hex4(&mut self) -> Result<u32, String> {
+    let mut code = 0;
+    for _ in 0..4 {
+      let c = self.next()?;
+      let digit = c
+        .to_digit(16)
+        .ok_or_else(|| self.error(&format!("invalid escape digit {:?}", c)))?;
+      code = code * 16 + digit;
+    }
+    Ok(code)
+  }
+}
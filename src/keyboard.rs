@@ -0,0 +1,195 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::emulator::{Emulator, Status, KBD};
+
+// Cycles each character of quoted text is held down, and then released, when
+// the script doesn't say.
+pub const DEFAULT_HOLD: u64 = 10_000;
+
+// Key codes of the Hack keyboard that aren't printable characters.
+const NAMED_KEYS: [(&str, u16); 16] = [
+  ("release", 0),
+  ("space", 32),
+  ("newline", 128),
+  ("enter", 128),
+  ("backspace", 129),
+  ("left", 130),
+  ("up", 131),
+  ("right", 132),
+  ("down", 133),
+  ("home", 134),
+  ("end", 135),
+  ("pageup", 136),
+  ("pagedown", 137),
+  ("insert", 138),
+  ("delete", 139),
+  ("esc", 140),
+];
+const F1: u16 = 141;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+  pub cycle: u64,
+  pub code: u16,
+}
+
+// Key presses replayed into the KBD register. Each line of a script is a
+// cycle count and a key, which stays pressed until the next event:
+//
+//   # cycle  key
+//   1000     a
+//   3000     release
+//   5000     enter
+//   8000     "hello" 2000
+//
+// A key is a single character, a name like `left` or `f1`, or quoted text
+// whose characters are each pressed and then released for the given number
+// of cycles.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyboardScript {
+  pub events: Vec<KeyEvent>,
+  next: usize,
+}
+
+pub fn key_code(key: &str) -> Result<u16, String> {
+  let mut chars = key.chars();
+  if let (Some(c), None) = (chars.next(), chars.next()) {
+    return if (' '..='~').contains(&c) {
+      Ok(c as u16)
+    } else {
+      Err(format!("{:?} is not on the Hack keyboard", c))
+    };
+  }
+  let name = key.to_lowercase();
+  if let Some((_, code)) = NAMED_KEYS.iter().find(|(key, _)| *key == name) {
+    return Ok(*code);
+  }
+  match name.strip_prefix('f').and_then(|n| n.parse::<u16>().ok()) {
+    Some(n @ 1..=12) => Ok(F1 + n - 1),
+    _ => Err(format!("unknown key {}", key)),
+  }
+}
+
+impl FromStr for KeyboardScript {
+  type Err = String;
+  fn from_str(str: &str) -> Result<Self, Self::Err> {
+    let mut events: Vec<KeyEvent> = Vec::new();
+    for (i, line) in str.lines().enumerate() {
+      let error = |message: String| format!("line {}: {}", i + 1, message);
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let (cycle, key) = line
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| error("expected a cycle and a key".to_string()))?;
+      let cycle: u64 = cycle
+        .parse()
+        .map_err(|_| error(format!("invalid cycle {}", cycle)))?;
+      let key = key.trim();
+      let start = events.len();
+      if let Some(quoted) = key.strip_prefix('"').filter(|_| key.len() > 1) {
+        let (text, hold) = quoted
+          .rsplit_once('"')
+          .ok_or_else(|| error("unterminated text".to_string()))?;
+        let hold = match hold.trim() {
+          "" => DEFAULT_HOLD,
+          hold => hold
+            .parse()
+            .map_err(|_| error(format!("invalid hold time {}", hold)))?,
+        };
+        for (j, c) in text.chars().enumerate() {
+          let cycle = cycle + 2 * j as u64 * hold;
+          let code = key_code(&c.to_string()).map_err(error)?;
+          events.push(KeyEvent { cycle, code });
+          events.push(KeyEvent {
+            cycle: cycle + hold,
+            code: 0,
+          });
+        }
+      } else {
+        let code = key_code(key).map_err(error)?;
+        events.push(KeyEvent { cycle, code });
+      }
+      if start > 0
+        && events
+          .get(start)
+          .is_some_and(|event| event.cycle < events[start - 1].cycle)
+      {
+        return Err(error(format!(
+          "cycle {} comes before the previous event at cycle {}",
+          cycle,
+          events[start - 1].cycle
+        )));
+      }
+    }
+    Ok(KeyboardScript { events, next: 0 })
+  }
+}
+
+impl KeyboardScript {
+  pub fn load(path: &Path) -> Result<Self, String> {
+    let script = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    script
+      .parse()
+      .map_err(|err| format!("{}: {}", path.display(), err))
+  }
+
+  // The number of events applied so far.
+  pub fn position(&self) -> usize {
+    self.next
+  }
+
+  // Continues from the event at `position`, as when a run is restored.
+  pub fn seek(&mut self, position: usize) -> Result<(), String> {
+    if position > self.events.len() {
+      return Err(format!(
+        "keyboard script has {} events, cannot continue from event {}",
+        self.events.len(),
+        position
+      ));
+    }
+    self.next = position;
+    Ok(())
+  }
+
+  // Sets KBD to the last key pressed at or before the emulator's cycle.
+  pub fn apply(&mut self, emulator: &mut Emulator) {
+    while let Some(event) = self.events.get(self.next) {
+      if event.cycle > emulator.cycles {
+        break;
+      }
+      emulator.ram[KBD] = event.code;
+      self.next += 1;
+    }
+  }
+
+  // Runs the emulator up to `cycle`, stopping at every key event on the way.
+  pub fn run_until(&mut self, emulator: &mut Emulator, cycle: u64) -> Result<Status, String> {
+    if let Some(loops) = &mut emulator.loops {
+      // Waiting for a key that's still to come isn't being stuck.
+      loops.input_until = self.events.last().map_or(0, |event| event.cycle);
+    }
+    loop {
+      self.apply(emulator);
+      let until = match self.events.get(self.next) {
+        Some(event) => event.cycle.min(cycle),
+        None => cycle,
+      };
+      if until <= emulator.cycles {
+        return Ok(if emulator.is_halted() {
+          Status::Halted
+        } else {
+          Status::CycleLimit
+        });
+      }
+      match emulator.run(until - emulator.cycles)? {
+        Status::Halted => return Ok(Status::Halted),
+        _ if until == cycle => return Ok(Status::CycleLimit),
+        _ => (),
+      }
+    }
+  }
+}
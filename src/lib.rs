@@ -0,0 +1,415 @@
+use std::fs;
+use std::io::{Error, Write};
+use std::iter::Iterator;
+use std::path::{Path, PathBuf};
+use std::result::Result;
+
+pub mod arithmetic;
+pub mod assembler;
+pub mod branching;
+pub mod checks;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod disassembler;
+pub mod emulator;
+pub mod function;
+pub mod gdb;
+pub mod gif;
+pub mod hack;
+pub mod history;
+pub mod interpreter;
+pub mod json;
+pub mod keyboard;
+pub mod listing;
+pub mod loops;
+pub mod memory_access;
+pub mod png;
+pub mod profiler;
+pub mod rom_format;
+pub mod screen;
+pub mod size_report;
+pub mod snapshot;
+pub mod source_map;
+pub mod terminal;
+pub mod test_script;
+pub mod traps;
+pub mod verify;
+pub mod watch;
+
+use arithmetic::Arithmetic;
+use assembler::Program;
+use branching::Branching;
+use checks::Checks;
+use emulator::Emulator;
+use function::Function;
+use memory_access::MemoryAccess;
+use source_map::SourceMap;
+
+#[derive(Debug)]
+pub enum Command {
+  Arithmetic(Arithmetic),
+  MemoryAccess(MemoryAccess),
+  Branching(Branching),
+  Function(Function),
+  Noop,
+}
+
+impl Command {
+  pub fn to_asm(&self) -> String {
+    match self {
+      Self::Arithmetic(command) => command.to_asm(),
+      Self::Branching(command) => command.to_asm(),
+      Self::MemoryAccess(command) => command.to_asm(),
+      Self::Function(command) => command.to_asm(),
+      Self::Noop => "".to_string(),
+    }
+  }
+
+  pub fn kind(&self) -> String {
+    match self {
+      Self::Arithmetic(_) => "arithmetic".to_string(),
+      Self::Branching(_) => "branching".to_string(),
+      Self::MemoryAccess(command) => {
+        format!("{:?} {:?}", command.command, command.segment).to_lowercase()
+      }
+      Self::Function(Function::Decl { .. }) => "function".to_string(),
+      Self::Function(Function::Call { .. }) => "call".to_string(),
+      Self::Function(Function::Return) => "return".to_string(),
+      Self::Noop => "noop".to_string(),
+    }
+  }
+
+  fn arithmetic(command: &str, i: usize) -> Result<Self, String> {
+    match command {
+      "add" => Ok(Self::Arithmetic(Arithmetic::Add)),
+      "sub" => Ok(Self::Arithmetic(Arithmetic::Sub)),
+      "neg" => Ok(Self::Arithmetic(Arithmetic::Neg)),
+      "and" => Ok(Self::Arithmetic(Arithmetic::And)),
+      "or" => Ok(Self::Arithmetic(Arithmetic::Or)),
+      "not" => Ok(Self::Arithmetic(Arithmetic::Not)),
+      "eq" => Ok(Self::Arithmetic(Arithmetic::Eq(i))),
+      "lt" => Ok(Self::Arithmetic(Arithmetic::Lt(i))),
+      "gt" => Ok(Self::Arithmetic(Arithmetic::Gt(i))),
+      _ => Err(format!("unknown arithmetic command {}", command)),
+    }
+  }
+
+  fn branching(command: &str, label: &str) -> Result<Self, String> {
+    match command {
+      "goto" => Ok(Self::Branching(Branching::Goto(label.to_string()))),
+      "if-goto" => Ok(Self::Branching(Branching::IfGoto(label.to_string()))),
+      "label" => Ok(Self::Branching(Branching::Label(label.to_string()))),
+      _ => Err(format!("unkown branching command {}", command)),
+    }
+  }
+
+  fn memory_access(command: &str, segment: &str, id: &str, filename: &str) -> Result<Self, String> {
+    Ok(Self::MemoryAccess(MemoryAccess {
+      command: command.parse()?,
+      segment: segment.parse()?,
+      index: id.parse().map_err(|_| format!("Error parsing {}", id))?,
+      original: format!("{} {} {}", command, segment, id),
+      filename: filename.to_string(),
+    }))
+  }
+
+  fn fn_decl(name: &str, nlocals: &str) -> Result<Self, String> {
+    Ok(Self::Function(Function::Decl {
+      name: name.to_string(),
+      nlocals: nlocals
+        .parse()
+        .map_err(|_| format!("Error parsing {}", nlocals))?,
+    }))
+  }
+
+  fn fn_call(name: &str, nargs: &str, index: usize) -> Result<Self, String> {
+    Ok(Self::Function(Function::Call {
+      name: name.to_string(),
+      nargs: nargs
+        .parse()
+        .map_err(|_| format!("Error parsing {}", nargs))?,
+      index,
+    }))
+  }
+
+  pub fn parse_from_str((i, str): &(usize, String), filename: &str) -> Result<Self, String> {
+    match str
+      .split("//")
+      .nth(0)
+      .unwrap()
+      .split_whitespace()
+      .collect::<Vec<&str>>()
+      .as_slice()
+    {
+      [] => Ok(Command::Noop),
+      command if command[0] == "//" => Ok(Command::Noop),
+      ["function", name, nlocals] => Ok(Command::fn_decl(name, nlocals)?),
+      ["call", name, nargs] => Ok(Command::fn_call(name, nargs, *i)?),
+      ["return"] => Ok(Command::Function(Function::Return)),
+      [command, memory, id] => Ok(Command::memory_access(command, memory, id, filename)?),
+      [command, label] => Ok(Command::branching(command, label)?),
+      [command] => Ok(Command::arithmetic(command, *i)?),
+      rest => {
+        println!("{:?}", rest);
+        Err(format!("couldn't parse command {}", str))
+      }
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct SourceCommand {
+  pub file: String,
+  pub line: usize,
+  pub text: String,
+  pub command: Command,
+}
+
+#[derive(Debug, Clone)]
+pub struct VmParser {
+  vm_file: Vec<(usize, String)>,
+  pub filename: String,
+}
+
+impl VmParser {
+  pub fn new(path: &std::path::PathBuf) -> Result<Self, Error> {
+    let vm_string = fs::read_to_string(path)?;
+    Ok(VmParser::from_source(path.to_str().unwrap(), &vm_string))
+  }
+
+  pub fn from_source(filename: &str, vm_string: &str) -> Self {
+    let mut lines: Vec<(usize, String)> = vm_string.lines().map(String::from).enumerate().collect();
+    lines.reverse();
+    VmParser {
+      vm_file: lines,
+      filename: filename.to_string(),
+    }
+  }
+
+  // A single file, or every .vm file of a directory in name order.
+  pub fn open(path: &Path) -> Result<Vec<Self>, Error> {
+    if !path.is_dir() {
+      return Ok(vec![VmParser::new(&path.to_path_buf())?]);
+    }
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(path)? {
+      let path = entry?.path();
+      if let Some("vm") = path.extension().and_then(|str| str.to_str()) {
+        paths.push(path);
+      }
+    }
+    paths.sort();
+    paths.iter().map(VmParser::new).collect()
+  }
+}
+
+impl Iterator for VmParser {
+  type Item = SourceCommand;
+  fn next(&mut self) -> Option<Self::Item> {
+    let next_item = self.vm_file.pop()?;
+    let path = Path::new(&self.filename);
+    let stem = path.file_stem().unwrap();
+    let command = Command::parse_from_str(&next_item, stem.to_str().unwrap())
+      .unwrap_or_else(|err| panic!("Error parsing command {}: {}", next_item.1, err));
+    Some(SourceCommand {
+      file: path.file_name().unwrap().to_str().unwrap().to_string(),
+      line: next_item.0 + 1,
+      text: next_item
+        .1
+        .split("//")
+        .next()
+        .unwrap()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" "),
+      command,
+    })
+  }
+}
+
+pub struct AsmWriter {
+  file: fs::File,
+  pub path: PathBuf,
+  pub checked: bool,
+}
+
+impl AsmWriter {
+  pub fn new(path: &Path) -> Result<Self, Error> {
+    let path = if path.is_dir() {
+      let name = path.file_name().expect("invalid direcotry name");
+      path.join(name).with_extension("asm")
+    } else {
+      path.with_extension("asm")
+    };
+    let file = fs::File::create(&path)?;
+    Ok(AsmWriter {
+      file,
+      path,
+      checked: false,
+    })
+  }
+
+  pub fn write(&mut self, parsers: Vec<VmParser>) -> Result<SourceMap, Error> {
+    if self.checked {
+      translate_checked(parsers, &mut self.file)
+    } else {
+      translate(parsers, &mut self.file)
+    }
+  }
+}
+
+// When to start the program with the bootstrap code that sets SP and calls
+// Sys.init.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bootstrap {
+  Always,
+  // Only when one of the files defines Sys.init. The test scripts of programs
+  // without it, like those of project 7, start them at their first command
+  // with RAM set up by the script.
+  WithSysInit,
+}
+
+// Writes the assembly for `parsers`, preceded by the bootstrap code, returning
+// where each command's instructions ended up.
+pub fn translate(parsers: Vec<VmParser>, out: &mut impl Write) -> Result<SourceMap, Error> {
+  write_asm(parsers, out, None, Bootstrap::Always)
+}
+
+// Like `translate`, with runtime checks before the commands that can corrupt
+// memory and the trap routines they jump to at the end.
+pub fn translate_checked(parsers: Vec<VmParser>, out: &mut impl Write) -> Result<SourceMap, Error> {
+  write_asm(parsers, out, Some(Checks::default()), Bootstrap::Always)
+}
+
+// Like `translate`, with the bootstrap code only when `bootstrap` asks for it.
+pub fn translate_with(
+  parsers: Vec<VmParser>,
+  out: &mut impl Write,
+  bootstrap: Bootstrap,
+) -> Result<SourceMap, Error> {
+  write_asm(parsers, out, None, bootstrap)
+}
+
+fn write_asm(
+  parsers: Vec<VmParser>,
+  out: &mut impl Write,
+  mut checks: Option<Checks>,
+  bootstrap: Bootstrap,
+) -> Result<SourceMap, Error> {
+  let mut source_map = SourceMap::default();
+  let files: Vec<Vec<SourceCommand>> = parsers.into_iter().map(Iterator::collect).collect();
+  let has_sys_init = files.iter().flatten().any(|source| match &source.command {
+    Command::Function(Function::Decl { name, .. }) => name == "Sys.init",
+    _ => false,
+  });
+  let emit_bootstrap = match bootstrap {
+    Bootstrap::Always => !files.is_empty(),
+    Bootstrap::WithSysInit => has_sys_init,
+  };
+  if emit_bootstrap {
+    let bootstrap = format!(
+      "//Initialize\n\
+       @256 // SP = 256\n\
+       D=A\n\
+       @SP\n\
+       M=D\n\
+       {}",
+      Command::Function(Function::Call {
+        name: "Sys.init".to_string(),
+        index: 0,
+        nargs: 0
+      })
+      .to_asm()
+    );
+    source_map.push(None, 0, None, "bootstrap", "bootstrap", &bootstrap);
+    write!(out, "{}", bootstrap)?;
+  }
+  for file in files {
+    let mut function = None;
+    for source in file {
+      if let Command::Noop = source.command {
+        continue;
+      }
+      if let Command::Function(Function::Decl { name, .. }) = &source.command {
+        function = Some(name.clone());
+      }
+      let mut asm = source.command.to_asm();
+      if let Some(checks) = &mut checks {
+        // A function's checks go after its label, so that calls run them.
+        let at = match &source.command {
+          Command::Function(Function::Decl { name, .. }) => {
+            let label = format!("({})\n", name);
+            asm.find(&label).map_or(0, |at| at + label.len())
+          }
+          _ => 0,
+        };
+        asm.insert_str(at, &checks.guard(&source.command));
+      }
+      source_map.push(
+        Some(&source.file),
+        source.line,
+        function.as_deref(),
+        &source.text,
+        &source.command.kind(),
+        &asm,
+      );
+      write!(out, "{}", asm)?;
+    }
+  }
+  if let Some(checks) = checks {
+    let asm = checks.finish();
+    source_map.push(None, 0, None, "runtime checks", "checks", &asm);
+    write!(out, "{}", asm)?;
+  }
+  Ok(source_map)
+}
+
+// A translated and assembled program loaded into a fresh emulator.
+#[derive(Debug)]
+pub struct Build {
+  pub program: Program,
+  pub source_map: SourceMap,
+  pub emulator: Emulator,
+}
+
+impl Build {
+  // Assembles `asm`, translated with `source_map`. Programs without Sys.init
+  // skip any bootstrap code and start at their first command with the stack
+  // empty at 256, as in the interpreter.
+  pub fn new(asm: &str, source_map: SourceMap) -> Result<Self, String> {
+    let program = assembler::assemble(asm)?;
+    let mut emulator = Emulator::new(&program.instructions)?;
+    let has_sys_init = source_map
+      .mappings
+      .iter()
+      .any(|mapping| mapping.kind == "function" && mapping.function.as_deref() == Some("Sys.init"));
+    if !has_sys_init {
+      if let Some(first) = source_map
+        .mappings
+        .iter()
+        .find(|mapping| mapping.file.is_some())
+      {
+        emulator.pc = first.address as u16;
+      }
+      emulator.ram[interpreter::SP] = 256;
+    }
+    Ok(Build {
+      program,
+      source_map,
+      emulator,
+    })
+  }
+}
+
+// Translates, with runtime checks when `checked`, and assembles `parsers`.
+pub fn build(parsers: Vec<VmParser>, checked: bool) -> Result<Build, String> {
+  let mut asm = Vec::new();
+  let source_map = if checked {
+    translate_checked(parsers, &mut asm)
+  } else {
+    translate(parsers, &mut asm)
+  }
+  .map_err(|err| err.to_string())?;
+  Build::new(&String::from_utf8(asm).unwrap(), source_map)
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::assembler;
+use crate::source_map::{Mapping, SourceMap};
+
+fn write_mapping(listing: &mut String, asm: &[&str], mapping: &Mapping) {
+  let mut address = mapping.address;
+  for line in &asm[mapping.asm_line - 1..mapping.asm_line - 1 + mapping.asm_lines] {
+    if assembler::is_instruction(line) {
+      writeln!(listing, "{:12} | {:>5}  {}", "", address, line.trim()).unwrap();
+      address += 1;
+    } else if assembler::is_label(line) {
+      writeln!(listing, "{:12} |        {}", "", line.trim()).unwrap();
+    }
+  }
+}
+
+// Every line of each VM file with the number of instructions generated for it,
+// followed by those instructions at their ROM addresses. `sources` holds the
+// file name and contents in translation order.
+pub fn listing(source_map: &SourceMap, asm: &str, sources: &[(String, String)]) -> String {
+  let asm: Vec<&str> = asm.lines().collect();
+  let mut by_line: HashMap<(&str, usize), &Mapping> = HashMap::new();
+  let mut listing = String::new();
+  for mapping in &source_map.mappings {
+    match &mapping.file {
+      Some(file) => {
+        by_line.insert((file, mapping.line), mapping);
+      }
+      None => {
+        writeln!(listing, "{:>12} | {}", mapping.count, mapping.command).unwrap();
+        write_mapping(&mut listing, &asm, mapping);
+      }
+    }
+  }
+
+  for (file, text) in sources {
+    writeln!(listing, "\n{}", file).unwrap();
+    for (i, line) in text.lines().enumerate() {
+      match by_line.get(&(file.as_str(), i + 1)) {
+        Some(mapping) => {
+          writeln!(
+            listing,
+            "{:>5} {:>6} | {}",
+            i + 1,
+            mapping.count,
+            line.trim_end()
+          )
+          .unwrap();
+          write_mapping(&mut listing, &asm, mapping);
+        }
+        None => writeln!(
+          listing,
+          "{}",
+          format!("{:>5} {:6} | {}", i + 1, "", line).trim_end()
+        )
+        .unwrap(),
+      }
+    }
+  }
+  writeln!(listing, "\n{} instructions", source_map.instruction_count()).unwrap();
+  listing
+}
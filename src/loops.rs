@@ -0,0 +1,142 @@
+use std::fmt;
+
+use crate::emulator::{Emulator, KBD};
+use crate::source_map::SourceMap;
+use crate::traps;
+
+// Loops longer than this are only caught once they repeat at this interval,
+// which bounds how long a stuck program runs before it's noticed.
+pub const MAX_PERIOD: u64 = 1 << 16;
+
+// A program that went back to an earlier state, registers and RAM alike, and
+// so will go around the same loop forever.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stuck {
+  // The cycle of the earlier state.
+  pub since: u64,
+  pub period: u64,
+  pub pc: u16,
+}
+
+impl fmt::Display for Stuck {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "no progress: PC, registers and RAM repeat every {} cycles since cycle {}",
+      self.period, self.since
+    )
+  }
+}
+
+#[derive(Debug, Clone)]
+struct Checkpoint {
+  cycle: u64,
+  state: (u16, u16, u16, u64),
+  ram: Vec<u16>,
+}
+
+// Finds repeating states with Brent's algorithm: the state is compared every
+// cycle with a checkpoint taken at doubling intervals, up to `MAX_PERIOD`. A
+// hash of RAM, kept up to date from the writes each instruction makes, makes
+// the comparison cheap, and a full comparison confirms it.
+#[derive(Debug, Clone)]
+pub struct LoopDetector {
+  // RAM as hashed, to take the old value of a word out of the hash.
+  shadow: Vec<u16>,
+  hash: u64,
+  checkpoint: Option<Checkpoint>,
+  interval: u64,
+  // The cycle of the last input scheduled from outside the program, before
+  // which a repeating state may still change.
+  pub input_until: u64,
+  pub stuck: Option<Stuck>,
+}
+
+fn mix(address: usize, value: u16) -> u64 {
+  let mut x = ((address as u64) << 16 | value as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+  x ^= x >> 31;
+  x.wrapping_mul(0xbf58_476d_1ce4_e5b9)
+}
+
+impl LoopDetector {
+  pub fn new(emulator: &Emulator) -> Self {
+    let hash = emulator
+      .ram
+      .iter()
+      .enumerate()
+      .fold(0u64, |hash, (address, value)| {
+        hash.wrapping_add(mix(address, *value))
+      });
+    LoopDetector {
+      shadow: emulator.ram.clone(),
+      hash,
+      checkpoint: None,
+      interval: 1,
+      input_until: 0,
+      stuck: None,
+    }
+  }
+
+  fn update(&mut self, ram: &[u16], address: usize) {
+    let value = ram[address];
+    let old = std::mem::replace(&mut self.shadow[address], value);
+    self.hash = self
+      .hash
+      .wrapping_sub(mix(address, old))
+      .wrapping_add(mix(address, value));
+  }
+
+  // Records the instruction that just ran, writing RAM[address] if any.
+  pub fn ran(&mut self, emulator: &Emulator, write: Option<u16>) -> Result<(), Stuck> {
+    if let Some(address) = write {
+      self.update(&emulator.ram, address as usize);
+    }
+    // Key presses change KBD behind the program's back.
+    if self.shadow[KBD] != emulator.ram[KBD] {
+      self.update(&emulator.ram, KBD);
+    }
+    // Sitting in the halt loop is how a program stops.
+    if emulator.cycles < self.input_until || emulator.is_halted() {
+      self.checkpoint = None;
+      return Ok(());
+    }
+    let state = (emulator.pc, emulator.a, emulator.d, self.hash);
+    match &self.checkpoint {
+      Some(checkpoint) if checkpoint.state == state && checkpoint.ram == emulator.ram => {
+        let stuck = Stuck {
+          since: checkpoint.cycle,
+          period: emulator.cycles - checkpoint.cycle,
+          pc: emulator.pc,
+        };
+        self.stuck = Some(stuck.clone());
+        Err(stuck)
+      }
+      Some(checkpoint) if emulator.cycles - checkpoint.cycle < self.interval => Ok(()),
+      _ => {
+        if self.checkpoint.is_some() {
+          self.interval = (self.interval * 2).min(MAX_PERIOD);
+        }
+        self.checkpoint = Some(Checkpoint {
+          cycle: emulator.cycles,
+          state,
+          ram: emulator.ram.clone(),
+        });
+        Ok(())
+      }
+    }
+  }
+}
+
+// Describes where the emulator got stuck, with the VM command when there's a
+// source map.
+pub fn report(emulator: &Emulator, source_map: Option<&SourceMap>) -> Option<String> {
+  let stuck = emulator.loops.as_ref()?.stuck.as_ref()?;
+  let mut report = format!(
+    "Stuck at cycle {}, ROM address {}: {}\n",
+    emulator.cycles, stuck.pc, stuck
+  );
+  if let Some(source_map) = source_map {
+    report += &traps::location(&emulator.ram, source_map, stuck.pc as usize);
+  }
+  Some(report)
+}
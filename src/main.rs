@@ -1,219 +1,916 @@
 use std::env;
 use std::fs;
-use std::io::{Error, Write};
-use std::iter::Iterator;
-use std::path::Path;
-use std::result::Result;
-
-mod arithmetic;
-mod branching;
-mod function;
-mod memory_access;
-
-use arithmetic::Arithmetic;
-use branching::Branching;
-use function::Function;
-use memory_access::MemoryAccess;
-
-#[derive(Debug)]
-enum Command {
-  Arithmetic(Arithmetic),
-  MemoryAccess(MemoryAccess),
-  Branching(Branching),
-  Function(Function),
-  Noop,
-}
-
-impl Command {
-  fn to_asm(&self) -> String {
-    match self {
-      Self::Arithmetic(command) => command.to_asm(),
-      Self::Branching(command) => command.to_asm(),
-      Self::MemoryAccess(command) => command.to_asm(),
-      Self::Function(command) => command.to_asm(),
-      Self::Noop => "".to_string(),
-    }
-  }
-
-  fn arithmetic(command: &str, i: usize) -> Result<Self, String> {
-    match command {
-      "add" => Ok(Self::Arithmetic(Arithmetic::Add)),
-      "sub" => Ok(Self::Arithmetic(Arithmetic::Sub)),
-      "neg" => Ok(Self::Arithmetic(Arithmetic::Neg)),
-      "and" => Ok(Self::Arithmetic(Arithmetic::And)),
-      "or" => Ok(Self::Arithmetic(Arithmetic::Or)),
-      "not" => Ok(Self::Arithmetic(Arithmetic::Not)),
-      "eq" => Ok(Self::Arithmetic(Arithmetic::Eq(i))),
-      "lt" => Ok(Self::Arithmetic(Arithmetic::Lt(i))),
-      "gt" => Ok(Self::Arithmetic(Arithmetic::Gt(i))),
-      _ => Err(format!("unknown arithmetic command {}", command)),
-    }
-  }
-
-  fn branching(command: &str, label: &str) -> Result<Self, String> {
-    match command {
-      "goto" => Ok(Self::Branching(Branching::Goto(label.to_string()))),
-      "if-goto" => Ok(Self::Branching(Branching::IfGoto(label.to_string()))),
-      "label" => Ok(Self::Branching(Branching::Label(label.to_string()))),
-      _ => Err(format!("unkown branching command {}", command)),
-    }
-  }
-
-  fn memory_access(command: &str, segment: &str, id: &str, filename: &str) -> Result<Self, String> {
-    Ok(Self::MemoryAccess(MemoryAccess {
-      command: command.parse()?,
-      segment: segment.parse()?,
-      index: id.parse().map_err(|_| format!("Error parsing {}", id))?,
-      original: format!("{} {} {}", command, segment, id),
-      filename: filename.to_string(),
-    }))
-  }
-
-  fn fn_decl(name: &str, nlocals: &str) -> Result<Self, String> {
-    Ok(Self::Function(Function::Decl {
-      name: name.to_string(),
-      nlocals: nlocals
-        .parse()
-        .map_err(|_| format!("Error parsing {}", nlocals))?,
-    }))
+use std::io::{self, BufRead, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use vm::dap::DapServer;
+use vm::debugger::{Breakpoint, Debugger, Stop};
+use vm::emulator::{self, Emulator};
+use vm::gdb::{self, GdbStub};
+use vm::gif::ScreenRecorder;
+use vm::interpreter::{self, Interpreter, Status};
+use vm::keyboard::KeyboardScript;
+use vm::rom_format::RomFormat;
+use vm::screen::Screen;
+use vm::snapshot::Snapshot;
+use vm::source_map::SourceMap;
+use vm::watch::Condition;
+use vm::{
+  assembler, checks, coverage, disassembler, hack, history, listing, loops, profiler, size_report,
+  terminal, test_script, traps, verify,
+};
+use vm::{AsmWriter, VmParser};
+
+fn translate(args: &[String]) {
+  let path = &args.first().expect(USAGE);
+  let path = Path::new(path);
+  let mut formats = Vec::new();
+  let mut write_source_map = false;
+  let mut write_listing = false;
+  let mut size_report = false;
+  let mut fail_on_overflow = false;
+  let mut checked = false;
+  let mut options = args[1..].iter();
+  while let Some(option) = options.next() {
+    match option.as_str() {
+      "--hack" => formats.push(RomFormat::Hack),
+      "--source-map" => write_source_map = true,
+      "--listing" => write_listing = true,
+      "--size-report" => size_report = true,
+      "--fail-on-overflow" => fail_on_overflow = true,
+      "--checked" => checked = true,
+      "--format" => formats.push(
+        options
+          .next()
+          .expect(USAGE)
+          .parse()
+          .unwrap_or_else(|err| panic!("{}", err)),
+      ),
+      _ => panic!("Unknown option {}\n{}", option, USAGE),
+    }
+  }
+  let parsers =
+    VmParser::open(path).unwrap_or_else(|err| panic!("Cannot open {}: {}", path.display(), err));
+  let sources: Vec<(String, String)> = if write_listing {
+    parsers
+      .iter()
+      .map(|parser| {
+        let path = Path::new(&parser.filename);
+        (
+          path.file_name().unwrap().to_str().unwrap().to_string(),
+          fs::read_to_string(path).expect("Cannot read vm file"),
+        )
+      })
+      .collect()
+  } else {
+    Vec::new()
+  };
+  let mut asm_writer = AsmWriter::new(path).expect("Cannot open asm file for writing");
+  asm_writer.checked = checked;
+  let source_map = asm_writer.write(parsers).expect("Error writing file.");
+  if size_report {
+    print!("{}", size_report::size_report(&source_map));
+  }
+  if source_map.instruction_count() > hack::ROM_SIZE {
+    eprintln!(
+      "warning: {} instructions exceed the Hack ROM size of {}",
+      source_map.instruction_count(),
+      hack::ROM_SIZE
+    );
+    if fail_on_overflow {
+      process::exit(1);
+    }
+  }
+  if write_listing {
+    let asm = fs::read_to_string(&asm_writer.path).expect("Cannot read asm file");
+    fs::write(
+      asm_writer.path.with_extension("lst"),
+      listing::listing(&source_map, &asm, &sources),
+    )
+    .expect("Cannot write listing");
+  }
+  if write_source_map {
+    fs::write(
+      asm_writer.path.with_extension("map.json"),
+      format!("{}\n", source_map.to_json()),
+    )
+    .expect("Cannot write source map");
+  }
+
+  if !formats.is_empty() {
+    let asm = fs::read_to_string(&asm_writer.path).expect("Cannot read asm file");
+    let program =
+      assembler::assemble(&asm).unwrap_or_else(|err| panic!("Error assembling: {}", err));
+    for format in formats {
+      fs::write(
+        asm_writer.path.with_extension(format.extension()),
+        format.encode(&program.instructions),
+      )
+      .expect("Cannot write rom file");
+    }
+    fs::write(asm_writer.path.with_extension("sym"), program.symbol_map())
+      .expect("Cannot write symbol file");
+  }
+}
+
+fn disassemble(args: &[String]) {
+  let path = Path::new(args.first().expect(USAGE));
+  let rom = hack::parse_hack(&fs::read_to_string(path).expect("Cannot read hack file"))
+    .unwrap_or_else(|err| panic!("Error reading {}: {}", path.display(), err));
+  let symbols_path = match args.get(1) {
+    Some(symbols) => Some(PathBuf::from(symbols)),
+    None => Some(path.with_extension("sym")).filter(|path| path.exists()),
+  };
+  let symbols = match symbols_path {
+    Some(symbols_path) => fs::read_to_string(&symbols_path)
+      .expect("Cannot read symbol file")
+      .parse()
+      .unwrap_or_else(|err| panic!("Error reading {}: {}", symbols_path.display(), err)),
+    None => disassembler::SymbolMap::default(),
+  };
+  print!("{}", disassembler::disassemble(&rom, &symbols));
+}
+
+fn interpret(args: &[String]) {
+  let path = Path::new(args.first().expect(USAGE));
+  let mut max_steps = 1_000_000;
+  let mut options = args[1..].iter();
+  while let Some(option) = options.next() {
+    match option.as_str() {
+      "--steps" => {
+        max_steps = options
+          .next()
+          .and_then(|steps| steps.parse().ok())
+          .expect(USAGE)
+      }
+      _ => panic!("Unknown option {}\n{}", option, USAGE),
+    }
+  }
+  let parsers =
+    VmParser::open(path).unwrap_or_else(|err| panic!("Cannot open {}: {}", path.display(), err));
+  let mut interpreter = Interpreter::new(parsers).unwrap_or_else(|err| panic!("{}", err));
+  if interpreter.bootstrap().is_err() {
+    // Without Sys.init run the commands in order on a fresh stack.
+    interpreter.ram[interpreter::SP] = 256;
+  }
+  let status = interpreter
+    .run(max_steps)
+    .unwrap_or_else(|err| panic!("Error after {} steps: {}", interpreter.steps, err));
+  match status {
+    Status::StepLimit => println!("Stopped after {} steps", interpreter.steps),
+    _ => println!("Halted after {} steps", interpreter.steps),
+  }
+  let ram = &interpreter.ram;
+  println!(
+    "SP={} LCL={} ARG={} THIS={} THAT={}",
+    ram[0], ram[1], ram[2], ram[3], ram[4]
+  );
+  let sp = (ram[interpreter::SP] as usize).clamp(256, ram.len());
+  println!(
+    "stack: {:?}",
+    ram[256..sp]
+      .iter()
+      .map(|word| *word as i16)
+      .collect::<Vec<i16>>()
+  );
+}
+
+// What `emulate` writes out along the way.
+enum Capture {
+  Screen(PathBuf),
+  Snapshot(PathBuf),
+}
+
+fn emulate(args: &[String]) {
+  let path = Path::new(args.first().expect(USAGE));
+  let mut max_cycles = 10_000_000;
+  // Screens and snapshots to save, by cycle; `None` saves when the program
+  // stops.
+  let mut captures: Vec<(Option<u64>, Capture)> = Vec::new();
+  let mut keyboard = None;
+  let mut gif_path = None;
+  let mut gif_every = 100_000;
+  let mut gif_delay = 4;
+  let mut traps = false;
+  let mut detect_loops = false;
+  let mut checked = false;
+  let mut options = args[1..].iter();
+  while let Some(option) = options.next() {
+    match option.as_str() {
+      "--cycles" => {
+        max_cycles = options
+          .next()
+          .and_then(|cycles| cycles.parse().ok())
+          .expect(USAGE)
+      }
+      "--keys" => {
+        let path = Path::new(options.next().expect(USAGE));
+        keyboard = Some(KeyboardScript::load(path).unwrap_or_else(|err| panic!("{}", err)));
+      }
+      "--gif" => gif_path = Some(PathBuf::from(options.next().expect(USAGE))),
+      "--gif-every" => {
+        gif_every = options
+          .next()
+          .and_then(|cycles| cycles.parse().ok())
+          .expect(USAGE)
+      }
+      "--gif-delay" => {
+        gif_delay = options
+          .next()
+          .and_then(|delay| delay.parse().ok())
+          .expect(USAGE)
+      }
+      "--traps" => traps = true,
+      "--detect-loops" => detect_loops = true,
+      "--checked" => checked = true,
+      "--screen" | "--snapshot" => {
+        let file = PathBuf::from(options.next().expect(USAGE));
+        captures.push((None, capture(option, file)));
+      }
+      "--screen-at" | "--snapshot-at" => {
+        let cycle = options
+          .next()
+          .and_then(|cycle| cycle.parse().ok())
+          .expect(USAGE);
+        let file = PathBuf::from(options.next().expect(USAGE));
+        captures.push((Some(cycle), capture(option, file)));
+      }
+      _ => panic!("Unknown option {}\n{}", option, USAGE),
+    }
+  }
+  captures.sort_by_key(|(cycle, _)| cycle.unwrap_or(u64::MAX));
+  let (mut emulator, source_map) = match path.extension().and_then(|str| str.to_str()) {
+    Some("snap") => {
+      let snapshot = Snapshot::load(path).unwrap_or_else(|err| panic!("{}", err));
+      snapshot
+        .restore_keyboard(keyboard.as_mut())
+        .unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+      (
+        snapshot.emulator().unwrap_or_else(|err| panic!("{}", err)),
+        None,
+      )
+    }
+    _ => build_program(path, checked),
+  };
+  let mut keyboard = keyboard.unwrap_or_default();
+  // A restored run keeps counting from the snapshot's cycle.
+  let max_cycles = emulator.cycles + max_cycles;
+  if traps {
+    emulator.enable_traps(source_map.is_some());
+  }
+  if detect_loops {
+    emulator.enable_loop_detection();
+  }
+  let mut recorder = gif_path
+    .as_ref()
+    .map(|_| ScreenRecorder::new(gif_every, gif_delay));
+  let mut status = emulator::Status::Running;
+  for (cycle, capture) in &captures {
+    let until = cycle.unwrap_or(max_cycles).min(max_cycles);
+    if status != emulator::Status::Halted && until > emulator.cycles {
+      status = advance(&mut emulator, &mut keyboard, &mut recorder, until)
+        .unwrap_or_else(|err| stop(&emulator, source_map.as_ref(), err));
+    }
+    match capture {
+      Capture::Screen(file) => Screen::new(&emulator.ram).save(file),
+      Capture::Snapshot(file) => Snapshot::new(&emulator, Some(&keyboard)).save(file),
+    }
+    .unwrap_or_else(|err| panic!("{}", err));
+  }
+  if status != emulator::Status::Halted && max_cycles > emulator.cycles {
+    status = advance(&mut emulator, &mut keyboard, &mut recorder, max_cycles)
+      .unwrap_or_else(|err| stop(&emulator, source_map.as_ref(), err));
+  }
+  if let (Some(recorder), Some(path)) = (recorder, gif_path) {
+    let (gif, frames) = recorder.finish(&emulator);
+    fs::write(&path, gif).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    println!("Recorded {} frames to {}", frames, path.display());
+  }
+  match status {
+    emulator::Status::Halted => println!("Halted after {} cycles", emulator.cycles),
+    _ => println!("Stopped after {} cycles", emulator.cycles),
+  }
+  if let Some((code, address)) = checks::trap(&emulator) {
+    println!(
+      "Trap {}: {} (check at ROM address {})",
+      code,
+      checks::describe(code),
+      address
+    );
+  }
+  let ram = &emulator.ram;
+  println!(
+    "A={} D={} PC={} SP={} LCL={} ARG={} THIS={} THAT={}",
+    emulator.a, emulator.d, emulator.pc, ram[0], ram[1], ram[2], ram[3], ram[4]
+  );
+  let sp = (ram[0] as usize).clamp(256, emulator::SCREEN);
+  println!(
+    "stack: {:?}",
+    ram[256..sp]
+      .iter()
+      .map(|word| *word as i16)
+      .collect::<Vec<i16>>()
+  );
+}
+
+fn capture(option: &str, file: PathBuf) -> Capture {
+  if option.starts_with("--screen") {
+    Capture::Screen(file)
+  } else {
+    Capture::Snapshot(file)
+  }
+}
+
+// Runs the emulator up to cycle `until`, replaying key presses and recording
+// the screen on the way.
+fn advance(
+  emulator: &mut Emulator,
+  keyboard: &mut KeyboardScript,
+  recorder: &mut Option<ScreenRecorder>,
+  until: u64,
+) -> Result<emulator::Status, String> {
+  loop {
+    if let Some(recorder) = recorder {
+      recorder.capture(emulator);
+    }
+    let stop = recorder
+      .as_ref()
+      .map_or(until, |recorder| recorder.next.min(until));
+    let status = keyboard.run_until(emulator, stop)?;
+    if status == emulator::Status::Halted || emulator.cycles >= until {
+      return Ok(status);
+    }
+  }
+}
+
+// Reports the trap, loop or error that stopped an emulator run and exits.
+fn stop(emulator: &Emulator, source_map: Option<&SourceMap>, err: String) -> ! {
+  match traps::report(emulator, source_map).or_else(|| loops::report(emulator, source_map)) {
+    Some(report) => {
+      print!("{}", report);
+      process::exit(1);
+    }
+    None => panic!("Error after {} cycles: {}", emulator.cycles, err),
+  }
+}
+
+fn verify(args: &[String]) {
+  let path = Path::new(args.first().expect(USAGE));
+  let mut max_steps = 1_000_000;
+  let mut options = args[1..].iter();
+  while let Some(option) = options.next() {
+    match option.as_str() {
+      "--steps" => {
+        max_steps = options
+          .next()
+          .and_then(|steps| steps.parse().ok())
+          .expect(USAGE)
+      }
+      _ => panic!("Unknown option {}\n{}", option, USAGE),
+    }
+  }
+  let parsers =
+    VmParser::open(path).unwrap_or_else(|err| panic!("Cannot open {}: {}", path.display(), err));
+  let report = verify::verify(parsers, max_steps).unwrap_or_else(|err| panic!("{}", err));
+  match report.divergence {
+    Some(divergence) => {
+      println!(
+        "Diverged after {} commands ({} cycles) at {}",
+        report.steps, report.cycles, divergence.location
+      );
+      for difference in divergence.differences {
+        println!("  {}", difference);
+      }
+      process::exit(1);
+    }
+    None => println!(
+      "{} after {} commands ({} cycles), interpreter and emulator agree",
+      if report.halted { "Halted" } else { "Stopped" },
+      report.steps,
+      report.cycles
+    ),
+  }
+}
+
+fn profile(args: &[String]) {
+  let path = Path::new(args.first().expect(USAGE));
+  let mut max_cycles = 100_000_000;
+  let mut folded = None;
+  let mut options = args[1..].iter();
+  while let Some(option) = options.next() {
+    match option.as_str() {
+      "--cycles" => {
+        max_cycles = options
+          .next()
+          .and_then(|cycles| cycles.parse().ok())
+          .expect(USAGE)
+      }
+      "--folded" => folded = Some(options.next().expect(USAGE)),
+      _ => panic!("Unknown option {}\n{}", option, USAGE),
+    }
+  }
+  let parsers =
+    VmParser::open(path).unwrap_or_else(|err| panic!("Cannot open {}: {}", path.display(), err));
+  let profile = profiler::profile(parsers, max_cycles).unwrap_or_else(|err| panic!("{}", err));
+  print!("{}\n{}", profile.flat(), profile.call_tree());
+  if let Some(folded) = folded {
+    fs::write(folded, profile.folded()).expect("Cannot write folded stacks");
+  }
+}
+
+fn cover(args: &[String]) {
+  let path = Path::new(args.first().expect(USAGE));
+  let mut max_cycles = 100_000_000;
+  let mut lcov = None;
+  let mut options = args[1..].iter();
+  while let Some(option) = options.next() {
+    match option.as_str() {
+      "--cycles" => {
+        max_cycles = options
+          .next()
+          .and_then(|cycles| cycles.parse().ok())
+          .expect(USAGE)
+      }
+      "--lcov" => lcov = Some(options.next().expect(USAGE)),
+      _ => panic!("Unknown option {}\n{}", option, USAGE),
+    }
+  }
+  let parsers =
+    VmParser::open(path).unwrap_or_else(|err| panic!("Cannot open {}: {}", path.display(), err));
+  let coverage = coverage::coverage(parsers, max_cycles).unwrap_or_else(|err| panic!("{}", err));
+  if !coverage.halted {
+    println!("Stopped after {} cycles", coverage.cycles);
+  }
+  print!("{}", coverage.summary());
+  if let Some(lcov) = lcov {
+    fs::write(lcov, coverage.lcov()).expect("Cannot write lcov file");
   }
+}
+
+fn test(args: &[String]) {
+  if args.is_empty() {
+    panic!("{}", USAGE);
+  }
+  let mut failed = false;
+  for script in args {
+    let path = Path::new(script);
+    let result = test_script::run_script(path)
+      .unwrap_or_else(|err| panic!("Error running {}: {}", path.display(), err));
+    if let Some(output_file) = &result.output_file {
+      fs::write(output_file, &result.output).expect("Cannot write output file");
+    }
+    match &result.mismatch {
+      Some(mismatch) => {
+        failed = true;
+        println!(
+          "{}: Comparison failure at line {}\n  expected: {}\n  actual:   {}",
+          path.display(),
+          mismatch.line,
+          mismatch.expected,
+          mismatch.actual
+        );
+      }
+      None if result.compare_file.is_some() => {
+        println!("{}: Comparison ended successfully", path.display())
+      }
+      None => println!("{}: End of script", path.display()),
+    }
+  }
+  if failed {
+    process::exit(1);
+  }
+}
+
+fn location(debugger: &Debugger) -> String {
+  match debugger.current() {
+    Some(mapping) => format!(
+      "{}:{}: {}",
+      mapping.file.as_deref().unwrap_or("bootstrap"),
+      mapping.line,
+      mapping.command
+    ),
+    None => format!("PC {}", debugger.emulator.pc),
+  }
+}
 
-  fn fn_call(name: &str, nargs: &str, index: usize) -> Result<Self, String> {
-    Ok(Self::Function(Function::Call {
-      name: name.to_string(),
-      nargs: nargs
+fn report_stop(debugger: &Debugger, stop: Stop) {
+  match stop {
+    Stop::Step => println!("{}", location(debugger)),
+    Stop::Breakpoint(i) => println!(
+      "Breakpoint {} ({}) at {}",
+      i + 1,
+      debugger.breakpoints[i].0,
+      location(debugger)
+    ),
+    Stop::Halted => println!("Halted after {} cycles", debugger.emulator.cycles),
+    Stop::CycleLimit => println!(
+      "Stopped after {} cycles at {}",
+      debugger.emulator.cycles,
+      location(debugger)
+    ),
+    Stop::Write(address) => println!(
+      "Last write to RAM[{}] at cycle {}, {}",
+      address,
+      debugger.emulator.cycles,
+      location(debugger)
+    ),
+    Stop::Watch {
+      watch,
+      address,
+      old,
+      new,
+      pc,
+    } => {
+      println!(
+        "Watch {} ({}): RAM[{}] {} -> {} at cycle {}",
+        watch + 1,
+        debugger.watches[watch].0,
+        address,
+        old as i16,
+        new as i16,
+        debugger.emulator.cycles
+      );
+      if let Some(mapping) = debugger.source_map.lookup(pc as usize) {
+        println!(
+          "  written by ROM address {} in {}:{}: {}",
+          pc,
+          mapping.file.as_deref().unwrap_or("bootstrap"),
+          mapping.line,
+          mapping.command
+        );
+      }
+    }
+    Stop::HistoryStart => println!(
+      "Start of recorded history at cycle {}, {}",
+      debugger.emulator.cycles,
+      location(debugger)
+    ),
+  }
+}
+
+// Runs one debugger command, returning false on `quit`.
+fn debug_command(debugger: &mut Debugger, line: &str) -> Result<bool, String> {
+  let words: Vec<&str> = line.split_whitespace().collect();
+  let stop = match words.as_slice() {
+    [] => return Ok(true),
+    ["quit"] | ["q"] => return Ok(false),
+    ["help"] | ["h"] => {
+      println!("{}", DEBUG_HELP);
+      return Ok(true);
+    }
+    ["break", _, ..] | ["b", _, ..] => {
+      let (breakpoint, condition) = parse_breakpoint(line.trim().split_once(' ').unwrap().1)?;
+      let address = debugger.add_breakpoint(breakpoint, condition)?;
+      println!(
+        "Breakpoint {} at ROM address {}",
+        debugger.breakpoints.len(),
+        address
+      );
+      return Ok(true);
+    }
+    ["delete", n] | ["d", n] => {
+      let n: usize = n.parse().map_err(|_| format!("invalid breakpoint {}", n))?;
+      if n == 0 || n > debugger.breakpoints.len() {
+        return Err(format!("no breakpoint {}", n));
+      }
+      debugger.breakpoints.remove(n - 1);
+      return Ok(true);
+    }
+    ["breakpoints"] => {
+      for (i, (breakpoint, address, condition)) in debugger.breakpoints.iter().enumerate() {
+        match condition {
+          Some(condition) => println!(
+            "{:>3}  {} if {} (ROM {})",
+            i + 1,
+            breakpoint,
+            condition,
+            address
+          ),
+          None => println!("{:>3}  {} (ROM {})", i + 1, breakpoint, address),
+        }
+      }
+      return Ok(true);
+    }
+    ["watch", _, ..] => {
+      let watch = line.trim().split_once(' ').unwrap().1.parse()?;
+      let (watch, address) = debugger.add_watch(watch)?;
+      match address {
+        Some(address) => println!(
+          "Watch {}: {} (RAM[{}])",
+          debugger.watches.len(),
+          watch,
+          address
+        ),
+        None => println!("Watch {}: {}", debugger.watches.len(), watch),
+      }
+      return Ok(true);
+    }
+    ["unwatch", n] => {
+      let n: usize = n.parse().map_err(|_| format!("invalid watch {}", n))?;
+      if n == 0 || n > debugger.watches.len() {
+        return Err(format!("no watch {}", n));
+      }
+      debugger.watches.remove(n - 1);
+      return Ok(true);
+    }
+    ["watches"] => {
+      for (i, (watch, _)) in debugger.watches.iter().enumerate() {
+        println!("{:>3}  {}", i + 1, watch);
+      }
+      return Ok(true);
+    }
+    ["backtrace"] | ["bt"] => {
+      for (i, frame) in debugger.backtrace().iter().enumerate() {
+        println!(
+          "#{:<3} {} at {}:{}: {} (LCL={} ARG={})",
+          i,
+          frame.function.as_deref().unwrap_or("??"),
+          frame.file,
+          frame.line,
+          frame.command,
+          frame.lcl,
+          frame.arg
+        );
+      }
+      return Ok(true);
+    }
+    ["print", segment] | ["p", segment] => {
+      print_segment(debugger, segment, None)?;
+      return Ok(true);
+    }
+    ["print", segment, index] | ["p", segment, index] => {
+      let index = index
+        .parse()
+        .map_err(|_| format!("invalid index {}", index))?;
+      print_segment(debugger, segment, Some(index))?;
+      return Ok(true);
+    }
+    ["save", file] => {
+      Snapshot::new(&debugger.emulator, None).save(Path::new(file))?;
+      println!("Saved cycle {} to {}", debugger.emulator.cycles, file);
+      return Ok(true);
+    }
+    ["where"] | ["w"] => {
+      println!(
+        "{} (cycle {})",
+        location(debugger),
+        debugger.emulator.cycles
+      );
+      return Ok(true);
+    }
+    ["step"] | ["s"] => debugger.step()?,
+    ["next"] | ["n"] => debugger.step_over()?,
+    ["finish"] => debugger.finish()?,
+    ["continue"] | ["c"] => debugger.cont()?,
+    ["reverse-step"] | ["rs"] => debugger.reverse_step()?,
+    ["reverse-next"] | ["rn"] => debugger.reverse_step_over()?,
+    ["reverse-continue"] | ["rc"] => debugger.reverse_continue()?,
+    ["reverse-continue", address] | ["rc", address] => {
+      let address = address
         .parse()
-        .map_err(|_| format!("Error parsing {}", nargs))?,
-      index,
-    }))
-  }
-
-  fn parse_from_str((i, str): &(usize, String), filename: &str) -> Result<Self, String> {
-    match str
-      .split("//")
-      .nth(0)
-      .unwrap()
-      .split_whitespace()
-      .collect::<Vec<&str>>()
-      .as_slice()
+        .map_err(|_| format!("invalid address {}", address))?;
+      debugger.reverse_to_write(address)?
+    }
+    ["cycle", cycle] => {
+      let cycle = cycle
+        .parse()
+        .map_err(|_| format!("invalid cycle {}", cycle))?;
+      debugger.seek(cycle)?;
+      Stop::Step
+    }
+    _ => return Err(format!("unknown command {}, try help", line.trim())),
+  };
+  report_stop(debugger, stop);
+  Ok(true)
+}
+
+// A breakpoint with an optional condition: `<function|file:line> [if <expr>]`.
+fn parse_breakpoint(str: &str) -> Result<(Breakpoint, Option<Condition>), String> {
+  match str.split_once(" if ") {
+    Some((breakpoint, condition)) => Ok((breakpoint.trim().parse()?, Some(condition.parse()?))),
+    None => Ok((str.trim().parse()?, None)),
+  }
+}
+
+fn print_segment(debugger: &Debugger, segment: &str, index: Option<u16>) -> Result<(), String> {
+  for (name, value) in debugger.segment(0, segment, index)? {
+    println!("{} = {}", name, value);
+  }
+  Ok(())
+}
+
+fn debug(args: &[String]) {
+  let path = Path::new(args.first().expect(USAGE));
+  let parsers =
+    VmParser::open(path).unwrap_or_else(|err| panic!("Cannot open {}: {}", path.display(), err));
+  let mut max_deltas = history::DEFAULT_DELTAS;
+  let mut breakpoints = Vec::new();
+  let mut snapshot = None;
+  let mut options = args[1..].iter();
+  while let Some(option) = options.next() {
+    match option.as_str() {
+      "--history" => {
+        max_deltas = options
+          .next()
+          .and_then(|cycles| cycles.parse().ok())
+          .expect(USAGE)
+      }
+      "--snapshot" => {
+        let path = Path::new(options.next().expect(USAGE));
+        snapshot = Some(Snapshot::load(path).unwrap_or_else(|err| panic!("{}", err)));
+      }
+      _ if option.starts_with("--") => panic!("Unknown option {}\n{}", option, USAGE),
+      _ => breakpoints.push(parse_breakpoint(option).unwrap_or_else(|err| panic!("{}", err))),
+    }
+  }
+  let mut debugger = Debugger::new(parsers, max_deltas).unwrap_or_else(|err| panic!("{}", err));
+  if let Some(snapshot) = snapshot {
+    debugger
+      .restore(&snapshot)
+      .unwrap_or_else(|err| panic!("{}", err));
+  }
+  for (breakpoint, condition) in breakpoints {
+    debugger
+      .add_breakpoint(breakpoint, condition)
+      .unwrap_or_else(|err| panic!("{}", err));
+  }
+  println!("{}", location(&debugger));
+  let stdin = io::stdin();
+  let mut last = String::new();
+  loop {
+    print!("(vm) ");
+    io::stdout().flush().unwrap();
+    let mut line = String::new();
+    if stdin
+      .lock()
+      .read_line(&mut line)
+      .expect("Cannot read stdin")
+      == 0
     {
-      [] => Ok(Command::Noop),
-      command if command[0] == "//" => Ok(Command::Noop),
-      ["function", name, nlocals] => Ok(Command::fn_decl(name, nlocals)?),
-      ["call", name, nargs] => Ok(Command::fn_call(name, nargs, *i)?),
-      ["return"] => Ok(Command::Function(Function::Return)),
-      [command, memory, id] => Ok(Command::memory_access(command, memory, id, filename)?),
-      [command, label] => Ok(Command::branching(command, label)?),
-      [command] => Ok(Command::arithmetic(command, *i)?),
-      rest => {
-        println!("{:?}", rest);
-        Err(format!("couldn't parse command {}", str))
-      }
-    }
-  }
-}
-
-#[derive(Debug)]
-struct VmParser {
-  vm_file: Vec<(usize, String)>,
-  filename: String,
-}
-
-impl VmParser {
-  fn new(path: &std::path::PathBuf) -> Result<Self, Error> {
-    let vm_string = fs::read_to_string(path)?;
-    let mut lines: Vec<(usize, String)> = vm_string.lines().map(String::from).enumerate().collect();
-    lines.reverse();
-    Ok(VmParser {
-      vm_file: lines,
-      filename: path.to_str().unwrap().to_string(),
-    })
-  }
-}
-
-impl Iterator for VmParser {
-  type Item = Command;
-  fn next(&mut self) -> Option<Self::Item> {
-    let next_item = self.vm_file.pop()?;
-    let path = Path::new(&self.filename).file_stem().unwrap();
-    let command = Command::parse_from_str(&next_item, path.to_str().unwrap())
-      .expect(&format!("Error parsing command {}", next_item.1));
-    Some(command)
-  }
-}
-
-struct AsmWriter {
-  file: fs::File,
-}
-
-impl AsmWriter {
-  fn new(path: &Path) -> Result<Self, Error> {
-    let path = if path.is_dir() {
-      let name = path.file_name().expect("invalid direcotry name");
-      path.join(name).with_extension("asm")
-    } else {
-      path.with_extension("asm")
-    };
-    let file = fs::File::create(path)?;
-    Ok(AsmWriter { file: file })
-  }
-
-  fn write(&mut self, parsers: Vec<VmParser>) -> Result<(), Error> {
-    if parsers.len() > 0 {
-      write!(
-        self.file,
-        "//Initialize\n\
-         @256 // SP = 256\n\
-         D=A\n\
-         @SP\n\
-         M=D\n"
-      )?;
-      write!(
-        self.file,
-        "{}",
-        Command::Function(Function::Call {
-          name: "Sys.init".to_string(),
-          index: 0,
-          nargs: 0
-        })
-        .to_asm()
-      )?;
-    }
-    for mut parser in parsers {
-      loop {
-        match parser.next() {
-          Some(command) => write!(self.file, "{}", command.to_asm())?,
-          None => break,
-        }
+      break;
+    }
+    // An empty line repeats the last command, like gdb.
+    if line.trim().is_empty() {
+      line = last.clone();
+    }
+    match debug_command(&mut debugger, &line) {
+      Ok(true) => last = line,
+      Ok(false) => break,
+      Err(err) => println!("error: {}", err),
+    }
+  }
+}
+
+// Loads a `.asm` or `.hack` file, or translates and assembles VM code.
+fn load_program(path: &Path) -> Emulator {
+  build_program(path, false).0
+}
+
+// Loads a .asm or .hack file or a snapshot, or translates VM code and keeps
+// its source map.
+fn build_program(path: &Path, checked: bool) -> (Emulator, Option<SourceMap>) {
+  match path.extension().and_then(|str| str.to_str()) {
+    Some("snap") => (
+      Snapshot::load(path)
+        .and_then(|snapshot| snapshot.emulator())
+        .unwrap_or_else(|err| panic!("{}", err)),
+      None,
+    ),
+    Some("asm") | Some("hack") => (
+      Emulator::load(path).unwrap_or_else(|err| panic!("{}", err)),
+      None,
+    ),
+    _ => {
+      let parsers = VmParser::open(path)
+        .unwrap_or_else(|err| panic!("Cannot open {}: {}", path.display(), err));
+      let build = vm::build(parsers, checked).unwrap_or_else(|err| panic!("{}", err));
+      (build.emulator, Some(build.source_map))
+    }
+  }
+}
+
+fn gdb_server(args: &[String]) {
+  let path = Path::new(args.first().expect(USAGE));
+  let mut port = gdb::DEFAULT_PORT;
+  let mut options = args[1..].iter();
+  while let Some(option) = options.next() {
+    match option.as_str() {
+      "--port" => {
+        port = options
+          .next()
+          .and_then(|port| port.parse().ok())
+          .expect(USAGE)
       }
+      _ => panic!("Unknown option {}\n{}", option, USAGE),
     }
-    Ok(())
   }
+  let mut stub = GdbStub::new(load_program(path));
+  let listener = TcpListener::bind(("127.0.0.1", port))
+    .unwrap_or_else(|err| panic!("Cannot listen on port {}: {}", port, err));
+  println!("Waiting for gdb on 127.0.0.1:{}", port);
+  stub
+    .serve(&listener)
+    .unwrap_or_else(|err| panic!("Connection failed: {}", err));
 }
 
+fn play(args: &[String]) {
+  let path = Path::new(args.first().expect(USAGE));
+  let mut options = terminal::Options::default();
+  let mut args = args[1..].iter();
+  while let Some(option) = args.next() {
+    match option.as_str() {
+      "--fps" => options.fps = args.next().and_then(|fps| fps.parse().ok()).expect(USAGE),
+      "--speed" => {
+        options.speed = args
+          .next()
+          .and_then(|speed| speed.parse().ok())
+          .expect(USAGE)
+      }
+      "--style" => {
+        options.style = args
+          .next()
+          .expect(USAGE)
+          .parse()
+          .unwrap_or_else(|err| panic!("{}", err))
+      }
+      _ => panic!("Unknown option {}\n{}", option, USAGE),
+    }
+  }
+  let mut emulator = load_program(path);
+  terminal::play(&mut emulator, options).unwrap_or_else(|err| panic!("{}", err));
+}
+
+const DEBUG_HELP: &str = "break|b <function|file:line>  set a breakpoint\n\
+                          break|b <location> if <expr>  set a breakpoint that stops where the\n\
+                                                        expression over RAM[n], A, D, PC,\n\
+                                                        SP..THAT and R0..R15 holds\n\
+                          delete|d <n>                  delete breakpoint n\n\
+                          breakpoints                   list breakpoints\n\
+                          watch <location>              stop when RAM[n], a register such as SP,\n\
+                                                        static [file] <i>, local [function] <i>\n\
+                                                        or argument [function] <i> changes\n\
+                          unwatch <n>                   delete watch n\n\
+                          watches                       list watches\n\
+                          continue|c                    run to the next breakpoint\n\
+                          step|s                        run one VM command, into calls\n\
+                          next|n                        run one VM command, over calls\n\
+                          finish                        run until the current function returns\n\
+                          reverse-step|rs               run back one VM command, into calls\n\
+                          reverse-next|rn               run back one VM command, over calls\n\
+                          reverse-continue|rc [address] run back to the previous breakpoint or\n\
+                                                        to the last write of RAM[address]\n\
+                          cycle <n>                     go to cycle n\n\
+                          backtrace|bt                  show the call stack\n\
+                          print|p <segment> [index]     show local, argument, this, that, static,\n\
+                                                        pointer or temp values\n\
+                          where|w                       show the current command\n\
+                          save <file>                   save a snapshot to continue from with\n\
+                                                        --snapshot\n\
+                          quit|q";
+
+const USAGE: &str = "USAGE: vm <filename|directory> [--hack] [--format <format>]...\n       \
+                     [--source-map] [--listing] [--size-report] [--fail-on-overflow]\n       \
+                     [--checked]\n       \
+                     vm disasm <file.hack> [file.sym]\n       \
+                     vm run <filename|directory> [--steps <n>]\n       \
+                     vm emulate <filename|directory|file.asm|file.hack|file.snap> [--cycles <n>]\n       \
+                     [--keys <file>] [--traps] [--checked] [--detect-loops]\n       \
+                     [--snapshot <file.snap>] [--snapshot-at <cycle> <file.snap>]...\n       \
+                     [--screen <file.png|file.pbm>] [--screen-at <cycle> <file.png|file.pbm>]...\n       \
+                     [--gif <file.gif>] [--gif-every <cycles>] [--gif-delay <centiseconds>]\n       \
+                     vm play <filename|directory|file.asm|file.hack|file.snap> [--fps <n>]\n       \
+                     [--speed <cycles>] [--style braille|blocks]\n       \
+                     vm verify <filename|directory> [--steps <n>]\n       \
+                     vm test <file.tst>...\n       \
+                     vm debug <filename|directory> [--history <cycles>] [--snapshot <file.snap>]\n       \
+                     [breakpoint]...\n       \
+                     vm gdb <filename|directory|file.asm|file.hack|file.snap> [--port <n>]\n       \
+                     vm dap\n       \
+                     vm profile <filename|directory> [--cycles <n>] [--folded <file>]\n       \
+                     vm coverage <filename|directory> [--cycles <n>] [--lcov <file>]\n\
+                     formats: hack, bin-le, bin-be, ihex, readmemb, readmemh, logisim";
+
 fn main() {
-  let args: Vec<String> = env::args().collect();
-  let path = &args.get(1).expect("USAGE: vm <filename|directory>");
-  let path = Path::new(path);
-  let parsers: Vec<VmParser> = if path.is_dir() {
-    fs::read_dir(path)
-      .expect("Not a directory")
-      .filter_map(|path| {
-        let path = &path.expect("Invalid path").path();
-        if let Some("vm") = path.extension().and_then(|str| str.to_str()) {
-          return Some(
-            VmParser::new(path).expect(&format!("Cannot open file {}", path.to_str().unwrap())),
-          );
-        }
-        None
-      })
-      .collect()
-  } else {
-    vec![VmParser::new(&path.to_path_buf()).expect(&format!(
-      "Cannot open file named {}",
-      path.to_str().unwrap()
-    ))]
-  };
-  let mut asm_writer = AsmWriter::new(path).expect("Cannot open asm file for writing");
-  asm_writer.write(parsers).expect("Error writing file.");
+  let args: Vec<String> = env::args().skip(1).collect();
+  match args.first().map(String::as_str) {
+    Some("disasm") => disassemble(&args[1..]),
+    Some("run") => interpret(&args[1..]),
+    Some("emulate") => emulate(&args[1..]),
+    Some("play") => play(&args[1..]),
+    Some("verify") => verify(&args[1..]),
+    Some("test") => test(&args[1..]),
+    Some("debug") => debug(&args[1..]),
+    Some("gdb") => gdb_server(&args[1..]),
+    Some("profile") => profile(&args[1..]),
+    Some("coverage") => cover(&args[1..]),
+    Some("dap") => {
+      let stdout = io::stdout();
+      DapServer::new(io::BufReader::new(io::stdin()), stdout.lock())
+        .run()
+        .unwrap_or_else(|err| panic!("{}", err));
+    }
+    _ => translate(&args),
+  }
 }
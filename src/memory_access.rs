@@ -235,18 +235,18 @@ impl MemoryAccess {
         filename = filename,
         index = index,
       ),
-      _ => panic!(format!("Unhandled memory access command: {:?}", self)),
+      _ => panic!("Unhandled memory access command: {:?}", self),
     }
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AccessCommand {
   Pop,
   Push,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Segment {
   Constant,
   Local,
@@ -265,7 +265,7 @@ impl Segment {
       Segment::This => "THIS",
       Segment::That => "THAT",
       Segment::Argument => "ARG",
-      _ => panic!(format!("Segment {:?}, does not have a label", self)),
+      _ => panic!("Segment {:?}, does not have a label", self),
     }
   }
 }
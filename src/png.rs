@@ -0,0 +1,518 @@
+use std::collections::HashMap;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const WINDOW: usize = 32768;
+const MAX_MATCH: usize = 258;
+
+const LENGTH_BASE: [u16; 29] = [
+  3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+  163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+  0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+  1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049,
+  3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+  0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// Order in which dynamic blocks list the code lengths of the code length code.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+  16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+// A decoded image as 8 bit gray levels, row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+  pub width: usize,
+  pub height: usize,
+  pub gray: Vec<u8>,
+}
+
+// Encodes a 1 bit grayscale image; `pixels` holds `width` entries per row,
+// true for white.
+pub fn encode_gray1(width: usize, height: usize, pixels: &[bool]) -> Vec<u8> {
+  let stride = width.div_ceil(8);
+  let mut raw = Vec::with_capacity((stride + 1) * height);
+  for row in pixels.chunks(width).take(height) {
+    raw.push(0);
+    for byte in row.chunks(8) {
+      let bits = byte
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, white)| bits | (*white as u8) << (7 - i));
+      raw.push(bits);
+    }
+  }
+  let mut header = Vec::new();
+  header.extend_from_slice(&(width as u32).to_be_bytes());
+  header.extend_from_slice(&(height as u32).to_be_bytes());
+  // Bit depth 1, grayscale, deflate, adaptive filtering, no interlace.
+  header.extend_from_slice(&[1, 0, 0, 0, 0]);
+  encode(&header, &raw)
+}
+
+fn encode(header: &[u8], raw: &[u8]) -> Vec<u8> {
+  let mut png = SIGNATURE.to_vec();
+  chunk(&mut png, b"IHDR", header);
+  chunk(&mut png, b"IDAT", &zlib(raw));
+  chunk(&mut png, b"IEND", &[]);
+  png
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+  png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  png.extend_from_slice(kind);
+  png.extend_from_slice(data);
+  png.extend_from_slice(&crc32(&[kind, data].concat()).to_be_bytes());
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+  let mut crc = 0xffff_ffff;
+  for byte in data {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 != 0 {
+        0xedb8_8320 ^ (crc >> 1)
+      } else {
+        crc >> 1
+      };
+    }
+  }
+  !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+  let (mut a, mut b) = (1u32, 0u32);
+  for byte in data {
+    a = (a + *byte as u32) % 65521;
+    b = (b + a) % 65521;
+  }
+  b << 16 | a
+}
+
+struct BitWriter {
+  bytes: Vec<u8>,
+  bits: u32,
+  count: u32,
+}
+
+impl BitWriter {
+  // Writes the low `count` bits of `value`, least significant first.
+  fn write(&mut self, value: u32, count: u32) {
+    self.bits |= value << self.count;
+    self.count += count;
+    while self.count >= 8 {
+      self.bytes.push(self.bits as u8);
+      self.bits >>= 8;
+      self.count -= 8;
+    }
+  }
+
+  // Huffman codes go out most significant bit first.
+  fn write_code(&mut self, code: u32, length: u32) {
+    let reversed = code.reverse_bits() >> (32 - length);
+    self.write(reversed, length);
+  }
+
+  fn finish(mut self) -> Vec<u8> {
+    if self.count > 0 {
+      self.bytes.push(self.bits as u8);
+    }
+    self.bytes
+  }
+}
+
+// Literal/length symbol in the fixed Huffman code.
+fn write_symbol(writer: &mut BitWriter, symbol: u32) {
+  match symbol {
+    0..=143 => writer.write_code(0x30 + symbol, 8),
+    144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+    256..=279 => writer.write_code(symbol - 256, 7),
+    _ => writer.write_code(0xc0 + symbol - 280, 8),
+  }
+}
+
+// Compresses `data` into a zlib stream of one fixed Huffman block, finding
+// repeats through the last position each three byte sequence was seen at.
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+  let mut writer = BitWriter {
+    bytes: vec![0x78, 0x01],
+    bits: 0,
+    count: 0,
+  };
+  writer.write(1, 1);
+  writer.write(1, 2);
+  let mut last: HashMap<[u8; 3], usize> = HashMap::new();
+  let mut i = 0;
+  while i < data.len() {
+    let mut length = 0;
+    let mut distance = 0;
+    if i + 3 <= data.len() {
+      let key = [data[i], data[i + 1], data[i + 2]];
+      if let Some(&start) = last.get(&key) {
+        if i - start <= WINDOW {
+          let max = (data.len() - i).min(MAX_MATCH);
+          length = (0..max)
+            .take_while(|k| data[start + k] == data[i + k])
+            .count();
+          distance = i - start;
+        }
+      }
+      last.insert(key, i);
+    }
+    if length < 3 {
+      write_symbol(&mut writer, data[i] as u32);
+      i += 1;
+      continue;
+    }
+    let code = LENGTH_BASE
+      .iter()
+      .rposition(|base| *base as usize <= length)
+      .unwrap();
+    write_symbol(&mut writer, 257 + code as u32);
+    writer.write(
+      (length - LENGTH_BASE[code] as usize) as u32,
+      LENGTH_EXTRA[code] as u32,
+    );
+    let code = DISTANCE_BASE
+      .iter()
+      .rposition(|base| *base as usize <= distance)
+      .unwrap();
+    writer.write_code(code as u32, 5);
+    writer.write(
+      (distance - DISTANCE_BASE[code] as usize) as u32,
+      DISTANCE_EXTRA[code] as u32,
+    );
+    for j in i + 1..(i + length).min(data.len().saturating_sub(2)) {
+      last.insert([data[j], data[j + 1], data[j + 2]], j);
+    }
+    i += length;
+  }
+  write_symbol(&mut writer, 256);
+  let mut bytes = writer.finish();
+  bytes.extend_from_slice(&adler32(data).to_be_bytes());
+  bytes
+}
+
+struct BitReader<'a> {
+  data: &'a [u8],
+  pos: usize,
+  bit: u32,
+}
+
+impl BitReader<'_> {
+  fn bits(&mut self, count: u32) -> Result<u32, String> {
+    let mut value = 0;
+    for i in 0..count {
+      let byte = *self.data.get(self.pos).ok_or("truncated deflate stream")?;
+      value |= ((byte >> self.bit) as u32 & 1) << i;
+      self.bit += 1;
+      if self.bit == 8 {
+        self.bit = 0;
+        self.pos += 1;
+      }
+    }
+    Ok(value)
+  }
+
+  fn align(&mut self) {
+    if self.bit > 0 {
+      self.bit = 0;
+      self.pos += 1;
+    }
+  }
+}
+
+// A canonical Huffman code as symbol lists per code length.
+struct Huffman {
+  counts: [u16; 16],
+  symbols: Vec<u16>,
+}
+
+impl Huffman {
+  fn new(lengths: &[u8]) -> Self {
+    let mut counts = [0; 16];
+    for length in lengths {
+      counts[*length as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut offsets = [0; 16];
+    for length in 1..16 {
+      offsets[length] = offsets[length - 1] + counts[length - 1];
+    }
+    let mut symbols = vec![0; lengths.len()];
+    for (symbol, length) in lengths.iter().enumerate() {
+      if *length > 0 {
+        symbols[offsets[*length as usize] as usize] = symbol as u16;
+        offsets[*length as usize] += 1;
+      }
+    }
+    Huffman { counts, symbols }
+  }
+
+  fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+    let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+    for length in 1..16 {
+      code |= reader.bits(1)? as i32;
+      let count = self.counts[length] as i32;
+      if code - first < count {
+        return Ok(self.symbols[(index + code - first) as usize]);
+      }
+      index += count;
+      first = (first + count) << 1;
+      code <<= 1;
+    }
+    Err("invalid Huffman code".to_string())
+  }
+}
+
+// Decompresses a zlib stream.
+pub fn inflate(zlib: &[u8]) -> Result<Vec<u8>, String> {
+  if zlib.len() < 2 || zlib[0] & 0x0f != 8 {
+    return Err("not a deflate stream".to_string());
+  }
+  let mut reader = BitReader {
+    data: &zlib[2..],
+    pos: 0,
+    bit: 0,
+  };
+  let mut out = Vec::new();
+  loop {
+    let last = reader.bits(1)? == 1;
+    match reader.bits(2)? {
+      0 => {
+        reader.align();
+        let header = reader
+          .data
+          .get(reader.pos..reader.pos + 4)
+          .ok_or("truncated stored block")?;
+        let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+        reader.pos += 4;
+        let block = reader
+          .data
+          .get(reader.pos..reader.pos + length)
+          .ok_or("truncated stored block")?;
+        out.extend_from_slice(block);
+        reader.pos += length;
+      }
+      1 => {
+        let mut lengths = [0; 288];
+        for (symbol, length) in lengths.iter_mut().enumerate() {
+          *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+          };
+        }
+        let literals = Huffman::new(&lengths);
+        let distances = Huffman::new(&[5; 30]);
+        inflate_block(&mut reader, &literals, &distances, &mut out)?;
+      }
+      2 => {
+        let (literals, distances) = dynamic_codes(&mut reader)?;
+        inflate_block(&mut reader, &literals, &distances, &mut out)?;
+      }
+      _ => return Err("invalid deflate block type".to_string()),
+    }
+    if last {
+      return Ok(out);
+    }
+  }
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+  let literal_count = reader.bits(5)? as usize + 257;
+  let distance_count = reader.bits(5)? as usize + 1;
+  let code_length_count = reader.bits(4)? as usize + 4;
+  let mut code_lengths = [0; 19];
+  for i in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+    code_lengths[*i] = reader.bits(3)? as u8;
+  }
+  let code_lengths = Huffman::new(&code_lengths);
+  let mut lengths = Vec::new();
+  while lengths.len() < literal_count + distance_count {
+    let (value, repeat) = match code_lengths.decode(reader)? {
+      symbol @ 0..=15 => (symbol as u8, 1),
+      16 => (
+        *lengths.last().ok_or("repeat with no previous length")?,
+        3 + reader.bits(2)?,
+      ),
+      17 => (0, 3 + reader.bits(3)?),
+      _ => (0, 11 + reader.bits(7)?),
+    };
+    lengths.extend(std::iter::repeat_n(value, repeat as usize));
+  }
+  if lengths.len() > literal_count + distance_count {
+    return Err("too many code lengths".to_string());
+  }
+  Ok((
+    Huffman::new(&lengths[..literal_count]),
+    Huffman::new(&lengths[literal_count..]),
+  ))
+}
+
+fn inflate_block(
+  reader: &mut BitReader,
+  literals: &Huffman,
+  distances: &Huffman,
+  out: &mut Vec<u8>,
+) -> Result<(), String> {
+  loop {
+    let symbol = literals.decode(reader)? as usize;
+    match symbol {
+      0..=255 => out.push(symbol as u8),
+      256 => return Ok(()),
+      _ => {
+        let code = symbol - 257;
+        let base = *LENGTH_BASE.get(code).ok_or("invalid length code")? as usize;
+        let length = base + reader.bits(LENGTH_EXTRA[code] as u32)? as usize;
+        let code = distances.decode(reader)? as usize;
+        let base = *DISTANCE_BASE.get(code).ok_or("invalid distance code")? as usize;
+        let distance = base + reader.bits(DISTANCE_EXTRA[code] as u32)? as usize;
+        if distance > out.len() {
+          return Err("distance before the start of the output".to_string());
+        }
+        let start = out.len() - distance;
+        for i in 0..length {
+          out.push(out[start + i]);
+        }
+      }
+    }
+  }
+}
+
+// Decodes a non-interlaced PNG of any color type to gray levels.
+pub fn decode(png: &[u8]) -> Result<Image, String> {
+  if !png.starts_with(&SIGNATURE) {
+    return Err("not a PNG file".to_string());
+  }
+  let mut pos = SIGNATURE.len();
+  let mut header = None;
+  let mut palette = Vec::new();
+  let mut data = Vec::new();
+  while pos + 8 <= png.len() {
+    let length = u32::from_be_bytes([png[pos], png[pos + 1], png[pos + 2], png[pos + 3]]) as usize;
+    let kind = &png[pos + 4..pos + 8];
+    let body = png
+      .get(pos + 8..pos + 8 + length)
+      .ok_or("truncated PNG chunk")?;
+    match kind {
+      b"IHDR" if length >= 13 => header = Some(body.to_vec()),
+      b"PLTE" => {
+        if !length.is_multiple_of(3) || length < 6 {
+          return Err(format!("invalid PNG palette of {} bytes", length));
+        }
+        palette = body.chunks(3).map(|rgb| rgb.to_vec()).collect();
+      }
+      b"IDAT" => data.extend_from_slice(body),
+      b"IEND" => break,
+      _ => (),
+    }
+    pos += 12 + length;
+  }
+  let header = header.ok_or("PNG without a header")?;
+  let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+  let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+  let (depth, color) = (header[8] as usize, header[9]);
+  if header[12] != 0 {
+    return Err("interlaced PNGs are not supported".to_string());
+  }
+  let samples = match color {
+    0 | 3 => 1,
+    2 => 3,
+    4 => 2,
+    6 => 4,
+    _ => return Err(format!("invalid PNG color type {}", color)),
+  };
+  let bits = samples * depth;
+  let stride = (width * bits).div_ceil(8);
+  let step = bits.div_ceil(8);
+  let raw = inflate(&data)?;
+  if raw.len() < (stride + 1) * height {
+    return Err("PNG image data is too short".to_string());
+  }
+
+  let mut rows = vec![0u8; stride * height];
+  for y in 0..height {
+    let filter = raw[y * (stride + 1)];
+    let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+    for x in 0..stride {
+      let left = if x >= step {
+        rows[y * stride + x - step]
+      } else {
+        0
+      };
+      let up = if y > 0 { rows[(y - 1) * stride + x] } else { 0 };
+      let up_left = if y > 0 && x >= step {
+        rows[(y - 1) * stride + x - step]
+      } else {
+        0
+      };
+      let predicted = match filter {
+        0 => 0,
+        1 => left,
+        2 => up,
+        3 => ((left as u16 + up as u16) / 2) as u8,
+        4 => paeth(left, up, up_left),
+        _ => return Err(format!("invalid PNG filter {}", filter)),
+      };
+      rows[y * stride + x] = line[x].wrapping_add(predicted);
+    }
+  }
+
+  let sample = |row: &[u8], index: usize| -> u8 {
+    match depth {
+      16 => row[index * 2],
+      8 => row[index],
+      _ => {
+        let bit = index * depth;
+        (row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1)
+      }
+    }
+  };
+  let max = (1u32 << depth.min(8)) - 1;
+  let mut gray = Vec::with_capacity(width * height);
+  for row in rows.chunks(stride) {
+    for x in 0..width {
+      let level = match color {
+        3 => {
+          let rgb = palette
+            .get(sample(row, x) as usize)
+            .ok_or("palette index out of range")?;
+          ((rgb[0] as u32 + rgb[1] as u32 + rgb[2] as u32) / 3) as u8
+        }
+        2 | 6 => {
+          let sum: u32 = (0..3).map(|i| sample(row, x * samples + i) as u32).sum();
+          (sum / 3) as u8
+        }
+        _ => (sample(row, x * samples) as u32 * 255 / max) as u8,
+      };
+      gray.push(level);
+    }
+  }
+  Ok(Image {
+    width,
+    height,
+    gray,
+  })
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+  let p = a as i16 + b as i16 - c as i16;
+  let (pa, pb, pc) = (
+    (p - a as i16).abs(),
+    (p - b as i16).abs(),
+    (p - c as i16).abs(),
+  );
+  if pa <= pb && pa <= pc {
+    a
+  } else if pb <= pc {
+    b
+  } else {
+    c
+  }
+}
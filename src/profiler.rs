@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::source_map::SourceMap;
+use crate::{Build, VmParser};
+
+const ROOT: usize = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+  Function(usize),
+  Return,
+  Other,
+}
+
+// A node of the call tree, one per distinct call path.
+#[derive(Debug)]
+struct Node {
+  function: usize,
+  parent: usize,
+  children: Vec<usize>,
+  cycles: u64,
+}
+
+#[derive(Debug)]
+pub struct Profile {
+  pub cycles: u64,
+  pub halted: bool,
+  pub source_map: SourceMap,
+  // Cycles spent in the instructions of each source map entry.
+  pub mapping_cycles: Vec<u64>,
+  names: Vec<String>,
+  calls: Vec<u64>,
+  nodes: Vec<Node>,
+}
+
+// Runs the translated program in the emulator, attributing every cycle to the
+// VM command it executes and to the current call path. The call path grows
+// when execution reaches a `function` command and shrinks when a `return`
+// jumps back to its caller.
+pub fn profile(parsers: Vec<VmParser>, max_cycles: u64) -> Result<Profile, String> {
+  let Build {
+    source_map,
+    mut emulator,
+    ..
+  } = crate::build(parsers, false)?;
+
+  let root = match source_map.lookup(emulator.pc as usize) {
+    Some(mapping) if mapping.file.is_none() => "(bootstrap)",
+    _ => "(outside any function)",
+  };
+  let mut names = vec![root.to_string()];
+  let mut ids: HashMap<&str, usize> = HashMap::new();
+  let mut kinds = Vec::new();
+  let mut owners = vec![usize::MAX; source_map.instruction_count()];
+  for (i, mapping) in source_map.mappings.iter().enumerate() {
+    kinds.push(match (mapping.kind.as_str(), &mapping.function) {
+      ("function", Some(function)) => Kind::Function(*ids.entry(function).or_insert_with(|| {
+        names.push(function.clone());
+        names.len() - 1
+      })),
+      ("return", _) => Kind::Return,
+      _ => Kind::Other,
+    });
+    for owner in &mut owners[mapping.address..mapping.address + mapping.count] {
+      *owner = i;
+    }
+  }
+
+  let mut profile = Profile {
+    cycles: 0,
+    halted: false,
+    mapping_cycles: vec![0; source_map.mappings.len()],
+    calls: vec![0; names.len()],
+    names,
+    nodes: vec![Node {
+      function: ROOT,
+      parent: ROOT,
+      children: Vec::new(),
+      cycles: 0,
+    }],
+    source_map,
+  };
+  let mut node = ROOT;
+  while profile.cycles < max_cycles {
+    let pc = emulator.pc as usize;
+    let owner = match owners.get(pc) {
+      Some(owner) if !emulator.is_halted() => *owner,
+      _ => {
+        profile.halted = true;
+        break;
+      }
+    };
+    if let Kind::Function(function) = kinds[owner] {
+      if pc == profile.source_map.mappings[owner].address {
+        profile.calls[function] += 1;
+        node = profile.child(node, function);
+      }
+    }
+    emulator.step()?;
+    profile.cycles += 1;
+    profile.mapping_cycles[owner] += 1;
+    profile.nodes[node].cycles += 1;
+    if kinds[owner] == Kind::Return && owners.get(emulator.pc as usize) != Some(&owner) {
+      node = profile.nodes[node].parent;
+    }
+  }
+  Ok(profile)
+}
+
+impl Profile {
+  fn child(&mut self, node: usize, function: usize) -> usize {
+    if let Some(child) = self.nodes[node]
+      .children
+      .iter()
+      .find(|child| self.nodes[**child].function == function)
+    {
+      return *child;
+    }
+    self.nodes.push(Node {
+      function,
+      parent: node,
+      children: Vec::new(),
+      cycles: 0,
+    });
+    let child = self.nodes.len() - 1;
+    self.nodes[node].children.push(child);
+    child
+  }
+
+  // Cycles of each node and everything it called. Children always come after
+  // their parents, so one backwards pass adds them up.
+  fn inclusive(&self) -> Vec<u64> {
+    let mut inclusive: Vec<u64> = self.nodes.iter().map(|node| node.cycles).collect();
+    for node in (1..self.nodes.len()).rev() {
+      inclusive[self.nodes[node].parent] += inclusive[node];
+    }
+    inclusive
+  }
+
+  // The call path leading to `node`, outermost function first.
+  fn path(&self, mut node: usize) -> Vec<usize> {
+    let mut path = vec![self.nodes[node].function];
+    while node != ROOT {
+      node = self.nodes[node].parent;
+      path.push(self.nodes[node].function);
+    }
+    path.reverse();
+    path
+  }
+
+  fn percent(&self, cycles: u64) -> f64 {
+    if self.cycles == 0 {
+      0.0
+    } else {
+      cycles as f64 * 100.0 / self.cycles as f64
+    }
+  }
+
+  // Self and inclusive cycles per function, then cycles per VM line.
+  pub fn flat(&self) -> String {
+    let mut own = vec![0; self.names.len()];
+    let mut inclusive = vec![0; self.names.len()];
+    for (
+      node,
+      Node {
+        function, cycles, ..
+      },
+    ) in self.nodes.iter().enumerate()
+    {
+      own[*function] += cycles;
+      let mut path = self.path(node);
+      path.sort_unstable();
+      path.dedup();
+      for function in path {
+        inclusive[function] += cycles;
+      }
+    }
+    let mut functions: Vec<usize> = (0..self.names.len())
+      .filter(|function| inclusive[*function] > 0)
+      .collect();
+    functions.sort_by(|a, b| {
+      own[*b]
+        .cmp(&own[*a])
+        .then(self.names[*a].cmp(&self.names[*b]))
+    });
+
+    let mut report = format!(
+      "Total: {} cycles{}\n\n{:<40}{:>12}{:>8}{:>12}{:>8}{:>10}\n",
+      self.cycles,
+      if self.halted { "" } else { " (cycle limit)" },
+      "Function",
+      "self",
+      "%",
+      "inclusive",
+      "%",
+      "calls"
+    );
+    for function in functions {
+      writeln!(
+        report,
+        "{:<40}{:>12}{:>7.1}%{:>12}{:>7.1}%{:>10}",
+        self.names[function],
+        own[function],
+        self.percent(own[function]),
+        inclusive[function],
+        self.percent(inclusive[function]),
+        self.calls[function]
+      )
+      .unwrap();
+    }
+
+    let mut lines: Vec<usize> = (0..self.mapping_cycles.len())
+      .filter(|i| self.mapping_cycles[*i] > 0)
+      .collect();
+    lines.sort_by(|a, b| {
+      self.mapping_cycles[*b]
+        .cmp(&self.mapping_cycles[*a])
+        .then(a.cmp(b))
+    });
+    writeln!(report, "\n{:<60}{:>12}{:>8}", "Line", "cycles", "%").unwrap();
+    for i in lines {
+      let mapping = &self.source_map.mappings[i];
+      let line = match &mapping.file {
+        Some(file) => format!("{}:{}: {}", file, mapping.line, mapping.command),
+        None => mapping.command.clone(),
+      };
+      writeln!(
+        report,
+        "{:<60}{:>12}{:>7.1}%",
+        line,
+        self.mapping_cycles[i],
+        self.percent(self.mapping_cycles[i])
+      )
+      .unwrap();
+    }
+    report
+  }
+
+  // Inclusive and self cycles along every call path, callees indented under
+  // their callers and sorted by inclusive cycles.
+  pub fn call_tree(&self) -> String {
+    let mut report = format!(
+      "{:>12}{:>8}{:>12}  {}\n",
+      "inclusive", "%", "self", "Function"
+    );
+    self.write_tree(&mut report, &self.inclusive(), ROOT, 0);
+    report
+  }
+
+  fn write_tree(&self, report: &mut String, inclusive: &[u64], node: usize, depth: usize) {
+    writeln!(
+      report,
+      "{:>12}{:>7.1}%{:>12}  {:indent$}{}",
+      inclusive[node],
+      self.percent(inclusive[node]),
+      self.nodes[node].cycles,
+      "",
+      self.names[self.nodes[node].function],
+      indent = depth * 2
+    )
+    .unwrap();
+    let mut children = self.nodes[node].children.clone();
+    children.sort_by_key(|child| std::cmp::Reverse(inclusive[*child]));
+    for child in children {
+      self.write_tree(report, inclusive, child, depth + 1);
+    }
+  }
+
+  // One `caller;callee cycles` line per call path, the format flame graph
+  // tools read.
+  pub fn folded(&self) -> String {
+    let mut folded = String::new();
+    for (node, Node { cycles, .. }) in self.nodes.iter().enumerate() {
+      if *cycles == 0 {
+        continue;
+      }
+      let path: Vec<&str> = self
+        .path(node)
+        .iter()
+        .map(|function| self.names[*function].as_str())
+        .collect();
+      writeln!(folded, "{} {}", path.join(";"), cycles).unwrap();
+    }
+    folded
+  }
+}
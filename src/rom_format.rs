@@ -0,0 +1,106 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RomFormat {
+  Hack,
+  BinLe,
+  BinBe,
+  IntelHex,
+  ReadMemB,
+  ReadMemH,
+  Logisim,
+}
+
+impl RomFormat {
+  pub fn extension(self) -> &'static str {
+    match self {
+      RomFormat::Hack => "hack",
+      RomFormat::BinLe => "le.bin",
+      RomFormat::BinBe => "be.bin",
+      RomFormat::IntelHex => "hex",
+      RomFormat::ReadMemB => "memb",
+      RomFormat::ReadMemH => "memh",
+      RomFormat::Logisim => "rom",
+    }
+  }
+
+  pub fn encode(self, rom: &[u16]) -> Vec<u8> {
+    match self {
+      RomFormat::Hack | RomFormat::ReadMemB => rom
+        .iter()
+        .map(|word| format!("{:016b}\n", word))
+        .collect::<String>()
+        .into_bytes(),
+      RomFormat::ReadMemH => rom
+        .iter()
+        .map(|word| format!("{:04x}\n", word))
+        .collect::<String>()
+        .into_bytes(),
+      RomFormat::BinLe => rom.iter().flat_map(|word| word.to_le_bytes()).collect(),
+      RomFormat::BinBe => rom.iter().flat_map(|word| word.to_be_bytes()).collect(),
+      RomFormat::IntelHex => intel_hex(rom).into_bytes(),
+      RomFormat::Logisim => logisim(rom).into_bytes(),
+    }
+  }
+}
+
+// Data records of 8 big-endian words each, addressed by byte. The Hack ROM is
+// exactly 64K bytes so no extended address records are needed.
+fn intel_hex(rom: &[u16]) -> String {
+  let mut hex = String::new();
+  for (i, chunk) in rom.chunks(8).enumerate() {
+    let address = (i * 16) as u16;
+    let data: Vec<u8> = chunk.iter().flat_map(|word| word.to_be_bytes()).collect();
+    let mut record = vec![data.len() as u8, (address >> 8) as u8, address as u8, 0x00];
+    record.extend(&data);
+    let checksum = record
+      .iter()
+      .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+      .wrapping_neg();
+    record.push(checksum);
+    hex.push(':');
+    record
+      .iter()
+      .for_each(|byte| hex.push_str(&format!("{:02X}", byte)));
+    hex.push('\n');
+  }
+  hex.push_str(":00000001FF\n");
+  hex
+}
+
+// Logisim "v2.0 raw" memory image, with runs of repeated words written as
+// `count*value` the way Logisim itself saves them.
+fn logisim(rom: &[u16]) -> String {
+  let mut entries = Vec::new();
+  let mut i = 0;
+  while i < rom.len() {
+    let run = rom[i..].iter().take_while(|word| **word == rom[i]).count();
+    if run >= 4 {
+      entries.push(format!("{}*{:x}", run, rom[i]));
+      i += run;
+    } else {
+      entries.push(format!("{:x}", rom[i]));
+      i += 1;
+    }
+  }
+  let mut image = "v2.0 raw\n".to_string();
+  for line in entries.chunks(8) {
+    image.push_str(&line.join(" "));
+    image.push('\n');
+  }
+  image
+}
+
+impl std::str::FromStr for RomFormat {
+  type Err = String;
+  fn from_str(str: &str) -> Result<Self, Self::Err> {
+    match str {
+      "hack" => Ok(Self::Hack),
+      "bin-le" => Ok(Self::BinLe),
+      "bin-be" => Ok(Self::BinBe),
+      "ihex" => Ok(Self::IntelHex),
+      "readmemb" => Ok(Self::ReadMemB),
+      "readmemh" => Ok(Self::ReadMemH),
+      "logisim" => Ok(Self::Logisim),
+      _ => Err(format!("unknown rom format {}", str)),
+    }
+  }
+}
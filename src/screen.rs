@@ -0,0 +1,207 @@
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::emulator::{KBD, SCREEN};
+use crate::png;
+
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 256;
+// Differing pixels listed in a comparison report.
+const LISTED_DIFFERENCES: usize = 10;
+
+// The Hack screen, one entry per pixel row by row, true for black.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Screen {
+  pixels: Vec<bool>,
+}
+
+impl Screen {
+  // Reads the screen memory map out of RAM. Each row is 32 words, and the
+  // least significant bit of a word is its leftmost pixel.
+  pub fn new(ram: &[u16]) -> Self {
+    let memory = &ram[SCREEN..KBD];
+    let pixels = (0..WIDTH * HEIGHT)
+      .map(|i| memory[i / 16] >> (i % 16) & 1 == 1)
+      .collect();
+    Screen { pixels }
+  }
+
+  pub fn pixels(&self) -> &[bool] {
+    &self.pixels
+  }
+
+  pub fn pixel(&self, x: usize, y: usize) -> bool {
+    self.pixels[y * WIDTH + x]
+  }
+
+  // Binary (P4) PBM, where a set bit is black.
+  pub fn to_pbm(&self) -> Vec<u8> {
+    let mut pbm = format!("P4\n{} {}\n", WIDTH, HEIGHT).into_bytes();
+    for byte in self.pixels.chunks(8) {
+      pbm.push(
+        byte
+          .iter()
+          .enumerate()
+          .fold(0, |bits, (i, black)| bits | (*black as u8) << (7 - i)),
+      );
+    }
+    pbm
+  }
+
+  pub fn to_png(&self) -> Vec<u8> {
+    let white: Vec<bool> = self.pixels.iter().map(|black| !black).collect();
+    png::encode_gray1(WIDTH, HEIGHT, &white)
+  }
+
+  // Parses a PBM (plain or binary) or PNG image of the screen's size. PNG
+  // pixels darker than mid gray are black.
+  pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+    let (width, height, pixels) = if bytes.starts_with(b"P1") || bytes.starts_with(b"P4") {
+      parse_pbm(bytes)?
+    } else {
+      let image = png::decode(bytes)?;
+      let pixels = image.gray.iter().map(|level| *level < 128).collect();
+      (image.width, image.height, pixels)
+    };
+    if (width, height) != (WIDTH, HEIGHT) {
+      return Err(format!(
+        "image is {}x{}, the screen is {}x{}",
+        width, height, WIDTH, HEIGHT
+      ));
+    }
+    Ok(Screen { pixels })
+  }
+
+  pub fn load(path: &Path) -> Result<Self, String> {
+    let bytes = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Screen::parse(&bytes).map_err(|err| format!("{}: {}", path.display(), err))
+  }
+
+  // Writes a `.pbm` or `.png` file, depending on the extension.
+  pub fn save(&self, path: &Path) -> Result<(), String> {
+    let bytes = match path.extension().and_then(|str| str.to_str()) {
+      Some("pbm") => self.to_pbm(),
+      Some("png") => self.to_png(),
+      _ => return Err(format!("{}: expected a .pbm or .png file", path.display())),
+    };
+    fs::write(path, bytes).map_err(|err| format!("{}: {}", path.display(), err))
+  }
+
+  // Coordinates of the pixels that differ from `other`.
+  pub fn diff(&self, other: &Screen) -> Vec<(usize, usize)> {
+    (0..WIDTH * HEIGHT)
+      .filter(|i| self.pixels[*i] != other.pixels[*i])
+      .map(|i| (i % WIDTH, i / WIDTH))
+      .collect()
+  }
+}
+
+// Compares the screen against a golden image. On a mismatch the rendered
+// screen is saved next to the golden one as `<name>.actual.<ext>`, and the
+// error describes where they differ.
+pub fn compare(actual: &Screen, golden: &Path) -> Result<(), String> {
+  let expected = Screen::load(golden)?;
+  let differences = actual.diff(&expected);
+  if differences.is_empty() {
+    return Ok(());
+  }
+  let actual_path = actual_path(golden);
+  actual.save(&actual_path)?;
+
+  let (x0, x1) = bounds(differences.iter().map(|(x, _)| *x));
+  let (y0, y1) = bounds(differences.iter().map(|(_, y)| *y));
+  let mut report = format!(
+    "{} pixels differ from {} within x {}..={}, y {}..={} (actual screen saved to {})\n",
+    differences.len(),
+    golden.display(),
+    x0,
+    x1,
+    y0,
+    y1,
+    actual_path.display()
+  );
+  let color = |black| if black { "black" } else { "white" };
+  for (x, y) in differences.iter().take(LISTED_DIFFERENCES) {
+    writeln!(
+      report,
+      "  ({}, {}): expected {}, got {}",
+      x,
+      y,
+      color(expected.pixel(*x, *y)),
+      color(actual.pixel(*x, *y))
+    )
+    .unwrap();
+  }
+  if differences.len() > LISTED_DIFFERENCES {
+    writeln!(report, "  ...").unwrap();
+  }
+  Err(report)
+}
+
+fn actual_path(golden: &Path) -> PathBuf {
+  let stem = golden.file_stem().unwrap_or_default().to_string_lossy();
+  let extension = golden.extension().unwrap_or_default().to_string_lossy();
+  golden.with_file_name(format!("{}.actual.{}", stem, extension))
+}
+
+fn bounds(values: impl Iterator<Item = usize>) -> (usize, usize) {
+  values.fold((usize::MAX, 0), |(min, max), value| {
+    (min.min(value), max.max(value))
+  })
+}
+
+// Header fields are separated by whitespace, and `#` starts a comment.
+fn parse_pbm(bytes: &[u8]) -> Result<(usize, usize, Vec<bool>), String> {
+  let mut pos = 2;
+  let field = |pos: &mut usize| -> Result<usize, String> {
+    loop {
+      match bytes.get(*pos) {
+        Some(b'#') => {
+          while bytes.get(*pos).is_some_and(|byte| *byte != b'\n') {
+            *pos += 1;
+          }
+        }
+        Some(byte) if byte.is_ascii_whitespace() => *pos += 1,
+        _ => break,
+      }
+    }
+    let start = *pos;
+    while bytes.get(*pos).is_some_and(u8::is_ascii_digit) {
+      *pos += 1;
+    }
+    std::str::from_utf8(&bytes[start..*pos])
+      .unwrap()
+      .parse()
+      .map_err(|_| "invalid PBM header".to_string())
+  };
+  let width = field(&mut pos)?;
+  let height = field(&mut pos)?;
+  let mut pixels = Vec::with_capacity(width * height);
+  if bytes[1] == b'4' {
+    // Exactly one whitespace character precedes the raster.
+    let stride = width.div_ceil(8);
+    let raster = bytes
+      .get(pos + 1..pos + 1 + stride * height)
+      .ok_or("truncated PBM raster")?;
+    for row in raster.chunks(stride) {
+      pixels.extend((0..width).map(|x| row[x / 8] >> (7 - x % 8) & 1 == 1));
+    }
+  } else {
+    let mut comment = false;
+    for byte in &bytes[pos..] {
+      match byte {
+        b'#' => comment = true,
+        b'\n' => comment = false,
+        b'0' if !comment => pixels.push(false),
+        b'1' if !comment => pixels.push(true),
+        _ => (),
+      }
+    }
+    if pixels.len() < width * height {
+      return Err("truncated PBM raster".to_string());
+    }
+    pixels.truncate(width * height);
+  }
+  Ok((width, height, pixels))
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::hack;
+use crate::source_map::SourceMap;
+
+fn section(report: &mut String, title: &str, sizes: HashMap<&str, (usize, usize)>, total: usize) {
+  let mut sizes: Vec<(&str, (usize, usize))> = sizes.into_iter().collect();
+  sizes.sort_by(|(a_name, (a_size, _)), (b_name, (b_size, _))| {
+    b_size.cmp(a_size).then(a_name.cmp(b_name))
+  });
+  writeln!(
+    report,
+    "\n{:<48}{:>10}{:>8}{:>10}",
+    title, "size", "%", "commands"
+  )
+  .unwrap();
+  for (name, (size, commands)) in sizes {
+    writeln!(
+      report,
+      "{:<48}{:>10}{:>7.1}%{:>10}",
+      name,
+      size,
+      percent(size, total),
+      commands
+    )
+    .unwrap();
+  }
+}
+
+fn percent(size: usize, total: usize) -> f64 {
+  if total == 0 {
+    0.0
+  } else {
+    size as f64 * 100.0 / total as f64
+  }
+}
+
+// Instruction counts aggregated by VM function, file and command kind.
+pub fn size_report(source_map: &SourceMap) -> String {
+  let mut functions = HashMap::new();
+  let mut files = HashMap::new();
+  let mut kinds = HashMap::new();
+  for mapping in &source_map.mappings {
+    let outside = if mapping.kind == "checks" {
+      "(runtime checks)"
+    } else {
+      "(bootstrap)"
+    };
+    let function = match (&mapping.file, &mapping.function) {
+      (None, _) => outside,
+      (Some(_), None) => "(outside any function)",
+      (Some(_), Some(function)) => function,
+    };
+    let file = mapping.file.as_deref().unwrap_or(outside);
+    for (sizes, name) in &mut [
+      (&mut functions, function),
+      (&mut files, file),
+      (&mut kinds, mapping.kind.as_str()),
+    ] {
+      let entry = sizes.entry(*name).or_insert((0, 0));
+      entry.0 += mapping.count;
+      entry.1 += 1;
+    }
+  }
+
+  let total = source_map.instruction_count();
+  let mut report = format!(
+    "Total: {} instructions, {:.1}% of the {} word Hack ROM\n",
+    total,
+    percent(total, hack::ROM_SIZE),
+    hack::ROM_SIZE
+  );
+  section(&mut report, "Function", functions, total);
+  section(&mut report, "File", files, total);
+  section(&mut report, "Command", kinds, total);
+  report
+}
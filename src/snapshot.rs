@@ -0,0 +1,132 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+use crate::emulator::{Emulator, RAM_SIZE};
+use crate::hack;
+use crate::keyboard::KeyboardScript;
+use crate::png;
+
+const MAGIC: &[u8; 8] = b"HACKSNAP";
+const VERSION: u16 = 1;
+
+// The full state of an emulator run, to pick it up again later: the program,
+// RAM, registers, cycle count and how far the keyboard script got.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+  pub rom: Vec<u16>,
+  pub ram: Vec<u16>,
+  pub a: u16,
+  pub d: u16,
+  pub pc: u16,
+  pub cycles: u64,
+  // The number of keyboard script events already applied.
+  pub keyboard: usize,
+}
+
+impl Snapshot {
+  pub fn new(emulator: &Emulator, keyboard: Option<&KeyboardScript>) -> Self {
+    Snapshot {
+      rom: emulator.rom[..emulator.size].to_vec(),
+      ram: emulator.ram.clone(),
+      a: emulator.a,
+      d: emulator.d,
+      pc: emulator.pc,
+      cycles: emulator.cycles,
+      keyboard: keyboard.map_or(0, KeyboardScript::position),
+    }
+  }
+
+  // An emulator in the saved state. Trap checks and loop detection start
+  // over, off.
+  pub fn emulator(&self) -> Result<Emulator, String> {
+    let mut emulator = Emulator::new(&self.rom)?;
+    emulator.ram.copy_from_slice(&self.ram);
+    emulator.a = self.a;
+    emulator.d = self.d;
+    emulator.pc = self.pc;
+    emulator.cycles = self.cycles;
+    Ok(emulator)
+  }
+
+  // Puts `keyboard` back where the snapshot left it. A snapshot taken
+  // partway through a script can't resume without it.
+  pub fn restore_keyboard(&self, keyboard: Option<&mut KeyboardScript>) -> Result<(), String> {
+    match keyboard {
+      Some(keyboard) => keyboard.seek(self.keyboard),
+      None if self.keyboard > 0 => Err(format!(
+        "snapshot was taken {} events into a keyboard script, which is needed to resume it",
+        self.keyboard
+      )),
+      None => Ok(()),
+    }
+  }
+
+  // The magic bytes, version, registers, cycle count, keyboard position and
+  // program length, followed by the ROM and RAM words zlib compressed. Numbers
+  // are little endian.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    for register in [self.a, self.d, self.pc] {
+      bytes.extend_from_slice(&register.to_le_bytes());
+    }
+    bytes.extend_from_slice(&self.cycles.to_le_bytes());
+    bytes.extend_from_slice(&(self.keyboard as u32).to_le_bytes());
+    bytes.extend_from_slice(&(self.rom.len() as u32).to_le_bytes());
+    let words: Vec<u8> = self
+      .rom
+      .iter()
+      .chain(&self.ram)
+      .flat_map(|word| word.to_le_bytes())
+      .collect();
+    bytes.extend(png::zlib(&words));
+    bytes
+  }
+
+  pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+    let header = MAGIC.len() + 24;
+    if bytes.len() < header || &bytes[..MAGIC.len()] != MAGIC {
+      return Err("not an emulator snapshot".to_string());
+    }
+    let field = |start: usize, len: usize| &bytes[MAGIC.len() + start..MAGIC.len() + start + len];
+    let word = |start| u16::from_le_bytes(field(start, 2).try_into().unwrap());
+    let version = word(0);
+    if version != VERSION {
+      return Err(format!("unsupported snapshot version {}", version));
+    }
+    let size = u32::from_le_bytes(field(20, 4).try_into().unwrap()) as usize;
+    if size > hack::ROM_SIZE {
+      return Err(format!("snapshot has a program of {} instructions", size));
+    }
+    let words = png::inflate(&bytes[header..])?;
+    if words.len() != (size + RAM_SIZE) * 2 {
+      return Err(format!(
+        "snapshot holds {} bytes of ROM and RAM, expected {}",
+        words.len(),
+        (size + RAM_SIZE) * 2
+      ));
+    }
+    let mut words = words
+      .chunks(2)
+      .map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+    Ok(Snapshot {
+      rom: words.by_ref().take(size).collect(),
+      ram: words.collect(),
+      a: word(2),
+      d: word(4),
+      pc: word(6),
+      cycles: u64::from_le_bytes(field(8, 8).try_into().unwrap()),
+      keyboard: u32::from_le_bytes(field(16, 4).try_into().unwrap()) as usize,
+    })
+  }
+
+  pub fn save(&self, path: &Path) -> Result<(), String> {
+    fs::write(path, self.to_bytes()).map_err(|err| format!("{}: {}", path.display(), err))
+  }
+
+  pub fn load(path: &Path) -> Result<Self, String> {
+    let bytes = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Snapshot::parse(&bytes).map_err(|err| format!("{}: {}", path.display(), err))
+  }
+}
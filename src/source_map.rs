@@ -0,0 +1,95 @@
+use crate::assembler;
+use crate::json::Json;
+
+// The instructions generated for one VM command, or for the bootstrap code
+// when `file` is `None`.
+#[derive(Debug, Clone)]
+pub struct Mapping {
+  pub address: usize,
+  pub count: usize,
+  pub asm_line: usize,
+  pub asm_lines: usize,
+  pub file: Option<String>,
+  pub line: usize,
+  pub function: Option<String>,
+  pub command: String,
+  pub kind: String,
+}
+
+#[derive(Debug, Default)]
+pub struct SourceMap {
+  pub mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+  pub fn push(
+    &mut self,
+    file: Option<&str>,
+    line: usize,
+    function: Option<&str>,
+    command: &str,
+    kind: &str,
+    asm: &str,
+  ) {
+    let (address, asm_line) = self.mappings.last().map_or((0, 1), |last| {
+      (last.address + last.count, last.asm_line + last.asm_lines)
+    });
+    self.mappings.push(Mapping {
+      address,
+      count: assembler::instruction_count(asm),
+      asm_line,
+      asm_lines: asm.lines().count(),
+      file: file.map(String::from),
+      line,
+      function: function.map(String::from),
+      command: command.to_string(),
+      kind: kind.to_string(),
+    });
+  }
+
+  pub fn instruction_count(&self) -> usize {
+    self
+      .mappings
+      .last()
+      .map_or(0, |last| last.address + last.count)
+  }
+
+  // The mapping whose instructions include `address`.
+  pub fn lookup(&self, address: usize) -> Option<&Mapping> {
+    let i = self
+      .mappings
+      .partition_point(|mapping| mapping.address + mapping.count <= address);
+    self
+      .mappings
+      .get(i)
+      .filter(|mapping| mapping.address <= address)
+  }
+
+  pub fn to_json(&self) -> Json {
+    Json::object(vec![
+      ("version", Json::Number(1)),
+      (
+        "mappings",
+        Json::Array(
+          self
+            .mappings
+            .iter()
+            .map(|mapping| {
+              Json::object(vec![
+                ("address", mapping.address.into()),
+                ("count", mapping.count.into()),
+                ("asmLine", mapping.asm_line.into()),
+                ("asmLines", mapping.asm_lines.into()),
+                ("file", mapping.file.clone().into()),
+                ("line", mapping.file.as_ref().map(|_| mapping.line).into()),
+                ("function", mapping.function.clone().into()),
+                ("command", mapping.command.as_str().into()),
+                ("kind", mapping.kind.as_str().into()),
+              ])
+            })
+            .collect(),
+        ),
+      ),
+    ])
+  }
+}
@@ -0,0 +1,272 @@
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::emulator::{Emulator, Status, KBD};
+use crate::keyboard::key_code;
+use crate::screen::{Screen, HEIGHT, WIDTH};
+
+pub const DEFAULT_FPS: u32 = 30;
+pub const DEFAULT_SPEED: u64 = 2_000_000;
+// Terminals report presses but not releases, so a key counts as held until
+// this long after its last press; holding a key down keeps it pressed through
+// the terminal's autorepeat.
+const HOLD: Duration = Duration::from_millis(150);
+const CTRL_C: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+  // 2x4 pixels per character, 256x64 characters.
+  Braille,
+  // 2x2 pixels per character, 256x128 characters.
+  Blocks,
+}
+
+impl std::str::FromStr for Style {
+  type Err = String;
+  fn from_str(str: &str) -> Result<Self, Self::Err> {
+    match str {
+      "braille" => Ok(Style::Braille),
+      "blocks" => Ok(Style::Blocks),
+      _ => Err(format!("Unknown style {}, expected braille or blocks", str)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+  pub style: Style,
+  pub fps: u32,
+  // Emulated cycles per second.
+  pub speed: u64,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      style: Style::Braille,
+      fps: DEFAULT_FPS,
+      speed: DEFAULT_SPEED,
+    }
+  }
+}
+
+// Draws the screen with one braille character per 2x4 pixel cell, black
+// pixels as raised dots.
+pub fn braille(screen: &Screen) -> String {
+  // Dot bit of each pixel in a cell, by row and column.
+  const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+  let mut text = String::new();
+  for y in (0..HEIGHT).step_by(4) {
+    for x in (0..WIDTH).step_by(2) {
+      let mut dots = 0;
+      for (dy, row) in DOTS.iter().enumerate() {
+        for (dx, dot) in row.iter().enumerate() {
+          if screen.pixel(x + dx, y + dy) {
+            dots |= dot;
+          }
+        }
+      }
+      text.push(char::from_u32(0x2800 + dots).unwrap());
+    }
+    text.push_str("\r\n");
+  }
+  text
+}
+
+// Draws the screen with one quadrant block character per 2x2 pixel cell.
+pub fn blocks(screen: &Screen) -> String {
+  // Indexed by upper left, upper right, lower left and lower right bits.
+  const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+  ];
+  let mut text = String::new();
+  for y in (0..HEIGHT).step_by(2) {
+    for x in (0..WIDTH).step_by(2) {
+      let index = screen.pixel(x, y) as usize
+        | (screen.pixel(x + 1, y) as usize) << 1
+        | (screen.pixel(x, y + 1) as usize) << 2
+        | (screen.pixel(x + 1, y + 1) as usize) << 3;
+      text.push(QUADRANTS[index]);
+    }
+    text.push_str("\r\n");
+  }
+  text
+}
+
+// Hack key codes for the bytes of one read from a raw mode terminal, which
+// holds one key press or escape sequence, or several typed characters.
+pub fn decode_keys(bytes: &[u8]) -> Vec<u16> {
+  let name = match bytes {
+    [0x1b] => Some("esc"),
+    [0x1b, b'[', b'A'] | [0x1b, b'O', b'A'] => Some("up"),
+    [0x1b, b'[', b'B'] | [0x1b, b'O', b'B'] => Some("down"),
+    [0x1b, b'[', b'C'] | [0x1b, b'O', b'C'] => Some("right"),
+    [0x1b, b'[', b'D'] | [0x1b, b'O', b'D'] => Some("left"),
+    [0x1b, b'[', b'H'] | [0x1b, b'O', b'H'] | [0x1b, b'[', b'1', b'~'] => Some("home"),
+    [0x1b, b'[', b'F'] | [0x1b, b'O', b'F'] | [0x1b, b'[', b'4', b'~'] => Some("end"),
+    [0x1b, b'[', b'2', b'~'] => Some("insert"),
+    [0x1b, b'[', b'3', b'~'] => Some("delete"),
+    [0x1b, b'[', b'5', b'~'] => Some("pageup"),
+    [0x1b, b'[', b'6', b'~'] => Some("pagedown"),
+    [0x1b, b'O', b'P'] => Some("f1"),
+    [0x1b, b'O', b'Q'] => Some("f2"),
+    [0x1b, b'O', b'R'] => Some("f3"),
+    [0x1b, b'O', b'S'] => Some("f4"),
+    [0x1b, b'[', b'1', b'5', b'~'] => Some("f5"),
+    [0x1b, b'[', b'1', b'7', b'~'] => Some("f6"),
+    [0x1b, b'[', b'1', b'8', b'~'] => Some("f7"),
+    [0x1b, b'[', b'1', b'9', b'~'] => Some("f8"),
+    [0x1b, b'[', b'2', b'0', b'~'] => Some("f9"),
+    [0x1b, b'[', b'2', b'1', b'~'] => Some("f10"),
+    [0x1b, b'[', b'2', b'3', b'~'] => Some("f11"),
+    [0x1b, b'[', b'2', b'4', b'~'] => Some("f12"),
+    [0x1b, ..] => return Vec::new(),
+    _ => None,
+  };
+  if let Some(name) = name {
+    return vec![key_code(name).unwrap()];
+  }
+  bytes
+    .iter()
+    .filter_map(|byte| match byte {
+      b'\r' | b'\n' => key_code("newline").ok(),
+      0x08 | 0x7f => key_code("backspace").ok(),
+      b' '..=b'~' => Some(*byte as u16),
+      _ => None,
+    })
+    .collect()
+}
+
+// Puts the terminal in raw mode through `stty` and restores its settings when
+// dropped.
+struct RawMode {
+  saved: String,
+}
+
+impl RawMode {
+  fn enable() -> Result<Self, String> {
+    let saved = stty(&["-g"])?;
+    stty(&["raw", "-echo"])?;
+    Ok(RawMode {
+      saved: saved.trim().to_string(),
+    })
+  }
+}
+
+impl Drop for RawMode {
+  fn drop(&mut self) {
+    let _ = stty(&[&self.saved]);
+  }
+}
+
+fn stty(args: &[&str]) -> Result<String, String> {
+  let output = Command::new("stty")
+    .args(args)
+    .stdin(Stdio::inherit())
+    .output()
+    .map_err(|err| format!("Cannot run stty: {}", err))?;
+  if !output.status.success() {
+    return Err(format!(
+      "stty failed, is standard input a terminal? {}",
+      String::from_utf8_lossy(&output.stderr).trim()
+    ));
+  }
+  Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Reads the terminal on its own thread so the emulator never waits for input.
+fn read_input() -> Receiver<Vec<u8>> {
+  let (sender, receiver) = mpsc::channel();
+  thread::spawn(move || {
+    let mut buffer = [0; 64];
+    let mut stdin = io::stdin();
+    while let Ok(count @ 1..) = stdin.read(&mut buffer) {
+      if sender.send(buffer[..count].to_vec()).is_err() {
+        break;
+      }
+    }
+  });
+  receiver
+}
+
+// Runs the emulator in the terminal until Ctrl-C, drawing the screen every
+// frame it changed and forwarding key presses to KBD.
+pub fn play(emulator: &mut Emulator, options: Options) -> Result<(), String> {
+  let raw_mode = RawMode::enable()?;
+  let input = read_input();
+  let mut out = io::stdout();
+  // Switch to the alternate screen and hide the cursor.
+  write!(out, "\x1b[?1049h\x1b[?25l\x1b[2J").map_err(|err| err.to_string())?;
+  let result = frames(emulator, options, &input, &mut out);
+  write!(out, "\x1b[?25h\x1b[?1049l").map_err(|err| err.to_string())?;
+  out.flush().map_err(|err| err.to_string())?;
+  drop(raw_mode);
+  result
+}
+
+fn frames(
+  emulator: &mut Emulator,
+  options: Options,
+  input: &Receiver<Vec<u8>>,
+  out: &mut impl Write,
+) -> Result<(), String> {
+  let frame = Duration::from_secs(1) / options.fps.max(1);
+  let cycles_per_frame = (options.speed / options.fps.max(1) as u64).max(1);
+  let mut last_screen = None;
+  let mut released_at = Instant::now();
+  let mut status = Status::Running;
+  loop {
+    let start = Instant::now();
+    while let Ok(bytes) = input.try_recv() {
+      if bytes.contains(&CTRL_C) {
+        return Ok(());
+      }
+      if let Some(code) = decode_keys(&bytes).last() {
+        emulator.ram[KBD] = *code;
+        released_at = start + HOLD;
+      }
+    }
+    if start >= released_at {
+      emulator.ram[KBD] = 0;
+    }
+    if status != Status::Halted {
+      status = match emulator.run(cycles_per_frame) {
+        Ok(status) => status,
+        Err(err) => return Err(format!("Error after {} cycles: {}", emulator.cycles, err)),
+      };
+    }
+
+    let screen = Screen::new(&emulator.ram);
+    if last_screen.as_ref() != Some(&screen) {
+      let text = match options.style {
+        Style::Braille => braille(&screen),
+        Style::Blocks => blocks(&screen),
+      };
+      write!(out, "\x1b[H{}", text).map_err(|err| err.to_string())?;
+      last_screen = Some(screen);
+    }
+    let rows = match options.style {
+      Style::Braille => HEIGHT / 4,
+      Style::Blocks => HEIGHT / 2,
+    };
+    write!(
+      out,
+      "\x1b[{};1H\x1b[7m{} cycles{}\x1b[0m  Ctrl-C to quit\x1b[K",
+      rows + 1,
+      emulator.cycles,
+      if status == Status::Halted {
+        ", halted"
+      } else {
+        ""
+      }
+    )
+    .and_then(|_| out.flush())
+    .map_err(|err| err.to_string())?;
+    if let Some(rest) = frame.checked_sub(start.elapsed()) {
+      thread::sleep(rest);
+    }
+  }
+}
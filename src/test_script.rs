@@ -0,0 +1,418 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::emulator::Emulator;
+use crate::hack;
+use crate::{Bootstrap, Build, VmParser};
+
+// Runaway `repeat` loops are cut off after this many iterations.
+const MAX_REPEAT: usize = 100_000_000;
+
+#[derive(Debug)]
+enum Statement {
+  Command(Vec<String>),
+  Repeat(usize, Vec<Statement>),
+}
+
+#[derive(Debug, Clone)]
+struct Column {
+  name: String,
+  format: char,
+  left: usize,
+  width: usize,
+  right: usize,
+}
+
+#[derive(Debug)]
+pub struct ScriptResult {
+  pub output: String,
+  pub output_file: Option<PathBuf>,
+  pub compare_file: Option<PathBuf>,
+  // The first line that differs from the compare file, if any.
+  pub mismatch: Option<Mismatch>,
+}
+
+#[derive(Debug)]
+pub struct Mismatch {
+  pub line: usize,
+  pub expected: String,
+  pub actual: String,
+}
+
+impl ScriptResult {
+  pub fn passed(&self) -> bool {
+    self.mismatch.is_none()
+  }
+}
+
+// Runs a CPU emulator `.tst` script. Loading `Foo.asm` translates `Foo.vm`
+// or the `.vm` files of a `Foo` directory next to the script when they exist,
+// so the script tests this translator's output rather than a stale `.asm`.
+pub fn run_script(path: &Path) -> Result<ScriptResult, String> {
+  let script = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+  let statements = parse(&script)?;
+  let mut runner = Runner {
+    dir: path
+      .parent()
+      .unwrap_or_else(|| Path::new("."))
+      .to_path_buf(),
+    emulator: None,
+    columns: Vec::new(),
+    output: String::new(),
+    output_file: None,
+    compare_file: None,
+  };
+  runner.run(&statements)?;
+
+  let mismatch = match &runner.compare_file {
+    Some(compare_file) => {
+      let expected = fs::read_to_string(compare_file)
+        .map_err(|err| format!("{}: {}", compare_file.display(), err))?;
+      compare(&expected, &runner.output)
+    }
+    None => None,
+  };
+  Ok(ScriptResult {
+    output: runner.output,
+    output_file: runner.output_file,
+    compare_file: runner.compare_file,
+    mismatch,
+  })
+}
+
+// Compares output lines against a compare file, where `*` matches any
+// character.
+fn compare(expected: &str, actual: &str) -> Option<Mismatch> {
+  let expected: Vec<&str> = expected.lines().collect();
+  let actual: Vec<&str> = actual.lines().collect();
+  for i in 0..expected.len().max(actual.len()) {
+    let (expected, actual) = (expected.get(i), actual.get(i));
+    let matches = match (expected, actual) {
+      (Some(expected), Some(actual)) => {
+        expected.len() == actual.len()
+          && expected
+            .chars()
+            .zip(actual.chars())
+            .all(|(e, a)| e == '*' || e == a)
+      }
+      _ => false,
+    };
+    if !matches {
+      return Some(Mismatch {
+        line: i + 1,
+        expected: expected.unwrap_or(&"").to_string(),
+        actual: actual.unwrap_or(&"").to_string(),
+      });
+    }
+  }
+  None
+}
+
+fn strip_comments(script: &str) -> String {
+  let mut stripped = String::new();
+  let mut rest = script;
+  while !rest.is_empty() {
+    if let Some(comment) = rest.strip_prefix("//") {
+      rest = comment.find('\n').map_or("", |end| &comment[end..]);
+    } else if let Some(comment) = rest.strip_prefix("/*") {
+      rest = comment.find("*/").map_or("", |end| &comment[end + 2..]);
+      stripped.push(' ');
+    } else {
+      let c = rest.chars().next().unwrap();
+      stripped.push(c);
+      rest = &rest[c.len_utf8()..];
+    }
+  }
+  stripped
+}
+
+fn parse(script: &str) -> Result<Vec<Statement>, String> {
+  let script = strip_comments(script);
+  let mut tokens = Vec::new();
+  let mut word = String::new();
+  let mut quoted = false;
+  for c in script.chars() {
+    match c {
+      '"' => {
+        quoted = !quoted;
+        word.push(c);
+      }
+      _ if quoted => word.push(c),
+      ',' | ';' | '{' | '}' => {
+        tokens.push(std::mem::take(&mut word));
+        tokens.push(c.to_string());
+      }
+      _ => word.push(c),
+    }
+  }
+  tokens.push(word);
+
+  let mut stack: Vec<(Option<usize>, Vec<Statement>)> = vec![(None, Vec::new())];
+  let mut repeat = None;
+  for token in tokens {
+    let words: Vec<String> = token.split_whitespace().map(String::from).collect();
+    match token.as_str() {
+      "," | ";" => (),
+      "{" => stack.push((Some(repeat.take().ok_or("unexpected {")?), Vec::new())),
+      "}" => {
+        let (count, body) = stack.pop().unwrap();
+        let count = count.ok_or("unexpected }")?;
+        stack
+          .last_mut()
+          .unwrap()
+          .1
+          .push(Statement::Repeat(count, body));
+      }
+      _ if words.is_empty() => (),
+      _ if words[0] == "repeat" => {
+        repeat = Some(match words.get(1) {
+          Some(count) => count
+            .parse()
+            .map_err(|_| format!("invalid repeat count {}", count))?,
+          None => MAX_REPEAT,
+        });
+      }
+      _ => stack.last_mut().unwrap().1.push(Statement::Command(words)),
+    }
+  }
+  if stack.len() != 1 {
+    return Err("missing }".to_string());
+  }
+  Ok(stack.pop().unwrap().1)
+}
+
+struct Runner {
+  dir: PathBuf,
+  emulator: Option<Emulator>,
+  columns: Vec<Column>,
+  output: String,
+  output_file: Option<PathBuf>,
+  compare_file: Option<PathBuf>,
+}
+
+impl Runner {
+  fn run(&mut self, statements: &[Statement]) -> Result<(), String> {
+    for statement in statements {
+      match statement {
+        Statement::Repeat(count, body) => {
+          for _ in 0..*count {
+            self.run(body)?;
+          }
+        }
+        Statement::Command(words) => self
+          .command(words)
+          .map_err(|err| format!("{}: {}", words.join(" "), err))?,
+      }
+    }
+    Ok(())
+  }
+
+  fn emulator(&mut self) -> Result<&mut Emulator, String> {
+    self
+      .emulator
+      .as_mut()
+      .ok_or_else(|| "no program loaded".to_string())
+  }
+
+  fn command(&mut self, words: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = words[1..].iter().map(String::as_str).collect();
+    match (words[0].as_str(), args.as_slice()) {
+      ("load", [file]) => self.emulator = Some(self.load(file)?),
+      ("output-file", [file]) => self.output_file = Some(self.dir.join(file)),
+      ("compare-to", [file]) => self.compare_file = Some(self.dir.join(file)),
+      ("output-list", columns) => {
+        self.columns = columns
+          .iter()
+          .map(|column| parse_column(column))
+          .collect::<Result<_, _>>()?;
+        let header = self
+          .columns
+          .iter()
+          .map(|column| {
+            let width = column.left + column.width + column.right;
+            let name: String = column.name.chars().take(width).collect();
+            let left = (width - name.len()) / 2;
+            format!(
+              "{:left$}{}{:right$}",
+              "",
+              name,
+              "",
+              left = left,
+              right = width - name.len() - left
+            )
+          })
+          .collect::<Vec<String>>();
+        self.output.push_str(&format!("|{}|\n", header.join("|")));
+      }
+      ("output", []) => {
+        let mut values = Vec::new();
+        for column in self.columns.clone() {
+          let value = self.get(&column.name)?;
+          values.push(format_value(&column, value));
+        }
+        self.output.push_str(&format!("|{}|\n", values.join("|")));
+      }
+      ("set", [name, value]) => {
+        let value = parse_value(value)?;
+        self.set(name, value)?;
+      }
+      ("ticktock", []) | ("tock", []) => self.emulator()?.step()?,
+      ("tick", []) | ("echo", _) | ("clear-echo", []) => (),
+      (command, _) => return Err(format!("unsupported command {}", command)),
+    }
+    Ok(())
+  }
+
+  fn load(&self, file: &str) -> Result<Emulator, String> {
+    let path = self.dir.join(file);
+    let stem = path
+      .file_stem()
+      .and_then(|stem| stem.to_str())
+      .unwrap_or("");
+    let vm_file = self.dir.join(stem).with_extension("vm");
+    let is_program_dir = self.dir.file_name().and_then(|name| name.to_str()) == Some(stem);
+    let sources = if vm_file.exists() {
+      Some(vm_file)
+    } else if is_program_dir {
+      Some(self.dir.clone())
+    } else {
+      None
+    };
+    match (path.extension().and_then(|ext| ext.to_str()), sources) {
+      (Some("asm"), Some(sources)) => {
+        let parsers =
+          VmParser::open(&sources).map_err(|err| format!("{}: {}", sources.display(), err))?;
+        let mut asm = Vec::new();
+        let source_map = crate::translate_with(parsers, &mut asm, Bootstrap::WithSysInit)
+          .map_err(|err| err.to_string())?;
+        Ok(Build::new(&String::from_utf8(asm).unwrap(), source_map)?.emulator)
+      }
+      (Some("hack"), _) => {
+        let text =
+          fs::read_to_string(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Emulator::new(&hack::parse_hack(&text)?)
+      }
+      _ => Emulator::load(&path),
+    }
+  }
+
+  fn get(&mut self, name: &str) -> Result<u16, String> {
+    let emulator = self.emulator()?;
+    Ok(match name {
+      "A" => emulator.a,
+      "D" => emulator.d,
+      "PC" => emulator.pc,
+      "time" => emulator.cycles as u16,
+      _ => match parse_indexed(name)? {
+        ("RAM", address) => *emulator
+          .ram
+          .get(address)
+          .ok_or_else(|| format!("RAM[{}] out of range", address))?,
+        ("ROM32K", address) | ("ROM", address) => *emulator
+          .rom
+          .get(address)
+          .ok_or_else(|| format!("ROM[{}] out of range", address))?,
+        _ => return Err(format!("unknown variable {}", name)),
+      },
+    })
+  }
+
+  fn set(&mut self, name: &str, value: u16) -> Result<(), String> {
+    let emulator = self.emulator()?;
+    match name {
+      "A" => emulator.a = value,
+      "D" => emulator.d = value,
+      "PC" => emulator.pc = value,
+      _ => match parse_indexed(name)? {
+        ("RAM", address) => {
+          *emulator
+            .ram
+            .get_mut(address)
+            .ok_or_else(|| format!("RAM[{}] out of range", address))? = value
+        }
+        _ => return Err(format!("cannot set {}", name)),
+      },
+    }
+    Ok(())
+  }
+}
+
+fn parse_indexed(name: &str) -> Result<(&str, usize), String> {
+  let open = name
+    .find('[')
+    .ok_or_else(|| format!("unknown variable {}", name))?;
+  let index = name[open + 1..]
+    .strip_suffix(']')
+    .and_then(|index| index.parse().ok())
+    .ok_or_else(|| format!("invalid index in {}", name))?;
+  Ok((&name[..open], index))
+}
+
+// Values are decimal unless prefixed with %B, %X or %D.
+fn parse_value(value: &str) -> Result<u16, String> {
+  let parsed = if let Some(binary) = value.strip_prefix("%B") {
+    u16::from_str_radix(binary, 2).ok()
+  } else if let Some(hex) = value.strip_prefix("%X") {
+    u16::from_str_radix(hex, 16).ok()
+  } else {
+    value
+      .strip_prefix("%D")
+      .unwrap_or(value)
+      .parse::<i32>()
+      .ok()
+      .filter(|value| (-32768..=65535).contains(value))
+      .map(|value| value as u16)
+  };
+  parsed.ok_or_else(|| format!("invalid value {}", value))
+}
+
+// `name%F<left>.<width>.<right>`, defaulting to `%D1.6.1`.
+fn parse_column(column: &str) -> Result<Column, String> {
+  let (name, format) = match column.find('%') {
+    Some(i) => (&column[..i], &column[i + 1..]),
+    None => (column, "D1.6.1"),
+  };
+  let invalid = || format!("invalid output format {}", column);
+  let mut chars = format.chars();
+  let kind = chars
+    .next()
+    .filter(|c| "BXDS".contains(*c))
+    .ok_or_else(invalid)?;
+  let sizes: Vec<usize> = chars
+    .as_str()
+    .split('.')
+    .map(|size| size.parse().map_err(|_| invalid()))
+    .collect::<Result<_, _>>()?;
+  match sizes.as_slice() {
+    [left, width, right] => Ok(Column {
+      name: name.to_string(),
+      format: kind,
+      left: *left,
+      width: *width,
+      right: *right,
+    }),
+    _ => Err(invalid()),
+  }
+}
+
+fn format_value(column: &Column, value: u16) -> String {
+  let text = match column.format {
+    'B' => format!("{:016b}", value),
+    'X' => format!("{:04X}", value),
+    _ => (value as i16).to_string(),
+  };
+  let text = if text.len() > column.width {
+    text[text.len() - column.width..].to_string()
+  } else {
+    text
+  };
+  format!(
+    "{:left$}{:>width$}{:right$}",
+    "",
+    text,
+    "",
+    left = column.left,
+    width = column.width,
+    right = column.right
+  )
+}
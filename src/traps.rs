@@ -0,0 +1,178 @@
+use std::fmt::{self, Write};
+
+use crate::debugger;
+use crate::emulator::{Emulator, KBD, SCREEN};
+use crate::interpreter::{SP, THAT};
+use crate::source_map::SourceMap;
+
+pub const STACK_START: u16 = 256;
+pub const STACK_END: u16 = 2048;
+
+// A condition that stops a program in trap checking mode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trap {
+  // The PC left the program, with the address it jumped from.
+  PcOutOfRange { pc: u16, from: Option<u16> },
+  UninitializedRead(u16),
+  InvalidWrite(u16),
+  StackPointer(u16),
+  // The emulator couldn't run the instruction at all.
+  Fault(String),
+}
+
+impl fmt::Display for Trap {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Trap::PcOutOfRange {
+        pc,
+        from: Some(from),
+      } => write!(
+        f,
+        "PC {} is outside the program (jumped from ROM address {})",
+        pc, from
+      ),
+      Trap::PcOutOfRange { pc, from: None } => write!(f, "PC {} is outside the program", pc),
+      Trap::UninitializedRead(address) => write!(f, "read of uninitialized RAM[{}]", address),
+      Trap::InvalidWrite(address) => write!(f, "write to RAM[{}] outside valid memory", address),
+      Trap::StackPointer(sp) => write!(
+        f,
+        "stack pointer {} outside the stack ({}..={})",
+        sp, STACK_START, STACK_END
+      ),
+      Trap::Fault(message) => write!(f, "{}", message),
+    }
+  }
+}
+
+// Watches the instructions an emulator runs for signs of a broken program.
+// RAM counts as written once an instruction stored to it, or if it held a
+// value when checking started; the VM pointers SP to THAT, which the bootstrap
+// code saves before ever setting, and memory-mapped I/O are always readable.
+#[derive(Debug, Clone)]
+pub struct Traps {
+  program_size: usize,
+  written: Vec<bool>,
+  // Whether RAM[0] is the VM stack pointer, to be kept within the stack.
+  stack: bool,
+  previous: Option<u16>,
+  pub trap: Option<Trap>,
+}
+
+impl Traps {
+  pub fn new(emulator: &Emulator, program_size: usize, stack: bool) -> Self {
+    let written = emulator
+      .ram
+      .iter()
+      .enumerate()
+      .map(|(address, word)| *word != 0 || address <= THAT || (SCREEN..=KBD).contains(&address))
+      .collect();
+    Traps {
+      program_size,
+      written,
+      stack,
+      previous: None,
+      trap: None,
+    }
+  }
+
+  // Checks the instruction about to run.
+  pub fn check(&self, emulator: &Emulator) -> Result<(), Trap> {
+    let pc = emulator.pc;
+    if pc as usize >= self.program_size {
+      return Err(Trap::PcOutOfRange {
+        pc,
+        from: self.previous,
+      });
+    }
+    let word = emulator.rom[pc as usize];
+    if word & 0x8000 == 0 {
+      return Ok(());
+    }
+    let address = emulator.a as usize;
+    if word & 0x1000 != 0 && !self.written.get(address).is_some_and(|written| *written) {
+      return Err(Trap::UninitializedRead(emulator.a));
+    }
+    if word & 0b001000 != 0 && address >= KBD {
+      return Err(Trap::InvalidWrite(emulator.a));
+    }
+    Ok(())
+  }
+
+  // Records an instruction that ran at `pc`, writing RAM[address] if any.
+  pub fn ran(&mut self, ram: &[u16], pc: u16, write: Option<u16>) -> Result<(), Trap> {
+    self.previous = Some(pc);
+    if let Some(address) = write {
+      self.written[address as usize] = true;
+      let sp = ram[SP];
+      if self.stack && address as usize == SP && !(STACK_START..=STACK_END).contains(&sp) {
+        return Err(Trap::StackPointer(sp));
+      }
+    }
+    Ok(())
+  }
+
+  // The ROM address to blame: the instruction that jumped out of the program
+  // or the one that was about to run.
+  fn culprit(&self, emulator: &Emulator) -> usize {
+    match self.trap {
+      Some(Trap::PcOutOfRange {
+        from: Some(from), ..
+      }) => from as usize,
+      Some(Trap::StackPointer(_)) => self.previous.unwrap_or(emulator.pc) as usize,
+      _ => emulator.pc as usize,
+    }
+  }
+}
+
+// Describes the trap the emulator stopped at, with the VM command and call
+// stack when there's a source map.
+pub fn report(emulator: &Emulator, source_map: Option<&SourceMap>) -> Option<String> {
+  let traps = emulator.traps.as_ref()?;
+  let trap = traps.trap.as_ref()?;
+  let address = traps.culprit(emulator);
+  let mut report = format!(
+    "Trap at cycle {}, ROM address {}: {}\n",
+    emulator.cycles, address, trap
+  );
+  if let Some(source_map) = source_map {
+    report += &location(&emulator.ram, source_map, address);
+  }
+  Some(report)
+}
+
+// The VM command at ROM `address` and the call stack that led to it.
+pub fn location(ram: &[u16], source_map: &SourceMap, address: usize) -> String {
+  let mut location = String::new();
+  match source_map.lookup(address) {
+    Some(mapping) => writeln!(
+      location,
+      "  in {}:{}: {}{}",
+      mapping.file.as_deref().unwrap_or("(bootstrap)"),
+      mapping.line,
+      mapping.command,
+      mapping
+        .function
+        .as_ref()
+        .map_or(String::new(), |function| format!(" ({})", function))
+    )
+    .unwrap(),
+    None => writeln!(location, "  outside the translated program").unwrap(),
+  }
+  let frames = debugger::backtrace(ram, source_map, address);
+  if !frames.is_empty() {
+    writeln!(location, "Call stack:").unwrap();
+    for (i, frame) in frames.iter().enumerate() {
+      writeln!(
+        location,
+        "  #{} {} at {}:{}: {}",
+        i,
+        frame.function.as_deref().unwrap_or("(no function)"),
+        frame.file,
+        frame.line,
+        frame.command
+      )
+      .unwrap();
+    }
+  }
+  location
+}
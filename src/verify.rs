@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+
+use crate::assembler::Program;
+use crate::emulator::{self, Emulator};
+use crate::interpreter::{self, Interpreter, Status};
+use crate::source_map::SourceMap;
+use crate::{Build, VmParser};
+
+// Cycles the generated code of a single VM command may take before we decide
+// it never reaches the next command.
+const COMMAND_CYCLES: u64 = 1_000_000;
+const MAX_DIFFERENCES: usize = 10;
+
+#[derive(Debug)]
+pub struct Divergence {
+  pub location: String,
+  pub differences: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Report {
+  pub steps: usize,
+  pub cycles: u64,
+  pub halted: bool,
+  pub divergence: Option<Divergence>,
+}
+
+// Runs the program in the interpreter and, translated and assembled, in the
+// emulator, one VM command at a time, comparing RAM after every command.
+pub fn verify(parsers: Vec<VmParser>, max_steps: usize) -> Result<Report, String> {
+  let build = crate::build(parsers.clone(), false)?;
+  verify_build(parsers, build, &[], max_steps)
+}
+
+// Like `verify` for `build`, a translation of the program, with the `(address,
+// value)` pairs of `ram` set after the bootstrap, the way a test script sets
+// up RAM.
+pub fn verify_build(
+  parsers: Vec<VmParser>,
+  build: Build,
+  ram: &[(usize, u16)],
+  max_steps: usize,
+) -> Result<Report, String> {
+  let Build {
+    program,
+    source_map,
+    mut emulator,
+  } = build;
+  let mut interpreter = Interpreter::new(parsers)?;
+  let verifier = Verifier::new(&source_map, &program, &interpreter);
+
+  if interpreter.bootstrap().is_ok() {
+    verifier.run_command(&mut emulator)?;
+  } else {
+    // Without Sys.init both start at the first command with an empty stack.
+    interpreter.ram[interpreter::SP] = 256;
+  }
+  for (address, value) in ram {
+    interpreter.ram[*address] = *value;
+    emulator.ram[*address] = *value;
+  }
+  let mut report = Report {
+    steps: 0,
+    cycles: 0,
+    halted: false,
+    divergence: None,
+  };
+  report.divergence = verifier.compare(&interpreter, &emulator, "bootstrap".to_string());
+
+  while report.divergence.is_none() && report.steps < max_steps {
+    let index = interpreter.pc;
+    let location = interpreter
+      .current()
+      .map(|source| format!("{}:{}: {}", source.file, source.line, source.text))
+      .unwrap_or_default();
+    let status = interpreter.step()?;
+    report.steps += 1;
+    if verifier.instruction_count(index) > 0 {
+      verifier.run_command(&mut emulator)?;
+    }
+    report.divergence = verifier.compare(&interpreter, &emulator, location);
+    if status == Status::Halted {
+      report.halted = true;
+      break;
+    }
+  }
+  report.cycles = emulator.cycles;
+  Ok(report)
+}
+
+struct Verifier<'a> {
+  source_map: &'a SourceMap,
+  // Number of leading bootstrap mappings before the first VM command.
+  offset: usize,
+  starts: HashSet<usize>,
+  statics: Vec<(String, u16, u16)>,
+}
+
+impl<'a> Verifier<'a> {
+  fn new(source_map: &'a SourceMap, program: &Program, interpreter: &Interpreter) -> Self {
+    let offset = source_map
+      .mappings
+      .iter()
+      .take_while(|mapping| mapping.file.is_none())
+      .count();
+    let mut starts: HashSet<usize> = source_map.mappings[offset..]
+      .iter()
+      .filter(|mapping| mapping.count > 0)
+      .map(|mapping| mapping.address)
+      .collect();
+    starts.insert(source_map.instruction_count());
+    let statics = interpreter
+      .statics()
+      .filter_map(|((file, index), address)| {
+        let name = format!("{}.{}", file, index);
+        let emulated = *program.variables.get(&name)?;
+        Some((name, *address, emulated))
+      })
+      .collect();
+    Verifier {
+      source_map,
+      offset,
+      starts,
+      statics,
+    }
+  }
+
+  // ROM address of interpreter command `index`, the end of the program past
+  // the last command.
+  fn address(&self, index: usize) -> usize {
+    self
+      .source_map
+      .mappings
+      .get(index + self.offset)
+      .map_or(self.source_map.instruction_count(), |mapping| {
+        mapping.address
+      })
+  }
+
+  fn instruction_count(&self, index: usize) -> usize {
+    self
+      .source_map
+      .mappings
+      .get(index + self.offset)
+      .map_or(0, |mapping| mapping.count)
+  }
+
+  // Steps the emulator until it reaches the first instruction of a command.
+  fn run_command(&self, emulator: &mut Emulator) -> Result<(), String> {
+    for _ in 0..COMMAND_CYCLES {
+      emulator.step()?;
+      if self.starts.contains(&(emulator.pc as usize)) || emulator.is_halted() {
+        return Ok(());
+      }
+    }
+    Ok(())
+  }
+
+  fn compare(
+    &self,
+    interpreter: &Interpreter,
+    emulator: &Emulator,
+    location: String,
+  ) -> Option<Divergence> {
+    let expected = &interpreter.ram;
+    let actual = &emulator.ram;
+    let mut differences = Vec::new();
+    let pc = self.address(interpreter.pc);
+    if emulator.pc as usize != pc {
+      let at = |address: usize| match self.source_map.lookup(address) {
+        Some(mapping) => format!(
+          "{} ({}:{}: {})",
+          address,
+          mapping.file.as_deref().unwrap_or("bootstrap"),
+          mapping.line,
+          mapping.command
+        ),
+        None => address.to_string(),
+      };
+      differences.push(format!(
+        "PC: interpreter at {}, emulator at {}",
+        at(pc),
+        at(emulator.pc as usize)
+      ));
+    }
+    let mut difference = |name: String, expected: u16, actual: u16| {
+      if expected != actual {
+        differences.push(format!(
+          "{}: interpreter {}, emulator {}",
+          name, expected as i16, actual as i16
+        ));
+      }
+    };
+
+    for (i, name) in ["SP", "LCL", "ARG", "THIS", "THAT"].iter().enumerate() {
+      difference(name.to_string(), expected[i], actual[i]);
+    }
+    for i in 0..8 {
+      difference(format!("temp {}", i), expected[5 + i], actual[5 + i]);
+    }
+    for (name, interpreted, emulated) in &self.statics {
+      difference(
+        format!("static {}", name),
+        expected[*interpreted as usize],
+        actual[*emulated as usize],
+      );
+    }
+
+    // Saved return addresses are command indexes in the interpreter and ROM
+    // addresses in the emulator, so they're left out of the comparison.
+    let sp = (expected[interpreter::SP] as usize).clamp(256, 2048);
+    let mut return_addresses = HashSet::new();
+    let mut lcl = expected[interpreter::LCL] as usize;
+    while (261..=sp).contains(&lcl) {
+      return_addresses.insert(lcl - 5);
+      let saved = expected[lcl - 4] as usize;
+      if saved >= lcl {
+        break;
+      }
+      lcl = saved;
+    }
+    for address in 256..sp {
+      if !return_addresses.contains(&address) {
+        difference(
+          format!("stack RAM[{}]", address),
+          expected[address],
+          actual[address],
+        );
+      }
+    }
+
+    let heap = 2048..emulator::KBD;
+    if expected[heap.clone()] != actual[heap.clone()] {
+      for address in heap {
+        difference(
+          format!("RAM[{}]", address),
+          expected[address],
+          actual[address],
+        );
+      }
+    }
+
+    if differences.is_empty() {
+      return None;
+    }
+    differences.truncate(MAX_DIFFERENCES);
+    Some(Divergence {
+      location,
+      differences,
+    })
+  }
+}
@@ -0,0 +1,334 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::emulator::{Emulator, RAM_SIZE};
+use crate::interpreter::{ARG, LCL, SP, THAT, THIS};
+
+// RAM addresses with names of their own.
+fn register(name: &str) -> Option<u16> {
+  match name {
+    "SP" => Some(SP as u16),
+    "LCL" => Some(LCL as u16),
+    "ARG" => Some(ARG as u16),
+    "THIS" => Some(THIS as u16),
+    "THAT" => Some(THAT as u16),
+    _ => name
+      .strip_prefix('R')
+      .and_then(|n| n.parse().ok())
+      .filter(|n| *n < 16),
+  }
+}
+
+// A RAM word to stop at when it changes: a fixed address, or a slot of a
+// segment. Statics live at the address the assembler gave them; locals and
+// arguments move with the frames of their function and are watched in every
+// activation of it. A missing file or function is the current one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Watch {
+  Address(u16),
+  Register(String),
+  Static(Option<String>, u16),
+  Local(Option<String>, u16),
+  Argument(Option<String>, u16),
+}
+
+impl FromStr for Watch {
+  type Err = String;
+  fn from_str(str: &str) -> Result<Self, Self::Err> {
+    let index = |index: &str| {
+      index
+        .parse()
+        .map_err(|_| format!("invalid index {}", index))
+    };
+    match str.split_whitespace().collect::<Vec<&str>>().as_slice() {
+      [segment, rest @ ..] if rest.len() == 1 || rest.len() == 2 => {
+        let owner = if rest.len() == 2 {
+          Some(rest[0].to_string())
+        } else {
+          None
+        };
+        let index = index(rest[rest.len() - 1])?;
+        match *segment {
+          "static" => Ok(Watch::Static(
+            owner.map(|file| file.trim_end_matches(".vm").to_string()),
+            index,
+          )),
+          "local" => Ok(Watch::Local(owner, index)),
+          "argument" => Ok(Watch::Argument(owner, index)),
+          _ => Err(format!("cannot watch {}", str)),
+        }
+      }
+      [word] => {
+        if register(word).is_some() {
+          return Ok(Watch::Register(word.to_string()));
+        }
+        let address = word
+          .strip_prefix("RAM[")
+          .and_then(|word| word.strip_suffix(']'))
+          .unwrap_or(word);
+        match address.parse() {
+          Ok(address) if (address as usize) < RAM_SIZE => Ok(Watch::Address(address)),
+          _ => Err(format!("cannot watch {}", str)),
+        }
+      }
+      _ => {
+        Err("expected RAM[address], a register or <segment> [file|function] <index>".to_string())
+      }
+    }
+  }
+}
+
+impl fmt::Display for Watch {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let segment = |f: &mut fmt::Formatter, name, owner: &Option<String>, index| match owner {
+      Some(owner) => write!(f, "{} {} {}", name, owner, index),
+      None => write!(f, "{} {}", name, index),
+    };
+    match self {
+      Watch::Address(address) => write!(f, "RAM[{}]", address),
+      Watch::Register(name) => write!(f, "{}", name),
+      Watch::Static(file, index) => segment(f, "static", file, index),
+      Watch::Local(function, index) => segment(f, "local", function, index),
+      Watch::Argument(function, index) => segment(f, "argument", function, index),
+    }
+  }
+}
+
+impl Watch {
+  // The address of a RAM or register watch.
+  pub fn address(&self) -> Option<u16> {
+    match self {
+      Watch::Address(address) => Some(*address),
+      Watch::Register(name) => register(name),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Number(i32),
+  Name(String),
+  Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 14] = [
+  "==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "(", ")", "[", "]",
+];
+
+fn tokenize(str: &str) -> Result<Vec<Token>, String> {
+  let mut tokens = Vec::new();
+  let mut rest = str.trim_start();
+  while !rest.is_empty() {
+    let length = if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+      tokens.push(Token::Symbol(symbol));
+      symbol.len()
+    } else {
+      let length = rest
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(rest.len());
+      if length == 0 {
+        return Err(format!("unexpected {} in condition", rest));
+      }
+      let word = &rest[..length];
+      tokens.push(match word.parse() {
+        Ok(number) => Token::Number(number),
+        Err(_) if word.starts_with(|c: char| c.is_ascii_digit()) => {
+          return Err(format!("invalid number {}", word))
+        }
+        Err(_) => Token::Name(word.to_string()),
+      });
+      length
+    };
+    rest = rest[length..].trim_start();
+  }
+  Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+  Number(i32),
+  A,
+  D,
+  Pc,
+  Ram(Box<Expr>),
+  Negate(Box<Expr>),
+  Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+// Recursive descent over `or: and ("||" and)*`, `and: comparison ("&&"
+// comparison)*`, `comparison: sum (op sum)?` and `sum: value (("+"|"-")
+// value)*`.
+struct Parser {
+  tokens: Vec<Token>,
+  next: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.next)
+  }
+
+  fn accept(&mut self, symbols: &[&'static str]) -> Option<&'static str> {
+    match self.peek() {
+      Some(Token::Symbol(symbol)) if symbols.contains(symbol) => {
+        let symbol = *symbol;
+        self.next += 1;
+        Some(symbol)
+      }
+      _ => None,
+    }
+  }
+
+  fn expect(&mut self, symbol: &'static str) -> Result<(), String> {
+    self
+      .accept(&[symbol])
+      .map(|_| ())
+      .ok_or_else(|| format!("expected {} in condition", symbol))
+  }
+
+  fn binary(
+    &mut self,
+    symbols: &[&'static str],
+    operand: fn(&mut Self) -> Result<Expr, String>,
+  ) -> Result<Expr, String> {
+    let mut expr = operand(self)?;
+    while let Some(symbol) = self.accept(symbols) {
+      expr = Expr::Binary(symbol, Box::new(expr), Box::new(operand(self)?));
+    }
+    Ok(expr)
+  }
+
+  fn or(&mut self) -> Result<Expr, String> {
+    self.binary(&["||"], Parser::and)
+  }
+
+  fn and(&mut self) -> Result<Expr, String> {
+    self.binary(&["&&"], Parser::comparison)
+  }
+
+  fn comparison(&mut self) -> Result<Expr, String> {
+    let left = self.sum()?;
+    match self.accept(&["==", "!=", "<=", ">=", "<", ">"]) {
+      Some(symbol) => Ok(Expr::Binary(symbol, Box::new(left), Box::new(self.sum()?))),
+      None => Ok(left),
+    }
+  }
+
+  fn sum(&mut self) -> Result<Expr, String> {
+    self.binary(&["+", "-"], Parser::value)
+  }
+
+  fn value(&mut self) -> Result<Expr, String> {
+    if self.accept(&["-"]).is_some() {
+      return Ok(Expr::Negate(Box::new(self.value()?)));
+    }
+    if self.accept(&["("]).is_some() {
+      let expr = self.or()?;
+      self.expect(")")?;
+      return Ok(expr);
+    }
+    let token = self.peek().cloned();
+    self.next += 1;
+    match token {
+      Some(Token::Number(number)) => Ok(Expr::Number(number)),
+      Some(Token::Name(name)) => match name.as_str() {
+        "A" => Ok(Expr::A),
+        "D" => Ok(Expr::D),
+        "PC" => Ok(Expr::Pc),
+        "RAM" => {
+          self.expect("[")?;
+          let address = self.sum()?;
+          self.expect("]")?;
+          Ok(Expr::Ram(Box::new(address)))
+        }
+        _ => register(&name)
+          .map(|address| Expr::Ram(Box::new(Expr::Number(address as i32))))
+          .ok_or_else(|| format!("unknown name {} in condition", name)),
+      },
+      Some(Token::Symbol(symbol)) => Err(format!("unexpected {} in condition", symbol)),
+      None => Err("condition ends early".to_string()),
+    }
+  }
+}
+
+impl Expr {
+  // RAM words and registers read as signed numbers, and comparisons give 1 or
+  // 0.
+  fn eval(&self, emulator: &Emulator) -> Result<i32, String> {
+    Ok(match self {
+      Expr::Number(number) => *number,
+      Expr::A => emulator.a as i16 as i32,
+      Expr::D => emulator.d as i16 as i32,
+      Expr::Pc => emulator.pc as i32,
+      Expr::Ram(address) => {
+        let address = address.eval(emulator)?;
+        *usize::try_from(address)
+          .ok()
+          .and_then(|address| emulator.ram.get(address))
+          .ok_or_else(|| format!("RAM[{}] is outside RAM", address))? as i16 as i32
+      }
+      Expr::Negate(expr) => -expr.eval(emulator)?,
+      Expr::Binary(symbol, left, right) => {
+        let left = left.eval(emulator)?;
+        if *symbol == "&&" && left == 0 || *symbol == "||" && left != 0 {
+          return Ok((*symbol == "||") as i32);
+        }
+        let right = right.eval(emulator)?;
+        match *symbol {
+          "+" => left.wrapping_add(right),
+          "-" => left.wrapping_sub(right),
+          "==" => (left == right) as i32,
+          "!=" => (left != right) as i32,
+          "<=" => (left <= right) as i32,
+          ">=" => (left >= right) as i32,
+          "<" => (left < right) as i32,
+          ">" => (left > right) as i32,
+          // The left side of `&&` and `||` didn't settle it.
+          "&&" | "||" => (right != 0) as i32,
+          _ => unreachable!(),
+        }
+      }
+    })
+  }
+}
+
+// A condition of a breakpoint, like `RAM[256] > 3 && SP != 300`, over RAM
+// words, the registers A, D and PC and the named RAM addresses SP to THAT and
+// R0 to R15.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+  text: String,
+  expr: Expr,
+}
+
+impl FromStr for Condition {
+  type Err = String;
+  fn from_str(str: &str) -> Result<Self, Self::Err> {
+    let mut parser = Parser {
+      tokens: tokenize(str)?,
+      next: 0,
+    };
+    let expr = parser.or()?;
+    if let Some(token) = parser.peek() {
+      return Err(format!("unexpected {:?} in condition", token));
+    }
+    Ok(Condition {
+      text: str.split_whitespace().collect::<Vec<&str>>().join(" "),
+      expr,
+    })
+  }
+}
+
+impl fmt::Display for Condition {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.text)
+  }
+}
+
+impl Condition {
+  pub fn holds(&self, emulator: &Emulator) -> Result<bool, String> {
+    Ok(self.expr.eval(emulator)? != 0)
+  }
+}
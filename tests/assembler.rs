@@ -0,0 +1,173 @@
+use std::path::Path;
+
+use vm::assembler;
+use vm::disassembler::{self, SymbolMap};
+use vm::hack::Instruction;
+use vm::VmParser;
+
+fn translate(path: &str) -> String {
+  let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+    .join("tests/programs")
+    .join(path);
+  let mut asm = Vec::new();
+  vm::translate(VmParser::open(&path).unwrap(), &mut asm).unwrap();
+  String::from_utf8(asm).unwrap()
+}
+
+fn words(asm: &str) -> Vec<u16> {
+  assembler::assemble(asm)
+    .unwrap_or_else(|err| panic!("{}\n{}", err, asm))
+    .instructions
+}
+
+#[test]
+fn every_instruction_round_trips() {
+  let mut asm = String::new();
+  let mut expected = Vec::new();
+  for word in 0xe000..=0xffffu16 {
+    if let Ok(instruction) = Instruction::decode(word) {
+      asm += &format!("{}\n", instruction.to_asm());
+      expected.push(word);
+    }
+  }
+  // 28 comps, 8 dests and 8 jumps, with the unused a bit clear.
+  assert_eq!(expected.len(), 28 * 8 * 8);
+  assert_eq!(words(&asm), expected);
+  // The CPU ignores the two bits after the C instruction's top bit.
+  for high in [0x8000, 0xa000, 0xc000] {
+    for word in &expected {
+      assert_eq!(
+        Instruction::decode(high | word & 0x1fff),
+        Instruction::decode(*word)
+      );
+    }
+  }
+}
+
+#[test]
+fn dest_uses_course_mnemonics() {
+  let asm = "M=D\nD=M\nMD=M+1\nA=A-1\nAM=M-1\nAD=D|A\nAMD=0;JMP\nDM=1\n";
+  let disassembled: Vec<String> = words(asm)
+    .iter()
+    .map(|word| Instruction::decode(*word).unwrap().to_asm())
+    .collect();
+  assert_eq!(
+    disassembled,
+    [
+      "M=D",
+      "D=M",
+      "MD=M+1",
+      "A=A-1",
+      "AM=M-1",
+      "AD=D|A",
+      "AMD=0;JMP",
+      "MD=1"
+    ]
+  );
+}
+
+#[test]
+fn program_round_trips() {
+  for path in [
+    "08/FunctionCalls/FibonacciElement",
+    "08/FunctionCalls/StaticsTest",
+    "07/MemoryAccess/BasicTest/BasicTest.vm",
+  ] {
+    let asm = translate(path);
+    let program = assembler::assemble(&asm).unwrap();
+    let bare = disassembler::disassemble(&program.instructions, &SymbolMap::default());
+    assert_eq!(words(&bare), program.instructions, "{}", path);
+    let symbols: SymbolMap = program.symbol_map().parse().unwrap();
+    let named = disassembler::disassemble(&program.instructions, &symbols);
+    assert_eq!(words(&named), program.instructions, "{}", path);
+  }
+}
+
+#[test]
+fn symbols() {
+  let program = assembler::assemble(
+    "@R15\nD=A\n@SCREEN\nD=A\n@KBD\nD=A\n@THAT\nD=A\n\
+     (LOOP)\n@first\nM=1\n@second\nM=1\n@first\nM=0\n@R16\nM=0\n\
+     @LOOP\n0;JMP\n@END\n(END)\n@END\n0;JMP",
+  )
+  .unwrap();
+  assert_eq!(
+    &program.instructions[..8],
+    &[15, 0xec10, 16384, 0xec10, 24576, 0xec10, 4, 0xec10]
+  );
+  assert_eq!(program.labels["LOOP"], 8);
+  assert_eq!(program.labels["END"], 19);
+  // Variables take addresses from 16 in order of first use, labels used
+  // before they're defined are still labels, and R16 is no register.
+  assert_eq!(program.variables["first"], 16);
+  assert_eq!(program.variables["second"], 17);
+  assert_eq!(program.variables["R16"], 18);
+  assert_eq!(program.variables.len(), 3);
+  assert_eq!(program.instructions[18], 19);
+  assert_eq!(
+    program.symbol_map(),
+    "label 8 LOOP\nlabel 19 END\nvar 16 first\nvar 17 second\nvar 18 R16\n"
+  );
+}
+
+#[test]
+fn errors() {
+  let error = |asm: &str| assembler::assemble(asm).unwrap_err();
+  assert_eq!(error("(A)\n@1\n(A)"), "line 3: duplicate label A");
+  assert_eq!(error("@32768"), "line 1: constant 32768 out of range");
+  assert_eq!(error("@1\nD=D*A"), "line 2: invalid comp D*A");
+  assert_eq!(error("MM=1"), "line 1: invalid dest MM");
+  assert_eq!(error("X=1"), "line 1: invalid dest X");
+  assert_eq!(error("0;JMZ"), "line 1: invalid jump JMZ");
+  assert!(error(&"@0\n".repeat(32769)).contains("32769 instructions"));
+  assert!("label x".parse::<SymbolMap>().is_err());
+  assert!("var 16 x y".parse::<SymbolMap>().is_err());
+}
+
+#[test]
+fn unknown_comps_disassemble_as_comments() {
+  // A C instruction without the unused high bits, and a comp with no
+  // mnemonic.
+  let asm = disassembler::disassemble(
+    &[
+      0x8000 | 0b0001100 << 6 | 0b010 << 3,
+      0xe000 | 0b0111000 << 6 | 0b111,
+    ],
+    &SymbolMap::default(),
+  );
+  assert_eq!(
+    asm,
+    format!(
+      "{:<32}// 0\n{:<32}// 1\n",
+      "D=D", "// 1110111000000111 unknown comp 0111000"
+    )
+  );
+  assert_eq!(
+    Instruction::decode(0xe000 | 0b0111000 << 6),
+    Err("unknown comp 0111000".to_string())
+  );
+}
+
+#[test]
+fn return_addresses_get_labels() {
+  let asm = translate("08/FunctionCalls/FibonacciElement");
+  let program = assembler::assemble(&asm).unwrap();
+  let bare = disassembler::disassemble(&program.instructions, &SymbolMap::default());
+  // Every call pushes a return address with `D=A`, which now has a label.
+  let returns: Vec<u16> = asm
+    .lines()
+    .filter_map(|line| line.split_whitespace().next()?.strip_prefix('@'))
+    .filter(|symbol| symbol.contains(".ret."))
+    .map(|symbol| program.labels[symbol])
+    .collect();
+  assert_eq!(returns.len(), 4);
+  for address in returns {
+    let label = format!("L{}", address);
+    assert!(bare.contains(&format!("({})\n", label)), "{}", bare);
+    assert!(
+      bare.contains(&format!("{:<32}//", format!("@{}", label))),
+      "{}",
+      bare
+    );
+  }
+}
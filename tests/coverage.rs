@@ -0,0 +1,77 @@
+use vm::coverage::{self, Coverage};
+use vm::VmParser;
+
+const SYS: &str = "function Sys.init 0\n\
+                   push constant 0\n\
+                   if-goto SKIP\n\
+                   call Main.used 0\n\
+                   pop temp 0\n\
+                   label SKIP\n\
+                   push constant 1\n\
+                   if-goto DONE\n\
+                   call Main.unused 0\n\
+                   label DONE\n\
+                   goto DONE\n";
+
+const MAIN: &str = "function Main.used 0\n\
+                    push constant 5\n\
+                    return\n\
+                    function Main.unused 0\n\
+                    push constant 6\n\
+                    return\n";
+
+fn coverage() -> Coverage {
+  coverage::coverage(
+    vec![
+      VmParser::from_source("Sys.vm", SYS),
+      VmParser::from_source("Main.vm", MAIN),
+    ],
+    10_000,
+  )
+  .unwrap()
+}
+
+#[test]
+fn lcov() {
+  let coverage = coverage();
+  assert!(coverage.halted);
+  // Labels generate no code and aren't lines; the loop the program halts in
+  // counts as reached.
+  assert_eq!(
+    coverage.lcov(),
+    "TN:\nSF:Sys.vm\n\
+     FN:1,Sys.init\nFNDA:1,Sys.init\nFNF:1\nFNH:1\n\
+     DA:1,1\nDA:2,1\nDA:3,1\nDA:4,1\nDA:5,1\nDA:7,1\nDA:8,1\nDA:9,0\nDA:11,1\n\
+     LF:9\nLH:8\nend_of_record\n\
+     TN:\nSF:Main.vm\n\
+     FN:1,Main.used\nFN:4,Main.unused\nFNDA:1,Main.used\nFNDA:0,Main.unused\nFNF:2\nFNH:1\n\
+     DA:1,1\nDA:2,1\nDA:3,1\nDA:4,0\nDA:5,0\nDA:6,0\n\
+     LF:6\nLH:3\nend_of_record\n"
+  );
+}
+
+#[test]
+fn summary() {
+  let summary = coverage().summary();
+  let row =
+    |file: &str, lines: &str, lines_percent: f64, functions: &str, functions_percent: f64| {
+      format!(
+        "{:<40}{:>14}{:>7.1}%{:>14}{:>7.1}%\n",
+        file, lines, lines_percent, functions, functions_percent
+      )
+    };
+  assert_eq!(
+    summary,
+    format!(
+      "{:<40}{:>14}{:>8}{:>14}{:>8}\n",
+      "File", "lines", "%", "functions", "%"
+    ) + &row("Sys.vm", "8/9", 88.9, "1/1", 100.0)
+      + &row("Main.vm", "3/6", 50.0, "1/2", 50.0)
+      + &row("Total", "11/15", 73.3, "2/3", 66.7)
+      + "\nNot executed:\n  \
+         Sys.vm:9: call Main.unused 0\n  \
+         Main.vm:4: function Main.unused 0\n  \
+         Main.vm:5: push constant 6\n  \
+         Main.vm:6: return\n"
+  );
+}
@@ -0,0 +1,343 @@
+use std::io::Cursor;
+
+use vm::dap::DapServer;
+use vm::json::Json;
+
+const FIBONACCI: &str = "tests/programs/08/FunctionCalls/FibonacciElement";
+
+// Counts up in static 0 forever.
+const COUNTER: &str = "function Sys.init 0\n\
+                       label LOOP\n\
+                       push static 0\n\
+                       push constant 1\n\
+                       add\n\
+                       pop static 0\n\
+                       goto LOOP\n";
+
+// Request `seq` framed as the client sends it.
+fn request(seq: usize, command: &str, arguments: Json) -> String {
+  let request = Json::object(vec![
+    ("seq", seq.into()),
+    ("type", "request".into()),
+    ("command", command.into()),
+    ("arguments", arguments),
+  ])
+  .to_string();
+  format!("Content-Length: {}\r\n\r\n{}", request.len(), request)
+}
+
+// Runs the server over `requests`, returning everything it sent.
+fn session(requests: Vec<(&str, Json)>) -> Vec<Json> {
+  let mut input = String::new();
+  for (seq, (command, arguments)) in requests.into_iter().enumerate() {
+    input += &request(seq + 1, command, arguments);
+  }
+  raw_session(input)
+}
+
+fn raw_session(input: String) -> Vec<Json> {
+  let mut output = Vec::new();
+  DapServer::new(Cursor::new(input.into_bytes()), &mut output)
+    .run()
+    .unwrap();
+
+  let mut output = String::from_utf8(output).unwrap();
+  let mut messages: Vec<Json> = Vec::new();
+  while !output.is_empty() {
+    let (header, rest) = output.split_once("\r\n\r\n").unwrap();
+    let length: usize = header
+      .strip_prefix("Content-Length: ")
+      .unwrap()
+      .parse()
+      .unwrap();
+    messages.push(rest[..length].parse().unwrap());
+    output = rest[length..].to_string();
+  }
+  for (seq, message) in messages.iter().enumerate() {
+    assert_eq!(
+      message.get("seq").and_then(Json::as_i64),
+      Some(seq as i64 + 1)
+    );
+  }
+  messages
+}
+
+// The response to request `seq`.
+fn response(messages: &[Json], seq: i64) -> &Json {
+  messages
+    .iter()
+    .find(|message| message.get("request_seq").and_then(Json::as_i64) == Some(seq))
+    .unwrap()
+}
+
+fn body(messages: &[Json], seq: i64) -> &Json {
+  let response = response(messages, seq);
+  assert_eq!(
+    response.get("success"),
+    Some(&Json::Bool(true)),
+    "{}",
+    response
+  );
+  response.get("body").unwrap()
+}
+
+// A summary of each message: `response <command>` or `event <event>`.
+fn kinds(messages: &[Json]) -> Vec<String> {
+  messages
+    .iter()
+    .map(|message| {
+      let kind = message.get("type").and_then(Json::as_str).unwrap();
+      let name = message
+        .get("command")
+        .or_else(|| message.get("event"))
+        .and_then(Json::as_str)
+        .unwrap();
+      match message.get("body").and_then(|body| body.get("reason")) {
+        Some(reason) => format!("{} {} {}", kind, name, reason.as_str().unwrap()),
+        None => format!("{} {}", kind, name),
+      }
+    })
+    .collect()
+}
+
+fn variables(messages: &[Json], seq: i64) -> Vec<(String, String)> {
+  body(messages, seq)
+    .get("variables")
+    .and_then(Json::as_array)
+    .unwrap()
+    .iter()
+    .map(|variable| {
+      (
+        variable
+          .get("name")
+          .and_then(Json::as_str)
+          .unwrap()
+          .to_string(),
+        variable
+          .get("value")
+          .and_then(Json::as_str)
+          .unwrap()
+          .to_string(),
+      )
+    })
+    .collect()
+}
+
+fn reference(reference: i64) -> Json {
+  Json::object(vec![("variablesReference", reference.into())])
+}
+
+#[test]
+fn breakpoints_and_variables() {
+  let main = format!("{}/Main.vm", FIBONACCI);
+  let messages = session(vec![
+    ("initialize", Json::object(vec![("adapterID", "vm".into())])),
+    ("launch", Json::object(vec![("program", FIBONACCI.into())])),
+    (
+      "setBreakpoints",
+      Json::object(vec![
+        ("source", Json::object(vec![("path", main.as_str().into())])),
+        (
+          "breakpoints",
+          Json::Array(vec![
+            Json::object(vec![("line", Json::Number(13))]),
+            Json::object(vec![("line", Json::Number(100))]),
+          ]),
+        ),
+      ]),
+    ),
+    ("configurationDone", Json::Null),
+    (
+      "stackTrace",
+      Json::object(vec![("threadId", Json::Number(1))]),
+    ),
+    ("scopes", Json::object(vec![("frameId", Json::Number(1))])),
+    ("variables", reference(2)),
+    ("variables", reference(9)),
+    ("variables", reference(0)),
+    ("variables", reference(29)),
+    (
+      "continue",
+      Json::object(vec![("threadId", Json::Number(1))]),
+    ),
+    ("disconnect", Json::Null),
+  ]);
+
+  assert_eq!(
+    kinds(&messages),
+    [
+      "response initialize",
+      "response launch",
+      "event initialized",
+      "response setBreakpoints",
+      "response configurationDone",
+      "event stopped breakpoint",
+      "response stackTrace",
+      "response scopes",
+      "response variables",
+      "response variables",
+      "response variables",
+      "response variables",
+      "response continue",
+      "event stopped breakpoint",
+      "response disconnect",
+    ]
+  );
+  let capabilities = body(&messages, 1);
+  assert_eq!(
+    capabilities.get("supportsStepBack"),
+    Some(&Json::Bool(true))
+  );
+
+  let breakpoints = body(&messages, 3).get("breakpoints").unwrap();
+  assert_eq!(
+    breakpoints.as_array().unwrap()[0],
+    Json::object(vec![("verified", true.into()), ("line", Json::Number(13))])
+  );
+  assert_eq!(
+    breakpoints.as_array().unwrap()[1].get("verified"),
+    Some(&Json::Bool(false))
+  );
+
+  // fib(4) calls fib(2), which calls fib(0).
+  let frames: Vec<(&str, &str, i64)> = body(&messages, 5)
+    .get("stackFrames")
+    .and_then(Json::as_array)
+    .unwrap()
+    .iter()
+    .map(|frame| {
+      (
+        frame.get("name").and_then(Json::as_str).unwrap(),
+        frame
+          .get("source")
+          .and_then(|source| source.get("path"))
+          .and_then(Json::as_str)
+          .unwrap(),
+        frame.get("line").and_then(Json::as_i64).unwrap(),
+      )
+    })
+    .collect();
+  let sys = format!("{}/Sys.vm", FIBONACCI);
+  assert_eq!(
+    frames,
+    [
+      ("Main.fibonacci", main.as_str(), 13),
+      ("Main.fibonacci", main.as_str(), 19),
+      ("Main.fibonacci", main.as_str(), 19),
+      ("Sys.init", sys.as_str(), 8),
+    ]
+  );
+
+  let scopes = body(&messages, 6)
+    .get("scopes")
+    .and_then(Json::as_array)
+    .unwrap();
+  assert_eq!(scopes.len(), 7);
+  assert_eq!(
+    scopes[1],
+    Json::object(vec![
+      ("name", "argument".into()),
+      ("variablesReference", Json::Number(9)),
+      ("expensive", false.into()),
+    ])
+  );
+  assert_eq!(
+    variables(&messages, 7),
+    [("argument 0".to_string(), "0".to_string())]
+  );
+  assert_eq!(
+    variables(&messages, 8),
+    [("argument 0".to_string(), "2".to_string())]
+  );
+  for (seq, reference) in [(9, 0), (10, 29)] {
+    let response = response(&messages, seq);
+    assert_eq!(response.get("success"), Some(&Json::Bool(false)));
+    assert_eq!(
+      response.get("message").and_then(Json::as_str),
+      Some(format!("invalid variablesReference {}", reference).as_str())
+    );
+  }
+}
+
+#[test]
+fn pause_interrupts_continue() {
+  let dir = std::env::temp_dir().join(format!("dap-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  std::fs::write(dir.join("Sys.vm"), COUNTER).unwrap();
+  let messages = session(vec![
+    (
+      "launch",
+      Json::object(vec![("program", dir.to_str().unwrap().into())]),
+    ),
+    ("configurationDone", Json::Null),
+    ("pause", Json::object(vec![("threadId", Json::Number(1))])),
+    (
+      "evaluate",
+      Json::object(vec![("expression", "static 0".into())]),
+    ),
+    ("disconnect", Json::Null),
+  ]);
+  std::fs::remove_dir_all(&dir).unwrap();
+
+  assert_eq!(
+    kinds(&messages),
+    [
+      "response launch",
+      "event initialized",
+      "response configurationDone",
+      "response pause",
+      "event stopped pause",
+      "response evaluate",
+      "response disconnect",
+    ]
+  );
+  let count: i64 = body(&messages, 4)
+    .get("result")
+    .and_then(Json::as_str)
+    .unwrap()
+    .parse()
+    .unwrap();
+  assert!(count > 0);
+}
+
+#[test]
+fn bad_messages_get_error_responses() {
+  let nested = format!("{}{}", "[".repeat(1000), "]".repeat(1000));
+  let input = [
+    format!("Content-Length: {}\r\n\r\n{}", nested.len(), nested),
+    "Content-Length: 1048577\r\n\r\n".to_string() + &"x".repeat(1_048_577),
+    request(1, "initialize", Json::Null),
+    "Content-Length: 99999999999999999999999\r\n\r\n".to_string(),
+    request(2, "disconnect", Json::Null),
+  ]
+  .concat();
+  let messages = raw_session(input);
+
+  assert_eq!(
+    kinds(&messages),
+    [
+      "response ",
+      "response ",
+      "response initialize",
+      "response ",
+      "response disconnect",
+    ]
+  );
+  let errors: Vec<&str> = messages
+    .iter()
+    .filter(|message| message.get("success") == Some(&Json::Bool(false)))
+    .map(|message| message.get("message").and_then(Json::as_str).unwrap())
+    .collect();
+  assert_eq!(
+    errors,
+    [
+      "nesting deeper than 64 levels at offset 64",
+      "message of 1048577 bytes is over the 1048576 byte limit",
+      "message without a Content-Length header",
+    ]
+  );
+  assert_eq!(
+    response(&messages, 2).get("success"),
+    Some(&Json::Bool(true))
+  );
+}
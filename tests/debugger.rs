@@ -0,0 +1,150 @@
+use std::path::Path;
+
+use vm::debugger::{Breakpoint, Debugger, Stop};
+use vm::history;
+use vm::VmParser;
+
+fn debugger() -> Debugger {
+  let path =
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs/08/FunctionCalls/NestedCall");
+  Debugger::new(VmParser::open(&path).unwrap(), history::DEFAULT_DELTAS).unwrap()
+}
+
+// The line of the current command.
+fn line(debugger: &Debugger) -> usize {
+  let mapping = debugger.current().unwrap();
+  assert_eq!(mapping.file.as_deref(), Some("Sys.vm"));
+  mapping.line
+}
+
+// Function and line of each frame, innermost first.
+fn frames(debugger: &Debugger) -> Vec<(String, usize)> {
+  debugger
+    .backtrace()
+    .iter()
+    .map(|frame| {
+      assert_eq!(frame.file, "Sys.vm");
+      (frame.function.clone().unwrap(), frame.line)
+    })
+    .collect()
+}
+
+fn value(debugger: &Debugger, frame: usize, segment: &str, index: u16) -> i16 {
+  debugger.segment(frame, segment, Some(index)).unwrap()[0].1
+}
+
+// Continues to the breakpoint on `line`.
+fn run_to(debugger: &mut Debugger, line: usize) {
+  debugger
+    .add_breakpoint(Breakpoint::Line("Sys.vm".to_string(), line), None)
+    .unwrap();
+  assert!(matches!(debugger.cont().unwrap(), Stop::Breakpoint(_)));
+}
+
+#[test]
+fn step_into_calls() {
+  let mut debugger = debugger();
+  // The bootstrap has run.
+  assert_eq!(line(&debugger), 8);
+  assert_eq!(frames(&debugger), [("Sys.init".to_string(), 8)]);
+  for expected in [9, 10, 11, 12, 13, 26, 27] {
+    assert_eq!(debugger.step().unwrap(), Stop::Step);
+    assert_eq!(line(&debugger), expected);
+  }
+  assert_eq!(
+    frames(&debugger),
+    [("Sys.main".to_string(), 27), ("Sys.init".to_string(), 13)]
+  );
+  // Sys.main's locals start out as 0.
+  assert_eq!(
+    debugger.segment(0, "local", None).unwrap(),
+    (0..5)
+      .map(|i| (format!("local {}", i), 0))
+      .collect::<Vec<_>>()
+  );
+  assert_eq!(debugger.segment(0, "argument", None).unwrap(), []);
+}
+
+#[test]
+fn next_steps_over_calls() {
+  let mut debugger = debugger();
+  run_to(&mut debugger, 37);
+  assert_eq!(debugger.step_over().unwrap(), Stop::Step);
+  assert_eq!(line(&debugger), 38);
+  assert_eq!(debugger.step_over().unwrap(), Stop::Step);
+  assert_eq!(line(&debugger), 39);
+  assert_eq!(
+    frames(&debugger),
+    [("Sys.main".to_string(), 39), ("Sys.init".to_string(), 13)]
+  );
+  assert_eq!(debugger.step_over().unwrap(), Stop::Step);
+  assert_eq!(line(&debugger), 40);
+  assert_eq!(value(&debugger, 0, "temp", 0), 135);
+  // Other commands just step.
+  for expected in [41, 42] {
+    assert_eq!(debugger.step_over().unwrap(), Stop::Step);
+    assert_eq!(line(&debugger), expected);
+  }
+}
+
+#[test]
+fn frames_and_segments() {
+  let mut debugger = debugger();
+  run_to(&mut debugger, 60);
+  assert_eq!(
+    frames(&debugger),
+    [
+      ("Sys.add12".to_string(), 60),
+      ("Sys.main".to_string(), 38),
+      ("Sys.init".to_string(), 13),
+    ]
+  );
+  assert_eq!(value(&debugger, 0, "argument", 0), 123);
+  assert_eq!(
+    debugger.segment(0, "argument", None).unwrap(),
+    [("argument 0".to_string(), 123)]
+  );
+  assert_eq!(debugger.segment(0, "local", None).unwrap(), []);
+  assert_eq!(
+    debugger.segment(0, "pointer", None).unwrap(),
+    [
+      ("pointer 0".to_string(), 4002),
+      ("pointer 1".to_string(), 5002)
+    ]
+  );
+  // Callers' frames keep their own segments.
+  assert_eq!(value(&debugger, 1, "local", 1), 200);
+  assert_eq!(value(&debugger, 1, "local", 3), 6);
+  assert_eq!(value(&debugger, 1, "pointer", 0), 4001);
+  assert_eq!(value(&debugger, 2, "pointer", 1), 5000);
+  assert_eq!(
+    debugger.segment(3, "local", Some(0)),
+    Err("no frame 3".to_string())
+  );
+  assert_eq!(
+    debugger.segment(0, "heap", Some(0)),
+    Err("unknown segment heap".to_string())
+  );
+}
+
+#[test]
+fn finish_returns_to_the_caller() {
+  let mut debugger = debugger();
+  run_to(&mut debugger, 61);
+  debugger.breakpoints.clear();
+  assert_eq!(debugger.finish().unwrap(), Stop::Step);
+  assert_eq!(line(&debugger), 39);
+  assert_eq!(
+    frames(&debugger),
+    [("Sys.main".to_string(), 39), ("Sys.init".to_string(), 13)]
+  );
+  // The result replaced the argument on top of the caller's stack.
+  let sp = debugger.emulator.ram[0] as usize;
+  assert_eq!(debugger.emulator.ram[sp - 1], 135);
+  assert_eq!(debugger.finish().unwrap(), Stop::Step);
+  assert_eq!(line(&debugger), 14);
+  assert_eq!(
+    debugger.finish(),
+    Err("the outermost frame has no caller".to_string())
+  );
+}
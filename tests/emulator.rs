@@ -0,0 +1,143 @@
+use vm::emulator::{Emulator, Status};
+
+// Runs the single C instruction `asm` with the given A, D and RAM[A].
+fn execute(asm: &str, a: u16, d: u16, m: u16) -> Emulator {
+  let mut emulator = Emulator::from_asm(asm).unwrap();
+  emulator.a = a;
+  emulator.d = d;
+  if let Some(word) = emulator.ram.get_mut(a as usize) {
+    *word = m;
+  }
+  emulator.step().unwrap();
+  emulator
+}
+
+#[test]
+fn alu() {
+  let (a, d, m) = (5, 12, -3i16 as u16);
+  for (comp, expected) in [
+    ("0", 0),
+    ("1", 1),
+    ("-1", -1),
+    ("D", 12),
+    ("A", 5),
+    ("!D", -13),
+    ("!A", -6),
+    ("-D", -12),
+    ("-A", -5),
+    ("D+1", 13),
+    ("A+1", 6),
+    ("D-1", 11),
+    ("A-1", 4),
+    ("D+A", 17),
+    ("D-A", 7),
+    ("A-D", -7),
+    ("D&A", 4),
+    ("D|A", 13),
+    ("M", -3),
+    ("!M", 2),
+    ("-M", 3),
+    ("M+1", -2),
+    ("M-1", -4),
+    ("D+M", 9),
+    ("D-M", 15),
+    ("M-D", -15),
+    ("D&M", 12),
+    ("D|M", -3),
+  ] {
+    let emulator = execute(&format!("D={}", comp), a, d, m);
+    assert_eq!(emulator.d as i16, expected, "{}", comp);
+    assert_eq!((emulator.a, emulator.pc), (a, 1), "{}", comp);
+  }
+  // Arithmetic wraps around at 16 bits.
+  assert_eq!(execute("D=D+1", 0, 0x7fff, 0).d, 0x8000);
+  assert_eq!(execute("D=D-A", 1, 0x8000, 0).d, 0x7fff);
+}
+
+#[test]
+fn jumps() {
+  for (jump, taken) in [
+    ("", [false, false, false]),
+    ("JGT", [false, false, true]),
+    ("JEQ", [false, true, false]),
+    ("JGE", [false, true, true]),
+    ("JLT", [true, false, false]),
+    ("JNE", [true, false, true]),
+    ("JLE", [true, true, false]),
+    ("JMP", [true, true, true]),
+  ] {
+    for (d, taken) in [-1i16, 0, 1].iter().zip(taken) {
+      let asm = if jump.is_empty() {
+        "D".to_string()
+      } else {
+        format!("D;{}", jump)
+      };
+      let emulator = execute(&asm, 100, *d as u16, 0);
+      assert_eq!(emulator.pc, if taken { 100 } else { 1 }, "{} {}", jump, d);
+    }
+  }
+  // The comp decides, not D: -32768 is negative.
+  assert_eq!(execute("D-1;JLT", 100, 0x8000, 0).pc, 1);
+  assert_eq!(execute("A;JLT", 0x8000, 0, 0).pc, 0x8000);
+}
+
+#[test]
+fn writes_go_through_the_old_a() {
+  let emulator = execute("AM=M+1", 7, 0, 3);
+  assert_eq!((emulator.a, emulator.ram[7], emulator.ram[4]), (4, 4, 0));
+  let emulator = execute("AMD=M-1", 7, 0, 3);
+  assert_eq!(
+    (emulator.a, emulator.d, emulator.ram[7], emulator.ram[2]),
+    (2, 2, 2, 0)
+  );
+  // A jump goes to the old A too.
+  let emulator = execute("A=A+1;JMP", 7, 0, 0);
+  assert_eq!((emulator.a, emulator.pc), (8, 7));
+}
+
+#[test]
+fn halt_loops() {
+  // At `@END` of `(END) @END 0;JMP`.
+  let mut emulator = Emulator::from_asm("@2\n0;JMP\n(END)\n@END\n0;JMP\n").unwrap();
+  assert!(!emulator.is_halted());
+  emulator.step().unwrap();
+  assert!(!emulator.is_halted());
+  emulator.step().unwrap();
+  assert_eq!(emulator.pc, 2);
+  assert!(emulator.is_halted());
+  assert_eq!(emulator.run(10), Ok(Status::Halted));
+  assert_eq!(emulator.cycles, 2);
+  // At a jump to itself with A already loaded.
+  let mut emulator = Emulator::from_asm("@LOOP\n(LOOP)\n0;JMP\n").unwrap();
+  assert!(!emulator.is_halted());
+  emulator.step().unwrap();
+  assert!(emulator.is_halted());
+  // Only unconditional jumps that don't write anywhere are halt loops.
+  for asm in ["@0\nD;JEQ\n", "@0\nD=D+1;JMP\n"] {
+    assert!(!Emulator::from_asm(asm).unwrap().is_halted(), "{}", asm);
+  }
+  let mut emulator = Emulator::from_asm("(LOOP)\n@LOOP\nD=D+1\n0;JMP\n").unwrap();
+  assert_eq!(emulator.run(10), Ok(Status::CycleLimit));
+}
+
+#[test]
+fn out_of_bounds() {
+  let mut emulator = Emulator::from_asm("@1\n").unwrap();
+  emulator.pc = 32767;
+  emulator.step().unwrap();
+  assert_eq!(emulator.pc, 32768);
+  assert_eq!(emulator.step(), Err("PC 32768 outside ROM".to_string()));
+
+  let mut emulator = Emulator::from_asm("A=-1\nD=M\n").unwrap();
+  emulator.step().unwrap();
+  assert_eq!(
+    emulator.step(),
+    Err("read outside RAM at 65535 (PC 1)".to_string())
+  );
+  let mut emulator = Emulator::from_asm("@32767\nA=A+1\nM=1\n").unwrap();
+  assert_eq!(
+    emulator.run(3),
+    Err("write outside RAM at 32768 (PC 2)".to_string())
+  );
+  assert!(Emulator::new(&vec![0; 32769]).is_err());
+}
@@ -0,0 +1,193 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use vm::emulator::Emulator;
+use vm::gdb::GdbStub;
+
+// Stores 5 in RAM[100] and halts, followed by a loop that never halts.
+const PROGRAM: &str = "@5\nD=A\n@100\nM=D\n(END)\n@END\n0;JMP\n\
+                       (SPIN)\n@101\nM=M+1\n@SPIN\n0;JMP\n";
+
+struct Gdb {
+  stream: TcpStream,
+}
+
+fn packet(data: &str) -> String {
+  let sum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+  format!("${}#{:02x}", data, sum)
+}
+
+impl Gdb {
+  fn byte(&mut self) -> u8 {
+    let mut byte = [0];
+    self.stream.read_exact(&mut byte).unwrap();
+    byte[0]
+  }
+
+  // The stub's next packet, checking its checksum and acknowledging it.
+  fn reply(&mut self) -> String {
+    assert_eq!(self.byte(), b'$');
+    let mut data = Vec::new();
+    loop {
+      match self.byte() {
+        b'#' => break,
+        byte => data.push(byte),
+      }
+    }
+    let checksum = [self.byte(), self.byte()];
+    let data = String::from_utf8(data).unwrap();
+    assert_eq!(
+      packet(&data),
+      format!("${}#{}", data, std::str::from_utf8(&checksum).unwrap())
+    );
+    self.stream.write_all(b"+").unwrap();
+    data
+  }
+
+  // Kills the target, which ends the session without a reply.
+  fn kill(&mut self) {
+    self.stream.write_all(packet("k").as_bytes()).unwrap();
+    assert_eq!(self.byte(), b'+');
+    assert_eq!(self.stream.read(&mut [0]).unwrap(), 0);
+  }
+
+  fn request(&mut self, data: &str) -> String {
+    self.stream.write_all(packet(data).as_bytes()).unwrap();
+    assert_eq!(self.byte(), b'+', "ack for {}", data);
+    self.reply()
+  }
+}
+
+// Runs `session` against a stub for `PROGRAM`, returning the stub after gdb
+// leaves.
+fn session(session: impl FnOnce(&mut Gdb)) -> GdbStub {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = listener.local_addr().unwrap();
+  let server = thread::spawn(move || {
+    let mut stub = GdbStub::new(Emulator::from_asm(PROGRAM).unwrap());
+    stub.serve(&listener).unwrap();
+    stub
+  });
+  let mut gdb = Gdb {
+    stream: TcpStream::connect(address).unwrap(),
+  };
+  gdb.stream.set_nodelay(true).unwrap();
+  gdb
+    .stream
+    .set_read_timeout(Some(Duration::from_secs(10)))
+    .unwrap();
+  session(&mut gdb);
+  server.join().unwrap()
+}
+
+#[test]
+fn registers_and_memory() {
+  let stub = session(|gdb| {
+    assert_eq!(gdb.request("?"), "S05");
+    // A, D and PC, little endian, with PC the byte address of ROM word 0.
+    assert_eq!(gdb.request("g"), "0000000000000200");
+    assert_eq!(gdb.request("s"), "S05");
+    assert_eq!(gdb.request("s"), "S05");
+    assert_eq!(gdb.request("g"), "0500050004000200");
+    assert_eq!(gdb.request("p2"), "04000200");
+    assert_eq!(gdb.request("p3"), "E01");
+    assert_eq!(gdb.request("P1=0700"), "OK");
+    assert_eq!(gdb.request("P2=0700"), "E01");
+    assert_eq!(gdb.request("P2=05000200"), "E01");
+    assert_eq!(gdb.request("G0900ffff00000200"), "OK");
+    assert_eq!(gdb.request("g"), "0900ffff00000200");
+    assert_eq!(gdb.request("G0900ffff0000"), "E01");
+    // PC must stay inside ROM.
+    assert_eq!(gdb.request("G0000000000000300"), "E01");
+    // ROM words from 0x20000: @5 and D=A, which PC points at.
+    assert_eq!(gdb.request("m20000,4"), "050010ec");
+    assert_eq!(gdb.request("m20001,2"), "0010");
+    assert_eq!(gdb.request("m10000,2"), "E01");
+    assert_eq!(gdb.request("mffffffffffffffff,2"), "E01");
+    assert_eq!(gdb.request("Mffffffffffffffff,2:0000"), "E01");
+    assert_eq!(gdb.request("M2,2:3412"), "OK");
+    assert_eq!(gdb.request("m0,4"), "00003412");
+    assert_eq!(gdb.request("M0,2:34"), "E01");
+    assert_eq!(gdb.request("vMustReplyEmpty"), "");
+    gdb.kill();
+  });
+  assert_eq!(stub.emulator.ram[1], 0x1234);
+  assert_eq!((stub.emulator.a, stub.emulator.pc), (9, 0));
+}
+
+#[test]
+fn breakpoints_and_continue() {
+  let stub = session(|gdb| {
+    // The breakpoint on ROM word 3.
+    assert_eq!(gdb.request("Z0,20006,2"), "OK");
+    assert_eq!(gdb.request("Z0,3,2"), "E01");
+    assert_eq!(gdb.request("Z0,20007,2"), "E01");
+    assert_eq!(gdb.request("c"), "T05swbreak:;");
+    assert_eq!(gdb.request("p2"), "06000200");
+    assert_eq!(gdb.request("mc8,2"), "0000");
+    assert_eq!(gdb.request("s"), "S05");
+    assert_eq!(gdb.request("mc8,2"), "0500");
+    // Continuing stops in the halt loop.
+    assert_eq!(gdb.request("c"), "S05");
+    assert_eq!(gdb.request("p2"), "08000200");
+    assert_eq!(gdb.request("z0,20006,2"), "OK");
+    assert_eq!(gdb.request("c0"), "E01");
+    assert_eq!(gdb.request("c20000"), "S05");
+    assert_eq!(gdb.request("Z2,3,2"), "");
+    assert_eq!(gdb.request("D"), "OK");
+  });
+  assert_eq!(stub.emulator.pc, 4);
+  assert_eq!(stub.emulator.ram[100], 5);
+}
+
+#[test]
+fn interrupt() {
+  let stub = session(|gdb| {
+    gdb.stream.write_all(packet("c2000c").as_bytes()).unwrap();
+    assert_eq!(gdb.byte(), b'+');
+    thread::sleep(Duration::from_millis(50));
+    gdb.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(gdb.reply(), "S02");
+    gdb.kill();
+  });
+  assert!(stub.emulator.ram[101] > 0);
+}
+
+#[test]
+fn checksums_and_queries() {
+  session(|gdb| {
+    // A corrupted packet is refused and resent.
+    gdb.stream.write_all(b"$g#00").unwrap();
+    assert_eq!(gdb.byte(), b'-');
+    assert_eq!(gdb.request("g"), "0000000000000200");
+    assert_eq!(
+      gdb.request("qSupported:multiprocess+"),
+      "PacketSize=4000;qXfer:features:read+;swbreak+"
+    );
+    let mut xml = String::new();
+    loop {
+      let reply = gdb.request(&format!(
+        "qXfer:features:read:target.xml:{:x},40",
+        xml.len()
+      ));
+      xml += &reply[1..];
+      if reply.starts_with('l') {
+        break;
+      }
+      assert!(reply.starts_with('m'));
+    }
+    assert!(xml.contains("<architecture>hack</architecture>"), "{}", xml);
+    assert!(xml.contains("<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"2\"/>"));
+    assert!(xml.ends_with("</target>"));
+    assert_eq!(gdb.request("qAttached"), "1");
+    // Packets that aren't ASCII get the empty reply for unknown commands.
+    assert_eq!(gdb.request("é"), "");
+    // Bytes that aren't UTF-8 at all.
+    gdb.stream.write_all(b"$\xff1#30").unwrap();
+    assert_eq!(gdb.byte(), b'+');
+    assert_eq!(gdb.reply(), "");
+    gdb.kill();
+  });
+}
@@ -0,0 +1,264 @@
+use vm::emulator::Emulator;
+use vm::gif::{GifEncoder, ScreenRecorder};
+use vm::screen::{Screen, HEIGHT, WIDTH};
+
+// A decoded GIF: the image after each frame with how long it is shown, and
+// the bounds each frame was stored with.
+#[derive(Debug)]
+struct Gif {
+  width: usize,
+  height: usize,
+  frames: Vec<(Vec<bool>, u16)>,
+  bounds: Vec<(usize, usize, usize, usize)>,
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+}
+
+impl Reader<'_> {
+  fn take(&mut self, len: usize) -> &[u8] {
+    self.pos += len;
+    &self.bytes[self.pos - len..self.pos]
+  }
+
+  fn byte(&mut self) -> u8 {
+    self.take(1)[0]
+  }
+
+  fn word(&mut self) -> usize {
+    u16::from_le_bytes([self.byte(), self.byte()]) as usize
+  }
+
+  fn sub_blocks(&mut self) -> Vec<u8> {
+    let mut data = Vec::new();
+    loop {
+      match self.byte() {
+        0 => return data,
+        len => data.extend_from_slice(self.take(len as usize)),
+      }
+    }
+  }
+}
+
+// A plain GIF decoder for what the encoder writes: the global black and
+// white palette, the looping extension, and frames drawn over the previous
+// ones.
+fn decode(bytes: &[u8]) -> Gif {
+  let mut reader = Reader { bytes, pos: 0 };
+  assert_eq!(reader.take(6), b"GIF89a");
+  let (width, height) = (reader.word(), reader.word());
+  assert_eq!(reader.take(3), [0x80, 0, 0]);
+  assert_eq!(reader.take(6), [0xff, 0xff, 0xff, 0, 0, 0]);
+  assert_eq!(reader.take(3), [0x21, 0xff, 0x0b]);
+  assert_eq!(reader.take(11), b"NETSCAPE2.0");
+  assert_eq!(reader.sub_blocks(), [1, 0, 0]);
+
+  let mut image = vec![false; width * height];
+  let mut gif = Gif {
+    width,
+    height,
+    frames: Vec::new(),
+    bounds: Vec::new(),
+  };
+  let mut delay = None;
+  loop {
+    match reader.byte() {
+      0x21 => {
+        assert_eq!(reader.byte(), 0xf9);
+        let control = reader.sub_blocks();
+        assert_eq!(control.len(), 4);
+        assert_eq!(control[0], 0x04);
+        delay = Some(u16::from_le_bytes([control[1], control[2]]));
+      }
+      0x2c => {
+        let (left, top) = (reader.word(), reader.word());
+        let (frame_width, frame_height) = (reader.word(), reader.word());
+        assert_eq!(reader.byte(), 0);
+        let code_size = reader.byte();
+        let indices = lzw_decode(code_size, &reader.sub_blocks());
+        assert_eq!(indices.len(), frame_width * frame_height);
+        for (i, index) in indices.iter().enumerate() {
+          let (x, y) = (left + i % frame_width, top + i / frame_width);
+          image[y * width + x] = *index == 1;
+        }
+        gif.frames.push((image.clone(), delay.take().unwrap()));
+        gif
+          .bounds
+          .push((left, top, left + frame_width, top + frame_height));
+      }
+      0x3b => break,
+      byte => panic!("unexpected block {:#x}", byte),
+    }
+  }
+  assert_eq!(reader.pos, bytes.len());
+  gif
+}
+
+fn lzw_decode(min_size: u8, data: &[u8]) -> Vec<u8> {
+  let clear = 1usize << min_size;
+  let end = clear + 1;
+  let initial: Vec<Vec<u8>> = (0..=end).map(|code| vec![code as u8]).collect();
+  let mut table = initial.clone();
+  let mut size = min_size + 1;
+  let mut pos = 0;
+  let mut previous: Option<Vec<u8>> = None;
+  let mut indices = Vec::new();
+  loop {
+    let code = read_code(data, pos, size);
+    pos += size as usize;
+    if code == clear {
+      table = initial.clone();
+      size = min_size + 1;
+      previous = None;
+      continue;
+    }
+    if code == end {
+      break;
+    }
+    let entry = match table.get(code) {
+      Some(entry) => entry.clone(),
+      None => {
+        assert_eq!(code, table.len(), "code out of order");
+        let previous = previous.as_ref().unwrap();
+        let mut entry = previous.clone();
+        entry.push(previous[0]);
+        entry
+      }
+    };
+    if let Some(mut previous) = previous.take() {
+      if table.len() < 4096 {
+        previous.push(entry[0]);
+        table.push(previous);
+        if table.len() == 1 << size && size < 12 {
+          size += 1;
+        }
+      }
+    }
+    indices.extend_from_slice(&entry);
+    previous = Some(entry);
+  }
+  // Nothing but the padding of the last byte follows the end code.
+  assert_eq!(pos.div_ceil(8), data.len());
+  indices
+}
+
+// The `size` bit code at bit `pos`, packed least significant bit first.
+fn read_code(data: &[u8], pos: usize, size: u8) -> usize {
+  (0..size as usize).fold(0, |code, bit| {
+    let byte = data[(pos + bit) / 8] as usize;
+    code | (byte >> ((pos + bit) % 8) & 1) << bit
+  })
+}
+
+// Pixels from a simple generator, so they don't compress too well.
+fn noise(len: usize, seed: u32) -> Vec<bool> {
+  let mut state = seed;
+  (0..len)
+    .map(|_| {
+      state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+      state >> 16 & 1 == 1
+    })
+    .collect()
+}
+
+#[test]
+fn header() {
+  let gif = GifEncoder::new(3, 2).finish();
+  assert_eq!(
+    gif,
+    [
+      b"GIF89a".as_slice(),
+      &[3, 0, 2, 0, 0x80, 0, 0],
+      &[0xff, 0xff, 0xff, 0, 0, 0],
+      b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00",
+      &[0x3b],
+    ]
+    .concat()
+  );
+  let gif = decode(&gif);
+  assert_eq!((gif.width, gif.height), (3, 2));
+  assert!(gif.frames.is_empty());
+}
+
+#[test]
+fn frames() {
+  let (width, height) = (10, 6);
+  let blank = vec![false; width * height];
+  let mut dot = blank.clone();
+  dot[2 * width + 3] = true;
+  dot[4 * width + 7] = true;
+  let mut encoder = GifEncoder::new(width, height);
+  encoder.add_frame(&blank, 5);
+  encoder.add_frame(&dot, 4);
+  // Repeats only lengthen the frame shown.
+  encoder.add_frame(&dot, 4);
+  encoder.add_frame(&dot, 2);
+  encoder.add_frame(&blank, 3);
+  encoder.add_frame(&blank, 7);
+  let gif = decode(&encoder.finish());
+  assert_eq!(
+    gif.frames,
+    [(blank.clone(), 5), (dot.clone(), 10), (blank.clone(), 10)]
+  );
+  // Later frames only store the rectangle that changed.
+  assert_eq!(
+    gif.bounds,
+    [(0, 0, width, height), (3, 2, 8, 5), (3, 2, 8, 5)]
+  );
+
+  // Frames that only differ in their delay still hold a pixel.
+  let mut encoder = GifEncoder::new(width, height);
+  encoder.add_frame(&dot, u16::MAX - 1);
+  encoder.add_frame(&dot, 2);
+  encoder.add_frame(&dot, 3);
+  let gif = decode(&encoder.finish());
+  assert_eq!(gif.frames, [(dot.clone(), u16::MAX), (dot.clone(), 3)]);
+  assert_eq!(gif.bounds[1], (0, 0, 1, 1));
+}
+
+#[test]
+fn large_frames() {
+  // Enough varied pixels to fill the code table several times over.
+  let frames: Vec<Vec<bool>> = (0..3).map(|seed| noise(WIDTH * HEIGHT, seed)).collect();
+  let mut encoder = GifEncoder::new(WIDTH, HEIGHT);
+  for frame in &frames {
+    encoder.add_frame(frame, 1);
+  }
+  encoder.add_frame(&vec![true; WIDTH * HEIGHT], 1);
+  let gif = decode(&encoder.finish());
+  let images: Vec<&Vec<bool>> = gif.frames.iter().map(|(image, _)| image).collect();
+  assert_eq!(images[..3], frames.iter().collect::<Vec<_>>()[..]);
+  assert!(images[3].iter().all(|black| *black));
+}
+
+#[test]
+fn recorder() {
+  // Blackens one screen word after another, 10 cycles a word, then halts.
+  let asm = "@SCREEN\nD=A\n@address\nM=D\n\
+             (LOOP)\n@address\nA=M\nM=-1\n@address\nM=M+1\n\
+             D=M\n@16416\nD=D-A\n@LOOP\nD;JLT\n\
+             (END)\n@END\n0;JMP\n";
+  let mut emulator = Emulator::from_asm(asm).unwrap();
+  let mut recorder = ScreenRecorder::new(100, 4);
+  for _ in 0..1000 {
+    recorder.capture(&emulator);
+    if emulator.is_halted() {
+      break;
+    }
+    emulator.step().unwrap();
+  }
+  assert!(emulator.is_halted());
+  let (bytes, frames) = recorder.finish(&emulator);
+  let gif = decode(&bytes);
+  assert_eq!(frames, gif.frames.len());
+  assert_eq!((gif.width, gif.height), (WIDTH, HEIGHT));
+  // Frames at cycles 0, 100, 200 and 300, and the final screen.
+  assert_eq!(emulator.cycles, 324);
+  assert_eq!(frames, 5);
+  let (last, _) = gif.frames.last().unwrap();
+  assert_eq!(last, Screen::new(&emulator.ram).pixels());
+  assert_eq!(last.iter().filter(|black| **black).count(), 32 * 16);
+  assert!(gif.frames.iter().all(|(_, delay)| *delay == 4));
+}
@@ -0,0 +1,107 @@
+use vm::emulator::Emulator;
+use vm::history::{History, MAX_SEEK_CYCLES};
+
+// Adds up 20000 down to 1 in `sum`, 10 cycles a number, then halts.
+const SUM: &str = "@20000\nD=A\n@n\nM=D\n\
+                   (LOOP)\n@n\nD=M\n@END\nD;JEQ\n@sum\nM=D+M\n@n\nM=M-1\n@LOOP\n0;JMP\n\
+                   (END)\n@END\n0;JMP\n";
+
+type State = (u16, u16, u16, u64, Vec<u16>);
+
+fn state(emulator: &Emulator) -> State {
+  (
+    emulator.pc,
+    emulator.a,
+    emulator.d,
+    emulator.cycles,
+    emulator.ram.clone(),
+  )
+}
+
+// The state after running `cycles` cycles from the start, without history.
+fn at(cycles: u64) -> State {
+  let mut emulator = Emulator::from_asm(SUM).unwrap();
+  for _ in 0..cycles {
+    emulator.step().unwrap();
+  }
+  state(&emulator)
+}
+
+fn run(cycles: u64, max_deltas: usize) -> (Emulator, History) {
+  let mut emulator = Emulator::from_asm(SUM).unwrap();
+  let mut history = History::new(&emulator, max_deltas);
+  for _ in 0..cycles {
+    history.step(&mut emulator).unwrap();
+  }
+  (emulator, history)
+}
+
+#[test]
+fn step_back_to_the_start() {
+  // Far more cycles than deltas kept, so stepping back has to replay from
+  // snapshots again and again.
+  let (mut emulator, mut history) = run(120_000, 1000);
+  assert_eq!(history.first_undoable(&emulator), 119_000);
+  let mut expected = at(120_000);
+  for cycle in (0..120_000).rev() {
+    let write = history.undo(&mut emulator).unwrap();
+    if cycle % 9973 == 0 || cycle == 119_000 || cycle == 100_000 {
+      let state = state(&emulator);
+      expected = at(cycle);
+      assert!(state == expected, "state differs at cycle {}", cycle);
+    }
+    // The instruction at this cycle wrote the RAM word it reports.
+    if let Some(address) = write {
+      assert_eq!(emulator.rom[emulator.pc as usize] & 0x8008, 0x8008);
+      assert_eq!(emulator.a, address);
+    }
+  }
+  assert_eq!(emulator.cycles, 0);
+  assert!(history.undo(&mut emulator).is_none());
+  assert!(state(&emulator) == expected);
+}
+
+#[test]
+fn seek_round_trips() {
+  let (mut emulator, mut history) = run(150_000, 5000);
+  for cycle in [
+    149_000, 60_000, 40_000, 140_000, 150_000, 0, 99_999, 100_000, 150_001, 175_000,
+  ] {
+    history.seek(&mut emulator, cycle).unwrap();
+    assert!(
+      state(&emulator) == at(cycle),
+      "state differs at cycle {}",
+      cycle
+    );
+  }
+  // Stepping back from a cycle reached through a snapshot.
+  history.seek(&mut emulator, 60_000).unwrap();
+  for _ in 0..15_000 {
+    history.undo(&mut emulator).unwrap();
+  }
+  assert!(state(&emulator) == at(45_000));
+  assert!(history.seek(&mut emulator, 180_000).is_ok());
+  assert!(state(&emulator) == at(180_000));
+}
+
+#[test]
+fn seek_forward_stops_at_halt_and_is_capped() {
+  let (mut emulator, mut history) = run(0, 1000);
+  let error = history
+    .seek(&mut emulator, MAX_SEEK_CYCLES + 1)
+    .unwrap_err();
+  assert!(error.contains("ahead"), "{}", error);
+  assert_eq!(emulator.cycles, 0);
+  // The program halts after 200_000 or so cycles.
+  history.seek(&mut emulator, MAX_SEEK_CYCLES).unwrap();
+  assert!(emulator.is_halted());
+  let halted = emulator.cycles;
+  assert!(halted < 300_000);
+  assert_eq!(emulator.ram[17], (20_000u32 * 20_001 / 2) as u16);
+  // Back in history the halt loop doesn't stop the emulator.
+  history.seek(&mut emulator, 10).unwrap();
+  history.seek(&mut emulator, halted).unwrap();
+  assert_eq!(emulator.cycles, halted);
+  assert!(history.seek(&mut emulator, 0).is_ok());
+  assert!(state(&emulator) == at(0));
+}
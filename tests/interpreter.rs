@@ -0,0 +1,139 @@
+use vm::interpreter::{Interpreter, Status, ARG, LCL, SP, THAT, THIS};
+use vm::VmParser;
+
+fn interpreter(files: &[(&str, &str)]) -> Interpreter {
+  Interpreter::new(
+    files
+      .iter()
+      .map(|(name, text)| VmParser::from_source(name, text))
+      .collect(),
+  )
+  .unwrap()
+}
+
+// Runs commands without a bootstrap, returning the stack.
+fn run(commands: &str) -> Vec<u16> {
+  let mut interpreter = interpreter(&[("Main.vm", commands)]);
+  interpreter.ram[SP] = 256;
+  assert_eq!(interpreter.run(1000), Ok(Status::Halted));
+  interpreter.ram[256..interpreter.ram[SP] as usize].to_vec()
+}
+
+#[test]
+fn arithmetic_wraps_around_at_16_bits() {
+  let stack = run(
+    "push constant 32767\npush constant 1\nadd\n\
+     push constant 32767\npush constant 1\nadd\npush constant 32767\npush constant 1\nadd\nadd\n\
+     push constant 0\npush constant 1\nsub\n\
+     push constant 1\nneg\n\
+     push constant 0\nneg\n\
+     push constant 32767\npush constant 1\nadd\nneg\n",
+  );
+  assert_eq!(stack, [0x8000, 0, 0xffff, 0xffff, 0, 0x8000]);
+}
+
+#[test]
+fn comparisons_are_signed() {
+  let stack = run(
+    "push constant 1\nneg\npush constant 1\nlt\n\
+     push constant 1\npush constant 1\nneg\nlt\n\
+     push constant 32767\npush constant 1\nneg\ngt\n\
+     push constant 32767\npush constant 1\nadd\npush constant 32767\ngt\n\
+     push constant 5\npush constant 5\ngt\n\
+     push constant 5\npush constant 5\nlt\n",
+  );
+  assert_eq!(stack, [0xffff, 0, 0xffff, 0, 0, 0]);
+}
+
+const SYS: &str = "function Sys.init 0\n\
+                   push constant 3000\n\
+                   pop pointer 0\n\
+                   push constant 4000\n\
+                   pop pointer 1\n\
+                   push constant 10\n\
+                   push constant 20\n\
+                   call Main.sub 2\n\
+                   label END\n\
+                   goto END\n";
+
+const MAIN: &str = "function Main.sub 1\n\
+                    push constant 3100\n\
+                    pop pointer 0\n\
+                    push argument 0\n\
+                    push argument 1\n\
+                    sub\n\
+                    return\n";
+
+#[test]
+fn call_and_return_frames() {
+  let mut interpreter = interpreter(&[("Sys.vm", SYS), ("Main.vm", MAIN)]);
+  interpreter.bootstrap().unwrap();
+  // The bootstrap's frame for Sys.init is at 256..261.
+  assert_eq!(
+    [
+      interpreter.ram[SP],
+      interpreter.ram[LCL],
+      interpreter.ram[ARG]
+    ],
+    [261, 261, 256]
+  );
+  while interpreter.current().unwrap().text != "push argument 0" {
+    assert_eq!(interpreter.step(), Ok(Status::Running));
+  }
+  let ram = &interpreter.ram;
+  // The arguments, the return address (the command after the call), the
+  // caller's LCL, ARG, THIS and THAT, and one local set to 0.
+  assert_eq!(&ram[261..269], &[10, 20, 8, 261, 256, 3000, 4000, 0][..]);
+  assert_eq!(interpreter.commands[8].text, "label END");
+  assert_eq!(
+    [ram[SP], ram[LCL], ram[ARG], ram[THIS], ram[THAT]],
+    [269, 268, 261, 3100, 4000]
+  );
+
+  assert_eq!(interpreter.run(100), Ok(Status::Halted));
+  let ram = &interpreter.ram;
+  // The return value replaces the arguments and the caller's pointers are
+  // back.
+  assert_eq!(ram[261], (-10i16) as u16);
+  assert_eq!(
+    [ram[SP], ram[LCL], ram[ARG], ram[THIS], ram[THAT]],
+    [262, 261, 256, 3000, 4000]
+  );
+  assert_eq!(interpreter.current().unwrap().text, "goto END");
+}
+
+#[test]
+fn statics_belong_to_their_file() {
+  let mut interpreter = interpreter(&[
+    ("Sys.vm", "function Sys.init 0\npush constant 1\npop static 1\npush constant 2\npop static 0\ncall Other.set 0\nreturn\n"),
+    ("Other.vm", "function Other.set 0\npush constant 3\npop static 0\npush static 0\npush static 0\nadd\nreturn\n"),
+  ]);
+  // Addresses from 16 in order of first use, one set per file.
+  assert_eq!(interpreter.static_address("Sys", 1), Some(16));
+  assert_eq!(interpreter.static_address("Sys", 0), Some(17));
+  assert_eq!(interpreter.static_address("Other", 0), Some(18));
+  assert_eq!(interpreter.static_address("Other", 1), None);
+  assert_eq!(interpreter.statics().count(), 3);
+  interpreter.bootstrap().unwrap();
+  assert_eq!(interpreter.run(100), Ok(Status::Halted));
+  assert_eq!(&interpreter.ram[16..19], &[1, 2, 3][..]);
+  assert_eq!(interpreter.ram[256], 6);
+}
+
+#[test]
+fn errors() {
+  let error =
+    |text: &str| Interpreter::new(vec![VmParser::from_source("Main.vm", text)]).unwrap_err();
+  assert_eq!(
+    error("goto NOWHERE"),
+    "Main.vm:1: goto NOWHERE: undefined label NOWHERE"
+  );
+  assert_eq!(error("label A\nlabel A"), "Main.vm:2: duplicate label A");
+  assert_eq!(
+    error("push temp 8"),
+    "Main.vm:1: push temp 8: temp 8 out of range"
+  );
+  assert!(interpreter(&[("Main.vm", "push constant 1")])
+    .bootstrap()
+    .is_err());
+}
@@ -0,0 +1,62 @@
+use vm::json::{Json, MAX_DEPTH};
+
+#[test]
+fn round_trip() {
+  let json = Json::object(vec![
+    ("name", "a \"quoted\"\nline".into()),
+    ("count", Json::Number(-3)),
+    ("flags", Json::Array(vec![true.into(), Json::Null])),
+    ("empty", Json::object(vec![])),
+  ]);
+  assert_eq!(json.to_string().parse::<Json>(), Ok(json));
+  assert_eq!(
+    " { \"a\" : [ 1 , 2 ] } ".parse::<Json>(),
+    Ok(Json::object(vec![(
+      "a",
+      Json::Array(vec![Json::Number(1), Json::Number(2)])
+    )]))
+  );
+}
+
+#[test]
+fn numbers() {
+  let number = |str: &str| str.parse::<Json>();
+  assert_eq!(number("42"), Ok(Json::Number(42)));
+  assert_eq!(number("-7"), Ok(Json::Number(-7)));
+  assert_eq!(number("1e5"), Ok(Json::Number(100_000)));
+  assert_eq!(number("2.5E+1"), Ok(Json::Number(25)));
+  assert_eq!(number("3.0"), Ok(Json::Number(3)));
+  assert_eq!(number("-1.20e1"), Ok(Json::Number(-12)));
+  assert_eq!(
+    number("1.5"),
+    Err("1.5 is not an integer at offset 3".to_string())
+  );
+  assert_eq!(
+    number("1e-1"),
+    Err("1e-1 is not an integer at offset 4".to_string())
+  );
+  assert_eq!(
+    number("1e999"),
+    Err("1e999 is not an integer at offset 5".to_string())
+  );
+  assert_eq!(
+    number("[1-2]"),
+    Err("invalid number 1-2 at offset 4".to_string())
+  );
+}
+
+#[test]
+fn nesting() {
+  let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+  assert!(nested(MAX_DEPTH).parse::<Json>().is_ok());
+  assert_eq!(
+    nested(MAX_DEPTH + 1).parse::<Json>(),
+    Err(format!(
+      "nesting deeper than {} levels at offset {}",
+      MAX_DEPTH, MAX_DEPTH
+    ))
+  );
+  // Deep enough to overflow the stack if it were recursed into.
+  let deep = "{\"a\":".repeat(100_000);
+  assert!(deep.parse::<Json>().is_err());
+}
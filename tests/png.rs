@@ -0,0 +1,39 @@
+use vm::png::{crc32, decode, zlib};
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+  png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  png.extend_from_slice(kind);
+  png.extend_from_slice(data);
+  png.extend_from_slice(&crc32(&[kind, data].concat()).to_be_bytes());
+}
+
+// A 4x1 image with 2-bit palette indices 0 to 3.
+fn indexed(palette: &[u8]) -> Vec<u8> {
+  let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+  // 4x1, bit depth 2, indexed color, deflate, adaptive filtering, no
+  // interlace.
+  chunk(&mut png, b"IHDR", &[0, 0, 0, 4, 0, 0, 0, 1, 2, 3, 0, 0, 0]);
+  chunk(&mut png, b"PLTE", palette);
+  chunk(&mut png, b"IDAT", &zlib(&[0, 0b00_01_10_11]));
+  chunk(&mut png, b"IEND", &[]);
+  png
+}
+
+#[test]
+fn palettes() {
+  let palette = [0, 0, 0, 30, 60, 90, 255, 255, 255, 10, 20, 30];
+  let image = decode(&indexed(&palette)).unwrap();
+  assert_eq!((image.width, image.height), (4, 1));
+  assert_eq!(image.gray, [0, 60, 255, 20]);
+
+  assert_eq!(
+    decode(&indexed(&palette[..6])).map(|image| image.gray),
+    Err("palette index out of range".to_string())
+  );
+  for len in [0, 3, 5, 10] {
+    assert_eq!(
+      decode(&indexed(&palette[..len])).map(|image| image.gray),
+      Err(format!("invalid PNG palette of {} bytes", len))
+    );
+  }
+}
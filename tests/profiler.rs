@@ -0,0 +1,123 @@
+use vm::profiler::{self, Profile};
+use vm::VmParser;
+
+const SYS: &str = "function Sys.init 0\n\
+                   call Main.one 0\n\
+                   call Main.one 0\n\
+                   call Main.two 0\n\
+                   label END\n\
+                   goto END\n";
+
+const MAIN: &str = "function Main.one 0\n\
+                    push constant 1\n\
+                    return\n\
+                    function Main.two 1\n\
+                    call Main.one 0\n\
+                    return\n";
+
+fn profile(max_cycles: u64) -> Profile {
+  profiler::profile(
+    vec![
+      VmParser::from_source("Sys.vm", SYS),
+      VmParser::from_source("Main.vm", MAIN),
+    ],
+    max_cycles,
+  )
+  .unwrap()
+}
+
+#[test]
+fn cycles_per_function() {
+  let profile = profile(10_000);
+  assert!(profile.halted);
+  // Every command runs straight through, but for `function` declarations:
+  // with no locals they skip the 8 instruction loop that pushes them.
+  let count = |file: &str, line: usize| {
+    let mapping = profile
+      .source_map
+      .mappings
+      .iter()
+      .find(|mapping| mapping.file.as_deref() == Some(file) && mapping.line == line)
+      .unwrap();
+    mapping.count as u64
+  };
+  let bootstrap = profile.source_map.mappings[0].count as u64;
+  let (call, ret) = (count("Sys.vm", 2), count("Main.vm", 3));
+  let one = count("Main.vm", 1) - 8 + count("Main.vm", 2) + ret;
+  let two = count("Main.vm", 4) + call + ret;
+  let sys_init = count("Sys.vm", 1) - 8 + 3 * call;
+  assert_eq!([bootstrap, one, two, sys_init], [52, 68, 117, 148]);
+  assert_eq!(profile.cycles, bootstrap + 3 * one + two + sys_init);
+  assert_eq!(
+    profile.folded(),
+    format!(
+      "(bootstrap) {}\n\
+       (bootstrap);Sys.init {}\n\
+       (bootstrap);Sys.init;Main.one {}\n\
+       (bootstrap);Sys.init;Main.two {}\n\
+       (bootstrap);Sys.init;Main.two;Main.one {}\n",
+      bootstrap,
+      sys_init,
+      2 * one,
+      two,
+      one
+    )
+  );
+
+  let flat = profile.flat();
+  let row = |name: &str, own: u64, inclusive: u64, calls: u64| {
+    format!(
+      "{:<40}{:>12}{:>7.1}%{:>12}{:>7.1}%{:>10}\n",
+      name,
+      own,
+      own as f64 * 100.0 / 521.0,
+      inclusive,
+      inclusive as f64 * 100.0 / 521.0,
+      calls
+    )
+  };
+  assert!(flat.starts_with("Total: 521 cycles\n"), "{}", flat);
+  for row in [
+    row("Main.one", 204, 204, 3),
+    row("Sys.init", 148, 469, 1),
+    row("Main.two", 117, 185, 1),
+    row("(bootstrap)", 52, 521, 0),
+  ] {
+    assert!(flat.contains(&row), "{}\n{}", row, flat);
+  }
+  assert!(flat.contains(&format!("{:<60}{:>12}", "Main.vm:3: return", 3 * ret)));
+  let node = |inclusive: u64, own: u64, depth: usize, name: &str| {
+    format!(
+      "{:>12}{:>7.1}%{:>12}  {:indent$}{}\n",
+      inclusive,
+      inclusive as f64 * 100.0 / 521.0,
+      own,
+      "",
+      name,
+      indent = depth * 2
+    )
+  };
+  assert_eq!(
+    profile.call_tree(),
+    format!("{:>12}{:>8}{:>12}  Function\n", "inclusive", "%", "self")
+      + &node(521, 52, 0, "(bootstrap)")
+      + &node(469, 148, 1, "Sys.init")
+      + &node(185, 117, 2, "Main.two")
+      + &node(68, 68, 3, "Main.one")
+      + &node(136, 136, 2, "Main.one")
+  );
+}
+
+#[test]
+fn cycle_limit() {
+  let profile = profile(100);
+  assert!(!profile.halted);
+  assert_eq!(profile.cycles, 100);
+  assert!(profile
+    .flat()
+    .starts_with("Total: 100 cycles (cycle limit)\n"));
+  assert_eq!(
+    profile.folded(),
+    "(bootstrap) 52\n(bootstrap);Sys.init 48\n"
+  );
+}
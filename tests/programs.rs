@@ -0,0 +1,496 @@
+use std::path::Path;
+
+use vm::checks;
+use vm::debugger::{Debugger, Stop};
+use vm::emulator::{Emulator, Status};
+use vm::history;
+use vm::keyboard::KeyboardScript;
+use vm::loops::Stuck;
+use vm::screen::{self, Screen};
+use vm::snapshot::Snapshot;
+use vm::traps::Trap;
+use vm::watch::Condition;
+use vm::VmParser;
+
+// Translates the program at `path` (a `.vm` file or a directory) and sets up
+// RAM.
+fn load(path: &str, ram: &[(usize, i16)]) -> Emulator {
+  build(path, ram, false)
+}
+
+fn build(path: &str, ram: &[(usize, i16)], checked: bool) -> Emulator {
+  let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+    .join("tests/programs")
+    .join(path);
+  let parsers = VmParser::open(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+  let mut emulator = vm::build(parsers, checked).unwrap().emulator;
+  for (address, value) in ram {
+    emulator.ram[*address] = *value as u16;
+  }
+  emulator
+}
+
+// Loads the program and runs it in the emulator for at most `cycles` cycles,
+// failing as soon as it gets stuck in a loop.
+fn emulate(path: &str, ram: &[(usize, i16)], cycles: u64) -> Emulator {
+  let mut emulator = load(path, ram);
+  emulator.enable_loop_detection();
+  emulator
+    .run(cycles)
+    .unwrap_or_else(|err| panic!("{} after {} cycles", err, emulator.cycles));
+  emulator
+}
+
+fn assert_ram(emulator: &Emulator, expected: &[(usize, i16)]) {
+  for (address, value) in expected {
+    assert_eq!(emulator.ram[*address] as i16, *value, "RAM[{}]", address);
+  }
+}
+
+#[test]
+fn simple_add() {
+  let emulator = emulate("07/StackArithmetic/SimpleAdd/SimpleAdd.vm", &[(0, 256)], 60);
+  assert_ram(&emulator, &[(0, 257), (256, 15)]);
+}
+
+#[test]
+fn stack_test() {
+  let emulator = emulate(
+    "07/StackArithmetic/StackTest/StackTest.vm",
+    &[(0, 256)],
+    1000,
+  );
+  assert_ram(
+    &emulator,
+    &[
+      (0, 266),
+      (256, -1),
+      (257, 0),
+      (258, 0),
+      (259, 0),
+      (260, -1),
+      (261, 0),
+      (262, -1),
+      (263, 0),
+      (264, 0),
+      (265, -91),
+    ],
+  );
+}
+
+#[test]
+fn basic_test() {
+  let emulator = emulate(
+    "07/MemoryAccess/BasicTest/BasicTest.vm",
+    &[(0, 256), (1, 300), (2, 400), (3, 3000), (4, 3010)],
+    600,
+  );
+  assert_ram(
+    &emulator,
+    &[
+      (256, 472),
+      (300, 10),
+      (401, 21),
+      (402, 22),
+      (3006, 36),
+      (3012, 42),
+      (3015, 45),
+      (11, 510),
+    ],
+  );
+}
+
+#[test]
+fn pointer_test() {
+  let emulator = emulate(
+    "07/MemoryAccess/PointerTest/PointerTest.vm",
+    &[(0, 256)],
+    450,
+  );
+  assert_ram(
+    &emulator,
+    &[(256, 6084), (3, 3030), (4, 3040), (3032, 32), (3046, 46)],
+  );
+}
+
+#[test]
+fn static_test() {
+  let emulator = emulate("07/MemoryAccess/StaticTest/StaticTest.vm", &[(0, 256)], 200);
+  assert_ram(&emulator, &[(256, 1110)]);
+}
+
+#[test]
+fn basic_loop() {
+  let emulator = emulate(
+    "08/ProgramFlow/BasicLoop/BasicLoop.vm",
+    &[(0, 256), (1, 300), (2, 400), (400, 3)],
+    600,
+  );
+  assert_ram(&emulator, &[(0, 257), (256, 6)]);
+}
+
+#[test]
+fn fibonacci_series() {
+  let emulator = emulate(
+    "08/ProgramFlow/FibonacciSeries/FibonacciSeries.vm",
+    &[(0, 256), (1, 300), (2, 400), (400, 6), (401, 3000)],
+    1100,
+  );
+  assert_ram(
+    &emulator,
+    &[
+      (3000, 0),
+      (3001, 1),
+      (3002, 1),
+      (3003, 2),
+      (3004, 3),
+      (3005, 5),
+    ],
+  );
+}
+
+#[test]
+fn simple_function() {
+  let emulator = emulate(
+    "08/FunctionCalls/SimpleFunction/SimpleFunction.vm",
+    &[
+      (0, 317),
+      (1, 317),
+      (2, 310),
+      (3, 3000),
+      (4, 4000),
+      (310, 1234),
+      (311, 37),
+      (312, 1000),
+      (313, 305),
+      (314, 300),
+      (315, 3010),
+      (316, 4010),
+    ],
+    300,
+  );
+  assert_ram(
+    &emulator,
+    &[
+      (0, 311),
+      (1, 305),
+      (2, 300),
+      (3, 3010),
+      (4, 4010),
+      (310, 1196),
+    ],
+  );
+}
+
+#[test]
+fn nested_call() {
+  // The stack is filled with -1 to check that locals are cleared to zero.
+  let mut ram = vec![(3, -3), (4, -4), (5, -1), (6, -1)];
+  ram.extend((261..300).map(|address| (address, -1)));
+  let emulator = emulate("08/FunctionCalls/NestedCall", &ram, 4000);
+  assert_ram(
+    &emulator,
+    &[
+      (0, 261),
+      (1, 261),
+      (2, 256),
+      (3, 4000),
+      (4, 5000),
+      (5, 135),
+      (6, 246),
+    ],
+  );
+}
+
+#[test]
+fn fibonacci_element() {
+  let emulator = emulate("08/FunctionCalls/FibonacciElement", &[], 6000);
+  assert_ram(&emulator, &[(0, 262), (261, 3)]);
+}
+
+#[test]
+fn statics_test() {
+  let emulator = emulate("08/FunctionCalls/StaticsTest", &[], 2500);
+  assert_ram(&emulator, &[(0, 263), (261, -2), (262, 8)]);
+}
+
+#[test]
+fn draw_screen() {
+  let emulator = emulate("Screen/Draw", &[], 100_000);
+  let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs/Screen/Draw/Draw.png");
+  let rendered = Screen::new(&emulator.ram);
+  screen::compare(&rendered, &golden).unwrap_or_else(|report| panic!("{}", report));
+  assert_eq!(Screen::parse(&rendered.to_png()), Ok(rendered.clone()));
+  assert_eq!(Screen::parse(&rendered.to_pbm()), Ok(rendered));
+}
+
+#[test]
+fn keyboard_echo() {
+  let mut emulator = load("Keyboard/Echo", &[]);
+  let mut keyboard: KeyboardScript = "# cycle key\n\
+                                      1000 \"Hi\" 500\n\
+                                      5000 left\n\
+                                      6000 release\n\
+                                      7000 enter\n\
+                                      8000 release\n\
+                                      9000 F1\n\
+                                      9500 release"
+    .parse()
+    .unwrap();
+  let status = keyboard.run_until(&mut emulator, 20_000).unwrap();
+  assert_eq!(status, Status::Halted);
+  assert_ram(
+    &emulator,
+    &[
+      (3000, 72),
+      (3001, 105),
+      (3002, 130),
+      (3003, 128),
+      (3004, 141),
+    ],
+  );
+}
+
+// Runs a `--checked` build of the program, returning the trap it stopped in.
+fn trap(path: &str, cycles: u64) -> Option<u16> {
+  let mut emulator = build(path, &[], true);
+  emulator.run(cycles).unwrap();
+  checks::trap(&emulator).map(|(code, _)| code)
+}
+
+#[test]
+fn checked_programs_run_unchanged() {
+  let mut emulator = build("08/FunctionCalls/FibonacciElement", &[], true);
+  emulator.run(20_000).unwrap();
+  assert_ram(&emulator, &[(0, 262), (261, 3)]);
+  assert_eq!(checks::trap(&emulator), None);
+  let mut emulator = build("08/FunctionCalls/StaticsTest", &[], true);
+  emulator.run(10_000).unwrap();
+  assert_ram(&emulator, &[(0, 263), (261, -2), (262, 8)]);
+  assert_eq!(checks::trap(&emulator), None);
+}
+
+// Without Sys.init, a program ends after its last command and must not run
+// on into the failure paths of its checks.
+#[test]
+fn checked_programs_without_sys_init_halt() {
+  let mut emulator = build("07/StackArithmetic/SimpleAdd/SimpleAdd.vm", &[], true);
+  assert_eq!(emulator.run(200), Ok(Status::Halted));
+  assert_ram(&emulator, &[(0, 257), (256, 15)]);
+  assert_eq!(checks::trap(&emulator), None);
+  let mut emulator = build("07/MemoryAccess/StaticTest/StaticTest.vm", &[], true);
+  assert_eq!(emulator.run(1000), Ok(Status::Halted));
+  assert_ram(&emulator, &[(0, 257), (256, 1110)]);
+  assert_eq!(checks::trap(&emulator), None);
+}
+
+#[test]
+fn checked_stack_overflow() {
+  assert_eq!(
+    trap("Checked/Recursion", 100_000),
+    Some(checks::STACK_OVERFLOW)
+  );
+}
+
+// A function's own locals are checked before they are pushed.
+#[test]
+fn checked_locals_overflow() {
+  let mut emulator = build("Checked/BigLocals", &[], true);
+  emulator.run(100_000).unwrap();
+  assert_eq!(
+    checks::trap(&emulator).map(|(code, _)| code),
+    Some(checks::STACK_OVERFLOW)
+  );
+  assert_ram(&emulator, &[(2048, 0)]);
+}
+
+#[test]
+fn checked_segment_bounds() {
+  assert_eq!(
+    trap("Checked/BadThat", 10_000),
+    Some(checks::SEGMENT_BOUNDS)
+  );
+}
+
+#[test]
+fn checked_bad_frame() {
+  assert_eq!(trap("Checked/BadFrame", 10_000), Some(checks::BAD_FRAME));
+}
+
+// Runs the program with trap checking, returning the trap it stopped at.
+fn run_with_traps(path: &str, cycles: u64) -> Option<Trap> {
+  let mut emulator = load(path, &[]);
+  emulator.enable_traps(true);
+  let _ = emulator.run(cycles);
+  emulator.traps.unwrap().trap
+}
+
+#[test]
+fn traps_pass_correct_programs() {
+  assert_eq!(
+    run_with_traps("08/FunctionCalls/FibonacciElement", 6000),
+    None
+  );
+  assert_eq!(run_with_traps("08/FunctionCalls/StaticsTest", 2500), None);
+  assert_eq!(run_with_traps("Keyboard/Echo", 2500), None);
+}
+
+#[test]
+fn trap_uninitialized_read() {
+  assert_eq!(
+    run_with_traps("Traps/UninitializedRead", 1000),
+    Some(Trap::UninitializedRead(5000))
+  );
+}
+
+#[test]
+fn trap_pc_out_of_range() {
+  match run_with_traps("Traps/BadReturn", 1000) {
+    Some(Trap::PcOutOfRange { pc: 30000, .. }) => {}
+    trap => panic!("{:?}", trap),
+  }
+}
+
+#[test]
+fn trap_invalid_write() {
+  assert_eq!(
+    run_with_traps("Traps/BadWrite", 1000),
+    Some(Trap::InvalidWrite(24577))
+  );
+}
+
+#[test]
+fn trap_stack_pointer() {
+  assert_eq!(
+    run_with_traps("Checked/Recursion", 100_000),
+    Some(Trap::StackPointer(2049))
+  );
+}
+
+// Runs the program with loop detection, returning where it got stuck.
+fn run_detecting_loops(path: &str, cycles: u64) -> Option<Stuck> {
+  let mut emulator = load(path, &[]);
+  emulator.enable_loop_detection();
+  let _ = emulator.run(cycles);
+  emulator.loops.unwrap().stuck
+}
+
+#[test]
+fn halted_programs_are_not_stuck() {
+  assert_eq!(
+    run_detecting_loops("08/FunctionCalls/FibonacciElement", 10_000),
+    None
+  );
+  assert_eq!(
+    run_detecting_loops("08/FunctionCalls/StaticsTest", 10_000),
+    None
+  );
+  let mut emulator = load("08/FunctionCalls/NestedCall", &[]);
+  emulator.enable_loop_detection();
+  assert_eq!(emulator.run(10_000), Ok(Status::Halted));
+}
+
+#[test]
+fn stuck_loop() {
+  let stuck = run_detecting_loops("Loops/Spin", 1_000_000).unwrap();
+  assert_eq!(stuck.period, 20);
+  assert!(stuck.since < 1000, "{:?}", stuck);
+}
+
+#[test]
+fn waiting_for_keys_is_not_stuck() {
+  let mut emulator = load("Keyboard/Echo", &[]);
+  emulator.enable_loop_detection();
+  let mut keyboard: KeyboardScript = "50000 \"abcde\" 1000".parse().unwrap();
+  let status = keyboard.run_until(&mut emulator, 100_000).unwrap();
+  assert_eq!(status, Status::Halted);
+  assert_ram(&emulator, &[(3000, 97), (3004, 101)]);
+  assert!(run_detecting_loops("Keyboard/Echo", 1_000_000).is_some());
+}
+
+fn debugger(path: &str) -> Debugger {
+  let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+    .join("tests/programs")
+    .join(path);
+  Debugger::new(VmParser::open(&path).unwrap(), history::DEFAULT_DELTAS).unwrap()
+}
+
+#[test]
+fn watch_static() {
+  let mut debugger = debugger("08/FunctionCalls/StaticsTest");
+  debugger
+    .add_watch("static Class1 1".parse().unwrap())
+    .unwrap();
+  match debugger.cont().unwrap() {
+    Stop::Watch { old, new, pc, .. } => {
+      assert_eq!((old, new), (0, 8));
+      let mapping = debugger.source_map.lookup(pc as usize).unwrap();
+      assert_eq!(mapping.command, "pop static 1");
+      assert_eq!(mapping.file.as_deref(), Some("Class1.vm"));
+    }
+    stop => panic!("{:?}", stop),
+  }
+}
+
+#[test]
+fn watch_argument_in_every_frame() {
+  let mut debugger = debugger("08/FunctionCalls/FibonacciElement");
+  debugger
+    .add_watch("argument Main.fibonacci 0".parse().unwrap())
+    .unwrap();
+  // Each call of Main.fibonacci returns its result over its argument, which
+  // only changes it for the four calls with n >= 2.
+  let mut hits = 0;
+  while let Stop::Watch { pc, .. } = debugger.cont().unwrap() {
+    let mapping = debugger.source_map.lookup(pc as usize).unwrap();
+    assert_eq!(mapping.command, "return");
+    hits += 1;
+  }
+  assert_eq!(hits, 4);
+}
+
+#[test]
+fn conditional_breakpoint() {
+  let mut debugger = debugger("08/FunctionCalls/FibonacciElement");
+  let condition: Condition = "RAM[ARG] == 1 && SP - LCL >= 0".parse().unwrap();
+  debugger
+    .add_breakpoint("Main.vm:13".parse().unwrap(), Some(condition))
+    .unwrap();
+  assert!(matches!(debugger.cont().unwrap(), Stop::Breakpoint(0)));
+  assert_eq!(debugger.segment(0, "argument", Some(0)).unwrap()[0].1, 1);
+  assert!("RAM[ARG] ==".parse::<Condition>().is_err());
+  assert!("PC + (1".parse::<Condition>().is_err());
+}
+
+#[test]
+fn snapshot_resumes_run() {
+  let script = "1000 \"Hi\" 500\n5000 left\n6000 release\n7000 \"ab\" 400";
+  let mut emulator = load("Keyboard/Echo", &[]);
+  let mut keyboard: KeyboardScript = script.parse().unwrap();
+  keyboard.run_until(&mut emulator, 5500).unwrap();
+  let path = std::env::temp_dir().join(format!("echo-{}.snap", std::process::id()));
+  Snapshot::new(&emulator, Some(&keyboard))
+    .save(&path)
+    .unwrap();
+  keyboard.run_until(&mut emulator, 20_000).unwrap();
+
+  let snapshot = Snapshot::load(&path).unwrap();
+  std::fs::remove_file(&path).unwrap();
+  assert_eq!(snapshot.cycles, 5500);
+  assert_eq!(snapshot.keyboard, 5);
+  let mut restored = snapshot.emulator().unwrap();
+  let mut resumed: KeyboardScript = script.parse().unwrap();
+  assert!(snapshot.restore_keyboard(None).is_err());
+  snapshot.restore_keyboard(Some(&mut resumed)).unwrap();
+  assert_eq!(resumed.run_until(&mut restored, 20_000), Ok(Status::Halted));
+  assert_eq!(restored.ram, emulator.ram);
+  assert_eq!(
+    (restored.pc, restored.cycles),
+    (emulator.pc, emulator.cycles)
+  );
+  assert_ram(
+    &restored,
+    &[(3000, 72), (3001, 105), (3002, 130), (3003, 97)],
+  );
+  assert!(Snapshot::parse(b"HACKSNAP").is_err());
+}
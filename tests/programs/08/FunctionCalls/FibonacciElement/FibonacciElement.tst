@@ -0,0 +1,13 @@
+// Tests FibonacciElement.asm on the CPU emulator. The program is a
+// directory of two files and starts at the bootstrap code.
+
+load FibonacciElement.asm,
+output-file FibonacciElement.out,
+compare-to FibonacciElement.cmp,
+output-list RAM[0]%D1.6.2 RAM[261]%D1.6.2;
+
+repeat 6000 {
+  ticktock;
+}
+
+output;
@@ -0,0 +1,98 @@
+use vm::listing::listing;
+use vm::size_report::size_report;
+use vm::source_map::SourceMap;
+use vm::VmParser;
+
+const SYS: &str = "// Adds two numbers.\n\
+                   function Sys.init 0\n\
+                   push constant 2\n\
+                   push constant 3\n\
+                   add\n\
+                   \n\
+                   label END\n\
+                   goto END\n";
+
+fn translate() -> (String, SourceMap) {
+  let mut asm = Vec::new();
+  let source_map = vm::translate(vec![VmParser::from_source("Sys.vm", SYS)], &mut asm).unwrap();
+  (String::from_utf8(asm).unwrap(), source_map)
+}
+
+#[test]
+fn listing_shows_each_line_with_its_instructions() {
+  let (asm, source_map) = translate();
+  let listing = listing(
+    &source_map,
+    &asm,
+    &[("Sys.vm".to_string(), SYS.to_string())],
+  );
+  assert!(
+    listing.starts_with("          52 | bootstrap\n             |     0  @256 // SP = 256\n"),
+    "{}",
+    listing
+  );
+  assert!(listing.contains(
+    "\nSys.vm\n    1        | // Adds two numbers.\n    2     12 | function Sys.init 0\n             |        (Sys.init)\n             |    52  @0"
+  ));
+  assert!(listing.contains("    3      7 | push constant 2\n             |    64  @2"));
+  assert!(listing.ends_with(
+    "    6        |\n\
+     \x20   7      0 | label END\n\
+     \x20            |        (END)\n\
+     \x20   8      2 | goto END\n\
+     \x20            |    92  @END\n\
+     \x20            |    93  0;JMP\n\
+     \n\
+     94 instructions\n"
+  ));
+  // Every instruction is listed once, at its address.
+  let addresses: Vec<usize> = listing
+    .lines()
+    .filter(|line| line.starts_with("             | ") && !line.ends_with(')'))
+    .map(|line| line[15..20].trim().parse().unwrap())
+    .collect();
+  assert_eq!(addresses, (0..94).collect::<Vec<usize>>());
+}
+
+#[test]
+fn size_report_totals_by_function_file_and_command() {
+  let (_, source_map) = translate();
+  let row = |name: &str, size: usize, percent: &str, commands: usize| {
+    format!("{:<48}{:>10}{:>8}{:>10}\n", name, size, percent, commands)
+  };
+  let header = |title: &str| {
+    format!(
+      "\n{:<48}{:>10}{:>8}{:>10}\n",
+      title, "size", "%", "commands"
+    )
+  };
+  let expected = String::from("Total: 94 instructions, 0.3% of the 32768 word Hack ROM\n")
+    + &header("Function")
+    + &row("(bootstrap)", 52, "55.3%", 1)
+    + &row("Sys.init", 42, "44.7%", 6)
+    + &header("File")
+    + &row("(bootstrap)", 52, "55.3%", 1)
+    + &row("Sys.vm", 42, "44.7%", 6)
+    + &header("Command")
+    + &row("bootstrap", 52, "55.3%", 1)
+    + &row("arithmetic", 14, "14.9%", 1)
+    + &row("push constant", 14, "14.9%", 2)
+    + &row("function", 12, "12.8%", 1)
+    + &row("branching", 2, "2.1%", 2);
+  assert_eq!(size_report(&source_map), expected);
+}
+
+#[test]
+fn size_report_counts_runtime_checks_apart() {
+  let mut asm = Vec::new();
+  let source_map =
+    vm::translate_checked(vec![VmParser::from_source("Sys.vm", SYS)], &mut asm).unwrap();
+  let report = size_report(&source_map);
+  let checks = source_map.mappings.last().unwrap().count;
+  assert!(
+    report.contains(&format!("\n{:<48}{:>10}", "(runtime checks)", checks)),
+    "{}",
+    report
+  );
+  assert!(report.contains(&format!("\n{:<48}{:>10}", "checks", checks)));
+}
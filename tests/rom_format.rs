@@ -0,0 +1,129 @@
+use vm::hack;
+use vm::rom_format::RomFormat;
+
+const FORMATS: [&str; 7] = [
+  "hack", "bin-le", "bin-be", "ihex", "readmemb", "readmemh", "logisim",
+];
+
+// A ROM with a run long enough for Logisim to compress, words with every
+// nibble in use, and a length that isn't a multiple of the 8 word records.
+fn rom() -> Vec<u16> {
+  let mut rom = vec![0x0000, 0xffff, 0x1234, 0xabcd, 0x8000, 0x7fff];
+  rom.extend(std::iter::repeat_n(0xec10, 6));
+  rom.extend((0..11).map(|i| i * 0x1111));
+  rom
+}
+
+fn decode(format: RomFormat, bytes: &[u8]) -> Vec<u16> {
+  let text = || String::from_utf8(bytes.to_vec()).unwrap();
+  match format {
+    RomFormat::Hack | RomFormat::ReadMemB => hack::parse_hack(&text()).unwrap(),
+    RomFormat::ReadMemH => text()
+      .lines()
+      .map(|line| u16::from_str_radix(line, 16).unwrap())
+      .collect(),
+    RomFormat::BinLe => bytes
+      .chunks(2)
+      .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+      .collect(),
+    RomFormat::BinBe => bytes
+      .chunks(2)
+      .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+      .collect(),
+    RomFormat::IntelHex => intel_hex(&text()),
+    RomFormat::Logisim => logisim(&text()),
+  }
+}
+
+fn intel_hex(text: &str) -> Vec<u16> {
+  let mut rom = Vec::new();
+  let mut ended = false;
+  for line in text.lines() {
+    assert!(!ended, "record after the end of file record");
+    let record: Vec<u8> = (1..line.len())
+      .step_by(2)
+      .map(|i| u8::from_str_radix(&line[i..i + 2], 16).unwrap())
+      .collect();
+    assert!(line.starts_with(':'));
+    assert_eq!(
+      record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)),
+      0,
+      "checksum of {}",
+      line
+    );
+    let (count, address, kind) = (
+      record[0] as usize,
+      record[1] as usize * 256 + record[2] as usize,
+      record[3],
+    );
+    assert_eq!(record.len(), count + 5);
+    match kind {
+      0 => {
+        assert_eq!(address, rom.len() * 2);
+        rom.extend(
+          record[4..4 + count]
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]])),
+        );
+      }
+      1 => ended = true,
+      _ => panic!("unexpected record type {}", kind),
+    }
+  }
+  assert!(ended);
+  rom
+}
+
+fn logisim(text: &str) -> Vec<u16> {
+  let mut lines = text.lines();
+  assert_eq!(lines.next(), Some("v2.0 raw"));
+  let mut rom = Vec::new();
+  for entry in lines.flat_map(str::split_whitespace) {
+    match entry.split_once('*') {
+      Some((count, word)) => rom.extend(std::iter::repeat_n(
+        u16::from_str_radix(word, 16).unwrap(),
+        count.parse().unwrap(),
+      )),
+      None => rom.push(u16::from_str_radix(entry, 16).unwrap()),
+    }
+  }
+  rom
+}
+
+#[test]
+fn formats_round_trip() {
+  let rom = rom();
+  for name in FORMATS {
+    let format: RomFormat = name.parse().unwrap();
+    assert_eq!(decode(format, &format.encode(&rom)), rom, "{}", name);
+  }
+  assert!("intel".parse::<RomFormat>().is_err());
+}
+
+#[test]
+fn encodings() {
+  let rom = [0x0010, 0xec10];
+  assert_eq!(
+    RomFormat::Hack.encode(&rom),
+    b"0000000000010000\n1110110000010000\n"
+  );
+  assert_eq!(RomFormat::ReadMemH.encode(&rom), b"0010\nec10\n");
+  assert_eq!(RomFormat::BinLe.encode(&rom), [0x10, 0x00, 0x10, 0xec]);
+  assert_eq!(RomFormat::BinBe.encode(&rom), [0x00, 0x10, 0xec, 0x10]);
+  assert_eq!(
+    RomFormat::IntelHex.encode(&rom),
+    b":040000000010EC10F0\n:00000001FF\n"
+  );
+  assert_eq!(
+    RomFormat::Logisim.encode(&[7, 7, 7, 7, 7, 1]),
+    b"v2.0 raw\n5*7 1\n"
+  );
+  let extensions: Vec<&str> = FORMATS
+    .iter()
+    .map(|name| name.parse::<RomFormat>().unwrap().extension())
+    .collect();
+  assert_eq!(
+    extensions,
+    ["hack", "le.bin", "be.bin", "hex", "memb", "memh", "rom"]
+  );
+}
@@ -0,0 +1,149 @@
+use vm::assembler;
+use vm::json::Json;
+use vm::source_map::SourceMap;
+use vm::{Bootstrap, VmParser};
+
+const SYS: &str = "function Sys.init 0\n\
+                   push constant 7\n\
+                   call Main.double 1\n\
+                   label END\n\
+                   goto END\n";
+
+const MAIN: &str = "// Doubles its argument.\n\
+                    function Main.double 0\n\
+                    push argument 0\n\
+                    push argument 0\n\
+                    add\n\
+                    return\n";
+
+fn translate() -> (String, SourceMap) {
+  let mut asm = Vec::new();
+  let source_map = vm::translate(
+    vec![
+      VmParser::from_source("Sys.vm", SYS),
+      VmParser::from_source("Main.vm", MAIN),
+    ],
+    &mut asm,
+  )
+  .unwrap();
+  (String::from_utf8(asm).unwrap(), source_map)
+}
+
+#[test]
+fn mappings_cover_the_program() {
+  let (asm, source_map) = translate();
+  let lines: Vec<&str> = asm.lines().collect();
+  let mut address = 0;
+  let mut asm_line = 1;
+  for mapping in &source_map.mappings {
+    assert_eq!(mapping.address, address, "{}", mapping.command);
+    assert_eq!(mapping.asm_line, asm_line, "{}", mapping.command);
+    let text = lines[asm_line - 1..asm_line - 1 + mapping.asm_lines].join("\n");
+    assert_eq!(assembler::instruction_count(&text), mapping.count);
+    address += mapping.count;
+    asm_line += mapping.asm_lines;
+  }
+  assert_eq!(asm_line - 1, lines.len());
+  assert_eq!(
+    source_map.instruction_count(),
+    assembler::assemble(&asm).unwrap().instructions.len()
+  );
+
+  let commands: Vec<(Option<&str>, usize, &str)> = source_map
+    .mappings
+    .iter()
+    .map(|mapping| {
+      (
+        mapping.file.as_deref(),
+        mapping.line,
+        mapping.command.as_str(),
+      )
+    })
+    .collect();
+  assert_eq!(
+    commands,
+    [
+      (None, 0, "bootstrap"),
+      (Some("Sys.vm"), 1, "function Sys.init 0"),
+      (Some("Sys.vm"), 2, "push constant 7"),
+      (Some("Sys.vm"), 3, "call Main.double 1"),
+      (Some("Sys.vm"), 4, "label END"),
+      (Some("Sys.vm"), 5, "goto END"),
+      (Some("Main.vm"), 2, "function Main.double 0"),
+      (Some("Main.vm"), 3, "push argument 0"),
+      (Some("Main.vm"), 4, "push argument 0"),
+      (Some("Main.vm"), 5, "add"),
+      (Some("Main.vm"), 6, "return"),
+    ]
+  );
+  assert_eq!(
+    source_map.mappings[8].function.as_deref(),
+    Some("Main.double")
+  );
+  assert_eq!(source_map.mappings[4].kind, "branching");
+  assert_eq!(source_map.mappings[4].count, 0);
+}
+
+#[test]
+fn lookup() {
+  let (_, source_map) = translate();
+  for address in 0..source_map.instruction_count() {
+    let mapping = source_map.lookup(address).unwrap();
+    assert!(mapping.address <= address && address < mapping.address + mapping.count);
+  }
+  // The label has no instructions of its own: its address is the goto's.
+  let goto = &source_map.mappings[5];
+  assert_eq!(source_map.mappings[4].address, goto.address);
+  assert_eq!(source_map.lookup(goto.address).unwrap().command, "goto END");
+  assert_eq!(
+    source_map.lookup(0).unwrap().command,
+    source_map.mappings[0].command
+  );
+  assert!(source_map.lookup(source_map.instruction_count()).is_none());
+  assert!(SourceMap::default().lookup(0).is_none());
+}
+
+#[test]
+fn to_json() {
+  let (_, source_map) = translate();
+  let json: Json = source_map.to_json().to_string().parse().unwrap();
+  assert_eq!(json.get("version").and_then(Json::as_i64), Some(1));
+  let mappings = json.get("mappings").and_then(Json::as_array).unwrap();
+  assert_eq!(mappings.len(), source_map.mappings.len());
+  assert_eq!(mappings[0].get("file"), Some(&Json::Null));
+  assert_eq!(mappings[0].get("line"), Some(&Json::Null));
+  let add = &source_map.mappings[9];
+  assert_eq!(
+    mappings[9],
+    Json::object(vec![
+      ("address", add.address.into()),
+      ("count", add.count.into()),
+      ("asmLine", add.asm_line.into()),
+      ("asmLines", add.asm_lines.into()),
+      ("file", "Main.vm".into()),
+      ("line", Json::Number(5)),
+      ("function", "Main.double".into()),
+      ("command", "add".into()),
+      ("kind", add.kind.as_str().into()),
+    ])
+  );
+}
+
+#[test]
+fn bootstrap_without_sys_init() {
+  let translate = |bootstrap| {
+    let mut asm = Vec::new();
+    vm::translate_with(
+      vec![VmParser::from_source("Main.vm", MAIN)],
+      &mut asm,
+      bootstrap,
+    )
+    .unwrap()
+  };
+  let always = translate(Bootstrap::Always);
+  assert_eq!(always.mappings[0].command, "bootstrap");
+  assert_eq!(always.mappings[1].file.as_deref(), Some("Main.vm"));
+  let with_sys_init = translate(Bootstrap::WithSysInit);
+  assert_eq!(with_sys_init.mappings[0].command, "function Main.double 0");
+  assert_eq!(with_sys_init.mappings[0].address, 0);
+}
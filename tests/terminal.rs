@@ -0,0 +1,83 @@
+use vm::emulator::{RAM_SIZE, SCREEN};
+use vm::screen::{Screen, HEIGHT, WIDTH};
+use vm::terminal::{blocks, braille, decode_keys, Style};
+
+// A screen with the given pixels set.
+fn screen(pixels: &[(usize, usize)]) -> Screen {
+  let mut ram = vec![0; RAM_SIZE];
+  for (x, y) in pixels {
+    ram[SCREEN + y * WIDTH / 16 + x / 16] |= 1 << (x % 16);
+  }
+  Screen::new(&ram)
+}
+
+// The character at column `x` of line `y`.
+fn cell(text: &str, x: usize, y: usize) -> char {
+  text.split("\r\n").nth(y).unwrap().chars().nth(x).unwrap()
+}
+
+#[test]
+fn braille_cells() {
+  let text = braille(&screen(&[(0, 0), (1, 3), (17, 5), (511, 255)]));
+  assert_eq!(text.matches("\r\n").count(), HEIGHT / 4);
+  assert!(text
+    .split("\r\n")
+    .take(HEIGHT / 4)
+    .all(|line| line.chars().count() == WIDTH / 2));
+  // Dots 1 and 8: the upper left and lower right pixels.
+  assert_eq!(cell(&text, 0, 0), '⢁');
+  // Dot 5: the right pixel in the second row of the cell.
+  assert_eq!(cell(&text, 8, 1), '⠐');
+  assert_eq!(cell(&text, 255, 63), '⢀');
+  assert_eq!(cell(&text, 1, 0), '⠀');
+  assert_eq!(text.chars().filter(|c| !"⠀\r\n".contains(*c)).count(), 3);
+}
+
+#[test]
+fn block_cells() {
+  let text = blocks(&screen(&[
+    (0, 0),
+    (2, 1),
+    (3, 1),
+    (4, 0),
+    (5, 1),
+    (511, 255),
+  ]));
+  assert_eq!(text.matches("\r\n").count(), HEIGHT / 2);
+  assert!(text.starts_with(&format!("▘▄▚{}\r\n", " ".repeat(WIDTH / 2 - 3))));
+  assert_eq!(cell(&text, 255, 127), '▗');
+}
+
+#[test]
+fn keys() {
+  assert_eq!(decode_keys(b"a"), [97]);
+  assert_eq!(decode_keys(b"Hi!"), [72, 105, 33]);
+  assert_eq!(decode_keys(b"\r"), [128]);
+  assert_eq!(decode_keys(b"\n"), [128]);
+  assert_eq!(decode_keys(&[0x7f]), [129]);
+  assert_eq!(decode_keys(&[0x08]), [129]);
+  assert_eq!(decode_keys(&[0x1b]), [140]);
+  assert_eq!(decode_keys(b"\x1b[D"), [130]);
+  assert_eq!(decode_keys(b"\x1bOA"), [131]);
+  assert_eq!(decode_keys(b"\x1b[C"), [132]);
+  assert_eq!(decode_keys(b"\x1b[B"), [133]);
+  assert_eq!(decode_keys(b"\x1b[1~"), [134]);
+  assert_eq!(decode_keys(b"\x1b[F"), [135]);
+  assert_eq!(decode_keys(b"\x1b[5~"), [136]);
+  assert_eq!(decode_keys(b"\x1b[3~"), [139]);
+  assert_eq!(decode_keys(b"\x1bOP"), [141]);
+  assert_eq!(decode_keys(b"\x1b[24~"), [152]);
+  // Unknown escape sequences and control characters are dropped.
+  assert!(decode_keys(b"\x1b[99~").is_empty());
+  assert_eq!(decode_keys(b"\tx\x01"), [120]);
+}
+
+#[test]
+fn styles() {
+  assert_eq!("braille".parse(), Ok(Style::Braille));
+  assert_eq!("blocks".parse(), Ok(Style::Blocks));
+  assert_eq!(
+    "ascii".parse::<Style>(),
+    Err("Unknown style ascii, expected braille or blocks".to_string())
+  );
+}
@@ -0,0 +1,71 @@
+use std::path::Path;
+
+use vm::test_script;
+
+fn run(script: &str) {
+  let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+    .join("tests/programs")
+    .join(script);
+  let result = test_script::run_script(&path).unwrap_or_else(|err| panic!("{}", err));
+  if let Some(mismatch) = result.mismatch {
+    panic!(
+      "{}: comparison failure at line {}\nexpected: {}\nactual:   {}",
+      script, mismatch.line, mismatch.expected, mismatch.actual
+    );
+  }
+}
+
+#[test]
+fn simple_add() {
+  run("07/StackArithmetic/SimpleAdd/SimpleAdd.tst");
+}
+
+#[test]
+fn stack_test() {
+  run("07/StackArithmetic/StackTest/StackTest.tst");
+}
+
+#[test]
+fn basic_test() {
+  run("07/MemoryAccess/BasicTest/BasicTest.tst");
+}
+
+#[test]
+fn pointer_test() {
+  run("07/MemoryAccess/PointerTest/PointerTest.tst");
+}
+
+#[test]
+fn static_test() {
+  run("07/MemoryAccess/StaticTest/StaticTest.tst");
+}
+
+#[test]
+fn basic_loop() {
+  run("08/ProgramFlow/BasicLoop/BasicLoop.tst");
+}
+
+#[test]
+fn fibonacci_series() {
+  run("08/ProgramFlow/FibonacciSeries/FibonacciSeries.tst");
+}
+
+#[test]
+fn simple_function() {
+  run("08/FunctionCalls/SimpleFunction/SimpleFunction.tst");
+}
+
+#[test]
+fn nested_call() {
+  run("08/FunctionCalls/NestedCall/NestedCall.tst");
+}
+
+#[test]
+fn fibonacci_element() {
+  run("08/FunctionCalls/FibonacciElement/FibonacciElement.tst");
+}
+
+#[test]
+fn statics_test() {
+  run("08/FunctionCalls/StaticsTest/StaticsTest.tst");
+}
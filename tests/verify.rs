@@ -0,0 +1,93 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use vm::verify::{self, Report};
+use vm::{Build, VmParser};
+
+// All of projects 7 and 8 but SimpleFunction, whose test script has it return
+// to a caller that isn't there.
+const PROGRAMS: [&str; 10] = [
+  "07/MemoryAccess/BasicTest",
+  "07/MemoryAccess/PointerTest",
+  "07/MemoryAccess/StaticTest",
+  "07/StackArithmetic/SimpleAdd",
+  "07/StackArithmetic/StackTest",
+  "08/FunctionCalls/FibonacciElement",
+  "08/FunctionCalls/NestedCall",
+  "08/FunctionCalls/StaticsTest",
+  "08/ProgramFlow/BasicLoop",
+  "08/ProgramFlow/FibonacciSeries",
+];
+
+fn path(program: &str) -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR"))
+    .join("tests/programs")
+    .join(program)
+}
+
+// The `set RAM[address] value` commands of the program's test script.
+fn test_script_ram(program: &str) -> Vec<(usize, u16)> {
+  let path = path(program);
+  let name = path.file_name().unwrap().to_str().unwrap();
+  let script = fs::read_to_string(path.join(name).with_extension("tst")).unwrap_or_default();
+  script
+    .lines()
+    .filter_map(|line| line.trim().strip_prefix("set RAM["))
+    .map(|set| {
+      let (address, rest) = set.split_once(']').unwrap();
+      let value = rest
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .find(|word| !word.is_empty())
+        .unwrap();
+      (
+        address.parse().unwrap(),
+        value.parse::<i16>().unwrap() as u16,
+      )
+    })
+    .collect()
+}
+
+fn verify(program: &str, asm: impl FnOnce(String) -> String) -> Report {
+  let parsers = VmParser::open(&path(program)).unwrap();
+  let mut translation = Vec::new();
+  let source_map = vm::translate(parsers.clone(), &mut translation).unwrap();
+  let build = Build::new(&asm(String::from_utf8(translation).unwrap()), source_map)
+    .unwrap_or_else(|err| panic!("{}: {}", program, err));
+  verify::verify_build(parsers, build, &test_script_ram(program), 100_000)
+    .unwrap_or_else(|err| panic!("{}: {}", program, err))
+}
+
+#[test]
+fn interpreter_and_emulator_agree() {
+  for program in PROGRAMS {
+    let report = verify(program, |asm| asm);
+    assert!(
+      report.divergence.is_none(),
+      "{}: {:?}",
+      program,
+      report.divergence
+    );
+    assert!(report.halted, "{}", program);
+  }
+  assert_eq!(
+    test_script_ram("08/ProgramFlow/BasicLoop"),
+    [(0, 256), (1, 300), (2, 400), (400, 3)]
+  );
+  let report = verify::verify(VmParser::open(&path(PROGRAMS[5])).unwrap(), 100_000).unwrap();
+  assert!(report.halted && report.divergence.is_none());
+}
+
+#[test]
+fn changed_translation_diverges() {
+  // `add` computing y - x instead.
+  let report = verify("07/StackArithmetic/SimpleAdd", |asm| {
+    asm.replacen("D=D+A", "D=D-A", 1)
+  });
+  let divergence = report.divergence.unwrap();
+  assert_eq!(divergence.location, "SimpleAdd.vm:4: add");
+  assert_eq!(
+    divergence.differences,
+    ["stack RAM[256]: interpreter 15, emulator 1"]
+  );
+  assert_eq!(report.steps, 3);
+}